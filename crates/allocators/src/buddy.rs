@@ -0,0 +1,175 @@
+use crate::{AllocatorStats, GrowHeap, HeapAllocator, grow_size};
+use alloc::alloc::Layout;
+use core::ptr;
+
+// Blocks are powers of two from 16 bytes up, aligned to their size
+const MIN_ORDER: usize = 4;
+const ORDERS: usize = 48;
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+// Binary buddy heap; finding a free buddy walks its free list, which stays short in practice
+pub struct BuddyAllocator {
+    free_lists: [*mut FreeBlock; ORDERS],
+    start: usize,
+    top: usize,
+    max_size: usize,
+    grow: Option<GrowHeap>,
+    used: usize,
+}
+
+unsafe impl Send for BuddyAllocator {}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn order(layout: &Layout) -> usize {
+    let size = layout.size().max(layout.align()).max(1 << MIN_ORDER);
+    size.next_power_of_two().trailing_zeros() as usize
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        BuddyAllocator {
+            free_lists: [ptr::null_mut(); ORDERS],
+            start: 0,
+            top: 0,
+            max_size: 0,
+            grow: None,
+            used: 0,
+        }
+    }
+
+    // Splits the range into the largest aligned blocks, which then merge with their buddies
+    fn add_range(&mut self, start: usize, end: usize) {
+        let mut start = start.next_multiple_of(1 << MIN_ORDER);
+        let end = end & !((1 << MIN_ORDER) - 1);
+        while start < end {
+            let mut order = (start.trailing_zeros() as usize).min(ORDERS - 1);
+            while start + (1 << order) > end {
+                order -= 1;
+            }
+            self.free_block(start, order);
+            start += 1 << order;
+        }
+    }
+
+    fn free_block(&mut self, block: usize, order: usize) {
+        let (mut block, mut order) = (block, order);
+        while order < ORDERS - 1 {
+            let buddy = block ^ (1 << order);
+            if buddy < self.start || buddy + (1 << order) > self.top || !self.remove(buddy, order) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.push(block, order);
+    }
+
+    // Takes a block of the order, splitting a larger one if needed
+    fn take(&mut self, order: usize) -> Option<usize> {
+        let found = (order..ORDERS).find(|&order| !self.free_lists[order].is_null())?;
+        let block = self.free_lists[found];
+        self.free_lists[found] = unsafe { (*block).next };
+        for order in (order..found).rev() {
+            self.push(block as usize + (1 << order), order);
+        }
+        Some(block as usize)
+    }
+
+    fn push(&mut self, block: usize, order: usize) {
+        let block = block as *mut FreeBlock;
+        unsafe {
+            block.write(FreeBlock {
+                next: self.free_lists[order],
+            })
+        };
+        self.free_lists[order] = block;
+    }
+
+    fn remove(&mut self, block: usize, order: usize) -> bool {
+        let mut link = &mut self.free_lists[order];
+        while !link.is_null() {
+            if *link as usize == block {
+                *link = unsafe { (**link).next };
+                return true;
+            }
+            link = unsafe { &mut (**link).next };
+        }
+        false
+    }
+
+    // Grows by twice the block size, so an aligned block fits whatever the top looks like
+    fn extend(&mut self, order: usize) -> bool {
+        let Some(grow) = self.grow else {
+            return false;
+        };
+        let available = self.max_size - (self.top - self.start);
+        let Some(size) = grow_size(2 << order, available) else {
+            return false;
+        };
+        if !grow(self.top, size) {
+            return false;
+        }
+        let old_top = self.top;
+        self.top += size;
+        self.add_range(old_top, self.top);
+        true
+    }
+}
+
+impl HeapAllocator for BuddyAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.start = heap_start;
+        self.top = heap_start + heap_size;
+        self.max_size = heap_size;
+        self.add_range(heap_start, heap_start + heap_size);
+    }
+
+    fn set_growth(&mut self, max_size: usize, grow: GrowHeap) {
+        self.max_size = max_size.max(self.top - self.start);
+        self.grow = Some(grow);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let order = order(&layout);
+        let mut block = None;
+        if order < ORDERS {
+            block = self.take(order);
+            if block.is_none() && self.extend(order) {
+                block = self.take(order);
+            }
+        }
+        let Some(block) = block else {
+            return ptr::null_mut();
+        };
+        self.used += 1 << order;
+        block as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let order = order(&layout);
+        self.used -= 1 << order;
+        self.free_block(ptr as usize, order);
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        let largest = (0..ORDERS)
+            .rev()
+            .find(|&order| !self.free_lists[order].is_null());
+        AllocatorStats {
+            strategy: "buddy",
+            heap_size: self.top - self.start,
+            heap_used: self.used,
+            heap_max_size: self.max_size,
+            largest_free: Some(largest.map_or(0, |order| 1 << order)),
+            ..AllocatorStats::default()
+        }
+    }
+}
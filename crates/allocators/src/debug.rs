@@ -0,0 +1,132 @@
+use crate::HeapAllocator;
+use alloc::alloc::Layout;
+use core::ptr;
+use serial::serial_println;
+
+// Every block is laid out as header, front guard, the caller's bytes and a back guard.
+// The first bytes of the header are left to the free lists, which link blocks through them
+const LINKS: usize = 16;
+const HEADER: usize = LINKS + 16;
+const GUARD: usize = 16;
+
+const GUARD_BYTE: u8 = 0xFD;
+const UNINIT_BYTE: u8 = 0xCD;
+const POISON_BYTE: u8 = 0x6B;
+
+const ALLOCATED: u32 = 0xA110_CA7E;
+// Freed blocks that the heap only reuses whole get their poison checked on reuse
+const FREED: u32 = 0xF4EE_D00D;
+// Freed into a heap that may merge or split the block later
+const RELEASED: u32 = 0xDEAD_BEEF;
+
+#[repr(C)]
+struct Header {
+    links: [u8; LINKS],
+    tag: u32,
+    align: u32,
+    size: u64,
+}
+
+fn front(align: usize) -> usize {
+    (HEADER + GUARD).next_multiple_of(align)
+}
+
+fn expand(layout: Layout) -> Option<Layout> {
+    let size = front(layout.align()) + layout.size() + GUARD;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+fn report(problem: &str, ptr: *const u8, layout: Layout) {
+    serial_println!(
+        "heap-debug: {} at {:#x} ({} bytes, align {})",
+        problem,
+        ptr as usize,
+        layout.size(),
+        layout.align()
+    );
+}
+
+// Returns the offset of the first byte that doesn't match
+unsafe fn check(start: *const u8, len: usize, byte: u8) -> Option<usize> {
+    let bytes = unsafe { core::slice::from_raw_parts(start, len) };
+    bytes.iter().position(|&value| value != byte)
+}
+
+pub(crate) fn allocate(allocator: &mut impl HeapAllocator, layout: Layout) -> *mut u8 {
+    let Some(expanded) = expand(layout) else {
+        return ptr::null_mut();
+    };
+    let base = allocator.allocate(expanded);
+    if base.is_null() {
+        return base;
+    }
+
+    unsafe {
+        let header = base as *mut Header;
+        let previous = header.read_unaligned();
+        if allocator.reuses_blocks(&expanded) && previous.tag == FREED {
+            let align = previous.align as usize;
+            let ptr = base.add(front(align));
+            let size = previous.size as usize;
+            if let Some(offset) = check(ptr, size, POISON_BYTE) {
+                let layout = Layout::from_size_align_unchecked(size, align);
+                report("write after free", ptr.add(offset), layout);
+            }
+        }
+
+        header.write_unaligned(Header {
+            links: [0; LINKS],
+            tag: ALLOCATED,
+            align: layout.align() as u32,
+            size: layout.size() as u64,
+        });
+        let ptr = base.add(front(layout.align()));
+        ptr::write_bytes(
+            base.add(HEADER),
+            GUARD_BYTE,
+            ptr as usize - base as usize - HEADER,
+        );
+        ptr::write_bytes(ptr, UNINIT_BYTE, layout.size());
+        ptr::write_bytes(ptr.add(layout.size()), GUARD_BYTE, GUARD);
+        ptr
+    }
+}
+
+pub(crate) unsafe fn deallocate(allocator: &mut impl HeapAllocator, ptr: *mut u8, layout: Layout) {
+    unsafe {
+        let base = ptr.sub(front(layout.align()));
+        let header = base as *mut Header;
+        let Header {
+            tag, align, size, ..
+        } = header.read_unaligned();
+        match tag {
+            ALLOCATED => {}
+            FREED | RELEASED => return report("double free", ptr, layout),
+            _ => return report("free of an unknown or corrupted block", ptr, layout),
+        }
+
+        // The block is freed the way it was allocated, so the free lists stay intact
+        let allocated = Layout::from_size_align_unchecked(size as usize, align as usize);
+        if allocated != layout {
+            report("free with the wrong layout", ptr, layout);
+            report("  allocated", ptr, allocated);
+        }
+        let front_guard = base.add(HEADER);
+        if let Some(offset) = check(front_guard, ptr as usize - front_guard as usize, GUARD_BYTE) {
+            report("underflow", front_guard.add(offset), allocated);
+        }
+        let back_guard = ptr.add(allocated.size());
+        if let Some(offset) = check(back_guard, GUARD, GUARD_BYTE) {
+            report("overflow", back_guard.add(offset), allocated);
+        }
+
+        ptr::write_bytes(ptr, POISON_BYTE, allocated.size());
+        let expanded = expand(allocated).unwrap();
+        let tag = match allocator.reuses_blocks(&expanded) {
+            true => FREED,
+            false => RELEASED,
+        };
+        ptr::addr_of_mut!((*header).tag).write_unaligned(tag);
+        allocator.deallocate(base, expanded);
+    }
+}
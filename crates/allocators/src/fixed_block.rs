@@ -1,23 +1,35 @@
-use alloc::alloc::{GlobalAlloc, Layout};
+use crate::{AllocatorStats, GrowHeap, HeapAllocator, Locked, grow_size};
+use alloc::alloc::Layout;
 use core::{mem, ptr};
-use custom_types::spin_lock::SpinLock;
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub(crate) const CLASSES: usize = BLOCK_SIZES.len();
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KB
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
 
-fn list_index(layout: &Layout) -> Option<usize> {
+pub(crate) fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClassStats {
+    pub allocations: usize,
+    pub frees: usize,
+    pub free_blocks: usize,
+}
+
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
 
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    classes: [ClassStats; CLASSES],
     fallback_allocator: linked_list_allocator::Heap,
+    max_size: usize,
+    grow: Option<GrowHeap>,
 }
 
 impl Default for FixedSizeBlockAllocator {
@@ -31,63 +43,120 @@ impl FixedSizeBlockAllocator {
         const EMPTY: Option<&'static mut ListNode> = None;
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
+            classes: [ClassStats {
+                allocations: 0,
+                frees: 0,
+                free_blocks: 0,
+            }; CLASSES],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            max_size: 0,
+            grow: None,
         }
     }
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.fallback_allocator.init(heap_start, heap_size);
-    }
-
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        if !self.extend(layout) {
+            return ptr::null_mut();
+        }
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
         }
     }
+
+    // Grows the heap at its top by enough for the layout, even if nothing at the top is free
+    fn extend(&mut self, layout: Layout) -> bool {
+        let Some(grow) = self.grow else {
+            return false;
+        };
+        let available = self.max_size - self.fallback_allocator.size();
+        let Some(size) = grow_size(layout.size() + layout.align(), available) else {
+            return false;
+        };
+        if !grow(self.fallback_allocator.top(), size) {
+            return false;
+        }
+        unsafe { self.fallback_allocator.extend(size) };
+        true
+    }
 }
 
-pub struct LockFixedSizeBlockAllocator(pub SpinLock<FixedSizeBlockAllocator>);
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.fallback_allocator.init(heap_start, heap_size) };
+        self.max_size = heap_size;
+    }
 
-unsafe impl GlobalAlloc for LockFixedSizeBlockAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.0.lock();
-        match list_index(&layout) {
-            Some(index) => match allocator.list_heads[index].take() {
+    fn set_growth(&mut self, max_size: usize, grow: GrowHeap) {
+        self.max_size = max_size.max(self.fallback_allocator.size());
+        self.grow = Some(grow);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let class = list_index(&layout);
+        let ptr = match class {
+            Some(index) => match self.list_heads[index].take() {
                 Some(node) => {
-                    allocator.list_heads[index] = node.next.take();
+                    self.list_heads[index] = node.next.take();
+                    self.classes[index].free_blocks -= 1;
                     node as *mut ListNode as *mut u8
                 }
                 None => {
                     let block_size = BLOCK_SIZES[index];
                     let block_align = block_size;
                     let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                    allocator.fallback_alloc(layout)
+                    self.fallback_alloc(layout)
                 }
             },
-            None => allocator.fallback_alloc(layout),
+            None => self.fallback_alloc(layout),
+        };
+        if let (Some(index), false) = (class, ptr.is_null()) {
+            self.classes[index].allocations += 1;
         }
+        ptr
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        unsafe {
-            let mut allocator = self.0.lock();
-            match list_index(&layout) {
-                Some(index) => {
-                    let new_node = ListNode {
-                        next: allocator.list_heads[index].take(),
-                    };
-                    assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                    assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                    let new_node_ptr = ptr as *mut ListNode;
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let class = list_index(&layout);
+        match class {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: self.list_heads[index].take(),
+                };
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                unsafe {
                     new_node_ptr.write(new_node);
-                    allocator.list_heads[index] = Some(&mut *new_node_ptr);
-                }
-                None => {
-                    let ptr = ptr::NonNull::new(ptr).unwrap();
-                    allocator.fallback_allocator.deallocate(ptr, layout);
+                    self.list_heads[index] = Some(&mut *new_node_ptr);
                 }
+                self.classes[index].frees += 1;
+                self.classes[index].free_blocks += 1;
             }
+            None => {
+                let ptr = ptr::NonNull::new(ptr).unwrap();
+                unsafe { self.fallback_allocator.deallocate(ptr, layout) };
+            }
+        }
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            strategy: "fixed-block",
+            heap_size: self.fallback_allocator.size(),
+            heap_used: self.fallback_allocator.used(),
+            heap_max_size: self.max_size,
+            classes: self.classes,
+            ..AllocatorStats::default()
         }
     }
+
+    fn reuses_blocks(&self, layout: &Layout) -> bool {
+        list_index(layout).is_some()
+    }
 }
+
+pub type LockFixedSizeBlockAllocator = Locked<FixedSizeBlockAllocator>;
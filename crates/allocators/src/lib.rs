@@ -1,4 +1,326 @@
 #![no_std]
 extern crate alloc;
 
+pub mod buddy;
+#[cfg(feature = "heap-debug")]
+mod debug;
 pub mod fixed_block;
+#[cfg(not(feature = "heap-debug"))]
+mod magazine;
+pub mod slab;
+pub mod tlsf;
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{Acquire, Relaxed, Release},
+};
+use custom_types::spin_lock::{Guard, SpinLock};
+use fixed_block::{CLASSES, ClassStats};
+#[cfg(not(feature = "heap-debug"))]
+use magazine::{CpuCache, MAX_CPUS, class_layout};
+
+// The heap grows by at least this much, so small allocations don't map a page each
+const GROW_STEP: usize = 64 * 1024;
+const PAGE_SIZE: usize = 4096;
+
+// Maps `size` bytes of fresh memory at `start`; returns false when that is not possible
+pub type GrowHeap = fn(start: usize, size: usize) -> bool;
+
+// Frees memory the owner can spare, such as clean cached blocks, and returns the bytes released.
+// Called from inside a failed allocation, so it must not wait for locks and should not allocate
+pub type Reclaim = fn() -> usize;
+
+const MAX_RECLAIMERS: usize = 8;
+
+// A heap strategy, which `Locked` turns into a global allocator
+pub trait HeapAllocator {
+    /// # Safety
+    /// The range must be mapped, unused and stay reserved for this heap.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    // Lets the heap grow at its top, up to `max_size` bytes in total
+    fn set_growth(&mut self, max_size: usize, grow: GrowHeap);
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// # Safety
+    /// The pointer must come from `allocate` on this heap with the same layout.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    fn stats(&self) -> AllocatorStats;
+
+    // Whether a block freed with this layout is only ever reused whole, for the same size class
+    fn reuses_blocks(&self, _layout: &Layout) -> bool {
+        false
+    }
+}
+
+// Bytes are counted as requested by the callers, without block rounding
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorStats {
+    pub strategy: &'static str,
+    pub allocations: usize,
+    pub frees: usize,
+    pub failed_allocations: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub heap_size: usize,
+    pub heap_used: usize,
+    pub heap_max_size: usize,
+    // The largest allocation possible without growing, if the strategy can tell
+    pub largest_free: Option<usize>,
+    // Free blocks held by the per-CPU caches, which the heap counts as used
+    pub cached: usize,
+    // Bytes released by the caches and reclaim callbacks when the heap ran out
+    pub reclaimed: usize,
+    // Counted per caller request; free blocks are those in the class free lists, which only the
+    // fixed-size block allocator keeps, plus those in the per-CPU caches
+    pub classes: [ClassStats; CLASSES],
+}
+
+// Counted outside the heap lock, so the per-CPU fast path can keep them up to date
+struct Counters {
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    failed_allocations: AtomicUsize,
+    in_use: AtomicUsize,
+    peak: AtomicUsize,
+    reclaimed: AtomicUsize,
+    class_allocations: [AtomicUsize; CLASSES],
+    class_frees: [AtomicUsize; CLASSES],
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            failed_allocations: AtomicUsize::new(0),
+            in_use: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            reclaimed: AtomicUsize::new(0),
+            class_allocations: [const { AtomicUsize::new(0) }; CLASSES],
+            class_frees: [const { AtomicUsize::new(0) }; CLASSES],
+        }
+    }
+
+    fn record_alloc(&self, ptr: *mut u8, layout: &Layout) {
+        if ptr.is_null() {
+            self.failed_allocations.fetch_add(1, Relaxed);
+            return;
+        }
+        self.allocations.fetch_add(1, Relaxed);
+        if let Some(class) = fixed_block::list_index(layout) {
+            self.class_allocations[class].fetch_add(1, Relaxed);
+        }
+        let in_use = self.in_use.fetch_add(layout.size(), Relaxed) + layout.size();
+        self.peak.fetch_max(in_use, Relaxed);
+    }
+
+    fn record_dealloc(&self, layout: &Layout) {
+        self.frees.fetch_add(1, Relaxed);
+        if let Some(class) = fixed_block::list_index(layout) {
+            self.class_frees[class].fetch_add(1, Relaxed);
+        }
+        self.in_use.fetch_sub(layout.size(), Relaxed);
+    }
+}
+
+// How far to grow for `required` more bytes, in whole pages and within what is left
+fn grow_size(required: usize, available: usize) -> Option<usize> {
+    let size = required
+        .max(GROW_STEP)
+        .next_multiple_of(PAGE_SIZE)
+        .min(available);
+    (size >= required).then_some(size)
+}
+
+// The shared heap behind one lock. Without heap-debug, size-class allocations go through
+// per-CPU caches first and only take the lock to refill or drain them
+pub struct Locked<A> {
+    heap: SpinLock<A>,
+    #[cfg(not(feature = "heap-debug"))]
+    caches: [SpinLock<CpuCache>; MAX_CPUS],
+    #[cfg(not(feature = "heap-debug"))]
+    cpu_id: fn() -> usize,
+    counters: Counters,
+    reclaimers: SpinLock<[Option<Reclaim>; MAX_RECLAIMERS]>,
+    reclaiming: AtomicBool,
+}
+
+// Only the boot CPU runs the kernel so far
+fn boot_cpu() -> usize {
+    0
+}
+
+impl<A> Locked<A> {
+    pub const fn new(allocator: A) -> Self {
+        Self::with_cpu_id(allocator, boot_cpu)
+    }
+
+    // CPUs with an id of MAX_CPUS or above always use the shared heap
+    #[cfg_attr(feature = "heap-debug", allow(unused_variables))]
+    pub const fn with_cpu_id(allocator: A, cpu_id: fn() -> usize) -> Self {
+        Locked {
+            heap: SpinLock::new(allocator),
+            #[cfg(not(feature = "heap-debug"))]
+            caches: [const { SpinLock::new(CpuCache::new()) }; MAX_CPUS],
+            #[cfg(not(feature = "heap-debug"))]
+            cpu_id,
+            counters: Counters::new(),
+            reclaimers: SpinLock::new([None; MAX_RECLAIMERS]),
+            reclaiming: AtomicBool::new(false),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, A> {
+        self.heap.lock()
+    }
+
+    // An interrupt that lands while its CPU is inside the cache falls back to the shared heap
+    #[cfg(not(feature = "heap-debug"))]
+    fn cache(&self) -> Option<Guard<'_, CpuCache>> {
+        self.caches.get((self.cpu_id)())?.try_lock()
+    }
+}
+
+impl<A: HeapAllocator> Locked<A> {
+    pub fn stats(&self) -> AllocatorStats {
+        let heap = self.heap.lock().stats();
+        let mut classes = heap.classes;
+        for (class, stats) in classes.iter_mut().enumerate() {
+            stats.allocations = self.counters.class_allocations[class].load(Relaxed);
+            stats.frees = self.counters.class_frees[class].load(Relaxed);
+        }
+        #[cfg(not(feature = "heap-debug"))]
+        let mut cached = 0;
+        #[cfg(not(feature = "heap-debug"))]
+        for cache in self.caches.iter().filter_map(|cache| cache.try_lock()) {
+            cached += cache.cached();
+            for (class, stats) in classes.iter_mut().enumerate() {
+                stats.free_blocks += cache.blocks(class);
+            }
+        }
+        #[cfg(feature = "heap-debug")]
+        let cached = 0;
+        AllocatorStats {
+            allocations: self.counters.allocations.load(Relaxed),
+            frees: self.counters.frees.load(Relaxed),
+            failed_allocations: self.counters.failed_allocations.load(Relaxed),
+            in_use: self.counters.in_use.load(Relaxed),
+            peak: self.counters.peak.load(Relaxed),
+            reclaimed: self.counters.reclaimed.load(Relaxed),
+            cached,
+            classes,
+            ..heap
+        }
+    }
+
+    // Returns the blocks held by the per-CPU caches to the heap, returning the bytes released
+    pub fn flush_caches(&self) -> usize {
+        self.flush().1
+    }
+
+    // Returns the number of blocks flushed and the bytes released. Blocks that go back onto a
+    // class free list are only reusable by their class, so they don't count as released
+    fn flush(&self) -> (usize, usize) {
+        #[cfg(not(feature = "heap-debug"))]
+        return (self.caches.iter())
+            .filter_map(|cache| cache.try_lock())
+            .map(|mut cache| cache.flush(&self.heap))
+            .fold((0, 0), |(blocks, bytes), flushed| {
+                (blocks + flushed.0, bytes + flushed.1)
+            });
+        #[cfg(feature = "heap-debug")]
+        (0, 0)
+    }
+
+    pub fn register_reclaim(&self, reclaim: Reclaim) -> Result<(), &'static str> {
+        let mut reclaimers = self.reclaimers.lock();
+        let slot = (reclaimers.iter_mut())
+            .find(|slot| slot.is_none())
+            .ok_or("Too many reclaim callbacks")?;
+        *slot = Some(reclaim);
+        Ok(())
+    }
+
+    // Empties the per-CPU caches, then runs the callbacks in the order they were registered,
+    // retrying after every step that released memory. A failure during reclaim fails right away
+    fn reclaim(&self, layout: Layout) -> *mut u8 {
+        if self.reclaiming.swap(true, Acquire) {
+            return ptr::null_mut();
+        }
+        let reclaimers = *self.reclaimers.lock();
+        let mut ptr = ptr::null_mut();
+        // Flushed blocks may be of the class that ran out, so retry even if no bytes were released
+        let (flushed, mut reclaimed) = self.flush();
+        if flushed > 0 {
+            ptr = self.allocate(layout);
+        }
+        for reclaim in reclaimers.iter().flatten() {
+            if !ptr.is_null() {
+                break;
+            }
+            let released = reclaim();
+            reclaimed += released;
+            if released > 0 {
+                ptr = self.allocate(layout);
+            }
+        }
+        self.counters.reclaimed.fetch_add(reclaimed, Relaxed);
+        self.reclaiming.store(false, Release);
+        ptr
+    }
+
+    #[cfg(feature = "heap-debug")]
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        debug::allocate(&mut *self.heap.lock(), layout)
+    }
+
+    #[cfg(not(feature = "heap-debug"))]
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = fixed_block::list_index(&layout) else {
+            return self.heap.lock().allocate(layout);
+        };
+        match self.cache() {
+            Some(mut cache) => cache.allocate(class, &self.heap),
+            None => self.heap.lock().allocate(class_layout(class)),
+        }
+    }
+
+    #[cfg(not(feature = "heap-debug"))]
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        let Some(class) = fixed_block::list_index(&layout) else {
+            return unsafe { self.heap.lock().deallocate(ptr, layout) };
+        };
+        match self.cache() {
+            Some(mut cache) => cache.deallocate(class, ptr, &self.heap),
+            None => unsafe { self.heap.lock().deallocate(ptr, class_layout(class)) },
+        }
+    }
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = self.allocate(layout);
+        if ptr.is_null() {
+            ptr = self.reclaim(layout);
+        }
+        self.counters.record_alloc(ptr, &layout);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.counters.record_dealloc(&layout);
+        #[cfg(feature = "heap-debug")]
+        unsafe {
+            debug::deallocate(&mut *self.heap.lock(), ptr, layout)
+        };
+        #[cfg(not(feature = "heap-debug"))]
+        unsafe {
+            self.deallocate(ptr, layout)
+        };
+    }
+}
@@ -0,0 +1,120 @@
+use crate::HeapAllocator;
+use crate::fixed_block::{BLOCK_SIZES, CLASSES};
+use alloc::alloc::Layout;
+use core::ptr;
+use custom_types::spin_lock::SpinLock;
+
+pub const MAX_CPUS: usize = 8;
+
+// A magazine holds up to this many blocks, but never more than MAGAZINE_BYTES of one class
+const MAGAZINE_SIZE: usize = 32;
+const MAGAZINE_BYTES: usize = 16 * 1024;
+
+// Every block in a cache is allocated with its class layout, so any caller layout of the class fits
+pub(crate) fn class_layout(class: usize) -> Layout {
+    Layout::from_size_align(BLOCK_SIZES[class], BLOCK_SIZES[class]).unwrap()
+}
+
+fn capacity(class: usize) -> usize {
+    (MAGAZINE_BYTES / BLOCK_SIZES[class]).clamp(4, MAGAZINE_SIZE)
+}
+
+struct Magazine {
+    count: usize,
+    blocks: [*mut u8; MAGAZINE_SIZE],
+}
+
+// Free blocks of every size class kept by one CPU; refills and drains move half a magazine at once
+pub(crate) struct CpuCache {
+    magazines: [Magazine; CLASSES],
+}
+
+unsafe impl Send for CpuCache {}
+
+impl CpuCache {
+    pub(crate) const fn new() -> Self {
+        CpuCache {
+            magazines: [const {
+                Magazine {
+                    count: 0,
+                    blocks: [ptr::null_mut(); MAGAZINE_SIZE],
+                }
+            }; CLASSES],
+        }
+    }
+
+    pub(crate) fn allocate(
+        &mut self,
+        class: usize,
+        heap: &SpinLock<impl HeapAllocator>,
+    ) -> *mut u8 {
+        let magazine = &mut self.magazines[class];
+        if magazine.count == 0 {
+            let layout = class_layout(class);
+            let mut heap = heap.lock();
+            while magazine.count < capacity(class) / 2 {
+                let block = heap.allocate(layout);
+                if block.is_null() {
+                    break;
+                }
+                magazine.blocks[magazine.count] = block;
+                magazine.count += 1;
+            }
+        }
+        if magazine.count == 0 {
+            return ptr::null_mut();
+        }
+        magazine.count -= 1;
+        magazine.blocks[magazine.count]
+    }
+
+    pub(crate) fn deallocate(
+        &mut self,
+        class: usize,
+        block: *mut u8,
+        heap: &SpinLock<impl HeapAllocator>,
+    ) {
+        let magazine = &mut self.magazines[class];
+        if magazine.count == capacity(class) {
+            let layout = class_layout(class);
+            let mut heap = heap.lock();
+            for _ in 0..capacity(class) / 2 {
+                magazine.count -= 1;
+                unsafe { heap.deallocate(magazine.blocks[magazine.count], layout) };
+            }
+        }
+        magazine.blocks[magazine.count] = block;
+        magazine.count += 1;
+    }
+
+    // Hands every cached block back to the heap, returning the number of blocks and the bytes
+    // the heap can reuse for any size
+    pub(crate) fn flush(&mut self, heap: &SpinLock<impl HeapAllocator>) -> (usize, usize) {
+        let (mut blocks, mut released) = (0, 0);
+        let mut heap = heap.lock();
+        for (class, magazine) in self.magazines.iter_mut().enumerate() {
+            let layout = class_layout(class);
+            for &block in &magazine.blocks[..magazine.count] {
+                unsafe { heap.deallocate(block, layout) };
+            }
+            if !heap.reuses_blocks(&layout) {
+                released += magazine.count * BLOCK_SIZES[class];
+            }
+            blocks += magazine.count;
+            magazine.count = 0;
+        }
+        (blocks, released)
+    }
+
+    pub(crate) fn blocks(&self, class: usize) -> usize {
+        self.magazines[class].count
+    }
+
+    pub(crate) fn cached(&self) -> usize {
+        self.magazines
+            .iter()
+            .zip(BLOCK_SIZES)
+            .map(|(magazine, size)| magazine.count * size)
+            .sum()
+    }
+}
@@ -0,0 +1,342 @@
+use alloc::alloc::{Layout, alloc, dealloc};
+use core::{
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+use custom_types::spin_lock::SpinLock;
+
+const PAGE_SIZE: usize = 4096;
+// Slabs grow past a page until they hold at least this many objects
+const MIN_OBJECTS: usize = 8;
+// Empty slabs kept around so a cache hovering at a slab boundary doesn't churn pages
+const MAX_EMPTY: usize = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slab_size: usize,
+    pub slabs: usize,
+    pub empty_slabs: usize,
+    pub in_use: usize,
+}
+
+// Header at the start of every slab, followed by a stack of free slot indices and the objects
+#[repr(C)]
+struct Slab {
+    next: *mut Slab,
+    prev: *mut Slab,
+    in_use: usize,
+    free: usize,
+}
+
+struct Slabs {
+    partial: *mut Slab,
+    full: *mut Slab,
+    empty: *mut Slab,
+    slabs: usize,
+    empty_slabs: usize,
+    in_use: usize,
+}
+
+unsafe impl Send for Slabs {}
+
+// Object cache for one type. With hooks, free objects stay constructed: the constructor runs when
+// a slab is carved, the destructor when it is given back, and objects are returned as they are
+pub struct SlabCache<T> {
+    name: &'static str,
+    constructor: Option<fn() -> T>,
+    destructor: Option<fn(T)>,
+    slabs: SpinLock<Slabs>,
+    slab_size: usize,
+    objects: usize,
+    objects_offset: usize,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Sync for SlabCache<T> {}
+unsafe impl<T: Send> Send for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+    pub const fn new(name: &'static str) -> Self {
+        let (slab_size, objects, objects_offset) = geometry(stride::<T>(), mem::align_of::<T>());
+        SlabCache {
+            name,
+            constructor: None,
+            destructor: None,
+            slabs: SpinLock::new(Slabs {
+                partial: ptr::null_mut(),
+                full: ptr::null_mut(),
+                empty: ptr::null_mut(),
+                slabs: 0,
+                empty_slabs: 0,
+                in_use: 0,
+            }),
+            slab_size,
+            objects,
+            objects_offset,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn with_hooks(name: &'static str, constructor: fn() -> T, destructor: fn(T)) -> Self {
+        let mut cache = Self::new(name);
+        cache.constructor = Some(constructor);
+        cache.destructor = Some(destructor);
+        cache
+    }
+
+    // Hands out a constructed object as it was returned last; None without a constructor
+    pub fn allocate(&self) -> Option<SlabBox<'_, T>> {
+        self.constructor?;
+        self.allocate_slot()
+    }
+
+    pub fn allocate_with(&self, value: T) -> Option<SlabBox<'_, T>> {
+        let slot = self.allocate_slot()?;
+        let object = slot.object.as_ptr();
+        unsafe {
+            match self.constructor {
+                Some(_) => *object = value,
+                None => object.write(value),
+            }
+        }
+        Some(slot)
+    }
+
+    // Gives every empty slab back to the heap; returns the number of bytes released
+    pub fn reclaim(&self) -> usize {
+        let mut slabs = self.slabs.lock();
+        let mut released = 0;
+        while !slabs.empty.is_null() {
+            let slab = slabs.empty;
+            unsafe { self.release(&mut slabs, slab) };
+            released += self.slab_size;
+        }
+        released
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let slabs = self.slabs.lock();
+        SlabStats {
+            name: self.name,
+            object_size: mem::size_of::<T>(),
+            objects_per_slab: self.objects,
+            slab_size: self.slab_size,
+            slabs: slabs.slabs,
+            empty_slabs: slabs.empty_slabs,
+            in_use: slabs.in_use,
+        }
+    }
+
+    fn allocate_slot(&self) -> Option<SlabBox<'_, T>> {
+        let mut slabs = self.slabs.lock();
+        let slab = match (slabs.partial, slabs.empty) {
+            (partial, _) if !partial.is_null() => partial,
+            (_, empty) if !empty.is_null() => unsafe {
+                unlink(&mut slabs.empty, empty);
+                slabs.empty_slabs -= 1;
+                push(&mut slabs.partial, empty);
+                empty
+            },
+            _ => {
+                let slab = self.carve()?;
+                slabs.slabs += 1;
+                unsafe { push(&mut slabs.partial, slab) };
+                slab
+            }
+        };
+
+        let object = unsafe {
+            let header = &mut *slab;
+            header.free -= 1;
+            let index = self.free_stack(slab).add(header.free).read();
+            header.in_use += 1;
+            if header.in_use == self.objects {
+                unlink(&mut slabs.partial, slab);
+                push(&mut slabs.full, slab);
+            }
+            self.object(slab, index as usize)
+        };
+        slabs.in_use += 1;
+        Some(SlabBox {
+            cache: self,
+            object: NonNull::new(object)?,
+        })
+    }
+
+    fn free_slot(&self, object: *mut T) {
+        let slab = (object as usize & !(self.slab_size - 1)) as *mut Slab;
+        let index = (object as usize - slab as usize - self.objects_offset) / stride::<T>();
+
+        let mut slabs = self.slabs.lock();
+        slabs.in_use -= 1;
+        unsafe {
+            let header = &mut *slab;
+            if header.in_use == self.objects {
+                unlink(&mut slabs.full, slab);
+                push(&mut slabs.partial, slab);
+            }
+            self.free_stack(slab).add(header.free).write(index as u16);
+            header.free += 1;
+            header.in_use -= 1;
+            if header.in_use == 0 {
+                unlink(&mut slabs.partial, slab);
+                push(&mut slabs.empty, slab);
+                slabs.empty_slabs += 1;
+                if slabs.empty_slabs > MAX_EMPTY {
+                    self.release(&mut slabs, slab);
+                }
+            }
+        }
+    }
+
+    fn carve(&self) -> Option<*mut Slab> {
+        let layout = Layout::from_size_align(self.slab_size, self.slab_size).ok()?;
+        let slab = unsafe { alloc(layout) } as *mut Slab;
+        if slab.is_null() {
+            return None;
+        }
+        unsafe {
+            slab.write(Slab {
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                in_use: 0,
+                free: self.objects,
+            });
+            // Reversed, so objects are handed out from the start of the slab
+            for index in 0..self.objects {
+                let slot = self.objects - 1 - index;
+                self.free_stack(slab).add(index).write(slot as u16);
+                if let Some(constructor) = self.constructor {
+                    self.object(slab, slot).write(constructor());
+                }
+            }
+        }
+        Some(slab)
+    }
+
+    unsafe fn release(&self, slabs: &mut Slabs, slab: *mut Slab) {
+        unsafe {
+            unlink(&mut slabs.empty, slab);
+            if self.constructor.is_some() {
+                for index in 0..self.objects {
+                    let object = self.object(slab, index).read();
+                    match self.destructor {
+                        Some(destructor) => destructor(object),
+                        None => drop(object),
+                    }
+                }
+            }
+            let layout = Layout::from_size_align_unchecked(self.slab_size, self.slab_size);
+            dealloc(slab as *mut u8, layout);
+        }
+        slabs.slabs -= 1;
+        slabs.empty_slabs -= 1;
+    }
+
+    fn free_stack(&self, slab: *mut Slab) -> *mut u16 {
+        unsafe { slab.add(1) as *mut u16 }
+    }
+
+    fn object(&self, slab: *mut Slab, index: usize) -> *mut T {
+        unsafe { (slab as *mut u8).add(self.objects_offset + index * stride::<T>()) as *mut T }
+    }
+}
+
+impl<T> Drop for SlabCache<T> {
+    // Objects borrow the cache, so by now every slab is empty
+    fn drop(&mut self) {
+        self.reclaim();
+    }
+}
+
+// Owned object in a slab cache, returned to the cache when dropped
+pub struct SlabBox<'a, T> {
+    cache: &'a SlabCache<T>,
+    object: NonNull<T>,
+}
+
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Sync> Sync for SlabBox<'_, T> {}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        // Constructed caches keep the object alive for the next user
+        if self.cache.constructor.is_none() {
+            unsafe { ptr::drop_in_place(self.object.as_ptr()) };
+        }
+        self.cache.free_slot(self.object.as_ptr());
+    }
+}
+
+const fn stride<T>() -> usize {
+    if mem::size_of::<T>() == 0 {
+        mem::align_of::<T>()
+    } else {
+        mem::size_of::<T>()
+    }
+}
+
+// Slab size, objects per slab and the offset of the first object
+const fn geometry(stride: usize, align: usize) -> (usize, usize, usize) {
+    let header = mem::size_of::<Slab>();
+    let mut slab_size = PAGE_SIZE;
+    loop {
+        let mut objects = (slab_size - header) / (stride + mem::size_of::<u16>());
+        while objects > 0 {
+            let offset = (header + objects * mem::size_of::<u16>()).next_multiple_of(align);
+            if offset + objects * stride <= slab_size {
+                break;
+            }
+            objects -= 1;
+        }
+        if objects >= MIN_OBJECTS {
+            let offset = (header + objects * mem::size_of::<u16>()).next_multiple_of(align);
+            return (slab_size, objects, offset);
+        }
+        slab_size *= 2;
+    }
+}
+
+unsafe fn push(head: &mut *mut Slab, slab: *mut Slab) {
+    unsafe {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = *head;
+        if !head.is_null() {
+            (**head).prev = slab;
+        }
+    }
+    *head = slab;
+}
+
+unsafe fn unlink(head: &mut *mut Slab, slab: *mut Slab) {
+    unsafe {
+        let Slab { next, prev, .. } = *slab;
+        if prev.is_null() {
+            *head = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
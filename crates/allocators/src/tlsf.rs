@@ -0,0 +1,361 @@
+use crate::{AllocatorStats, GrowHeap, HeapAllocator, grow_size};
+use alloc::alloc::Layout;
+use core::ptr;
+
+// Two-level segregated fit: the first level splits sizes by powers of two, the second splits
+// each of those into SL_COUNT linear classes. Sizes below SMALL share the first level
+const ALIGN: usize = 16;
+const SL_LOG2: u32 = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+const FL_SHIFT: u32 = SL_LOG2 + ALIGN.trailing_zeros();
+const SMALL: usize = 1 << FL_SHIFT;
+const FL_COUNT: usize = 32;
+
+// Every block starts with a header; free blocks keep their list links in the payload
+const HEADER: usize = 16;
+const MIN_PAYLOAD: usize = 16;
+
+// Flags in the low bits of the payload size
+const FREE: usize = 1;
+const PREV_FREE: usize = 2;
+const FLAGS: usize = FREE | PREV_FREE;
+
+#[repr(C)]
+struct Block {
+    prev_phys: *mut Block,
+    size: usize,
+    next_free: *mut Block,
+    prev_free: *mut Block,
+}
+
+impl Block {
+    fn size(&self) -> usize {
+        self.size & !FLAGS
+    }
+
+    fn set_size(&mut self, size: usize) {
+        self.size = size | (self.size & FLAGS);
+    }
+
+    fn is_free(&self) -> bool {
+        self.size & FREE != 0
+    }
+
+    fn next_phys(&mut self) -> *mut Block {
+        (self as *mut Block as usize + HEADER + self.size()) as *mut Block
+    }
+}
+
+// The sentinel has no payload, so only its header may be written
+unsafe fn write_sentinel(sentinel: *mut Block, prev_phys: *mut Block, size: usize) {
+    unsafe {
+        ptr::addr_of_mut!((*sentinel).prev_phys).write(prev_phys);
+        ptr::addr_of_mut!((*sentinel).size).write(size);
+    }
+}
+
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL {
+        return (0, size / (SMALL / SL_COUNT));
+    }
+    let log = usize::BITS - 1 - size.leading_zeros();
+    let fl = (log - FL_SHIFT + 1) as usize;
+    let sl = (size >> (log - SL_LOG2)) ^ SL_COUNT;
+    (fl, sl)
+}
+
+// Rounds up to the next class boundary, so every block in the class found is large enough
+fn mapping_search(size: usize) -> (usize, usize) {
+    if size < SMALL {
+        return mapping(size);
+    }
+    let log = usize::BITS - 1 - size.leading_zeros();
+    mapping(size + (1 << (log - SL_LOG2)) - 1)
+}
+
+pub struct TlsfAllocator {
+    fl_bitmap: u32,
+    sl_bitmaps: [u16; FL_COUNT],
+    heads: [[*mut Block; SL_COUNT]; FL_COUNT],
+    start: usize,
+    top: usize,
+    // Zero-sized block at the top that ends the physical block chain
+    sentinel: *mut Block,
+    max_size: usize,
+    grow: Option<GrowHeap>,
+    used: usize,
+}
+
+unsafe impl Send for TlsfAllocator {}
+
+impl Default for TlsfAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TlsfAllocator {
+    pub const fn new() -> Self {
+        TlsfAllocator {
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            heads: [[ptr::null_mut(); SL_COUNT]; FL_COUNT],
+            start: 0,
+            top: 0,
+            sentinel: ptr::null_mut(),
+            max_size: 0,
+            grow: None,
+            used: 0,
+        }
+    }
+
+    unsafe fn insert(&mut self, block: *mut Block) {
+        let (fl, sl) = mapping(unsafe { (*block).size() });
+        let head = self.heads[fl][sl];
+        unsafe {
+            (*block).next_free = head;
+            (*block).prev_free = ptr::null_mut();
+            if !head.is_null() {
+                (*head).prev_free = block;
+            }
+        }
+        self.heads[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmaps[fl] |= 1 << sl;
+    }
+
+    unsafe fn remove(&mut self, block: *mut Block) {
+        let (fl, sl) = mapping(unsafe { (*block).size() });
+        let Block {
+            next_free,
+            prev_free,
+            ..
+        } = unsafe { block.read() };
+        unsafe {
+            if !next_free.is_null() {
+                (*next_free).prev_free = prev_free;
+            }
+            if prev_free.is_null() {
+                self.heads[fl][sl] = next_free;
+            } else {
+                (*prev_free).next_free = next_free;
+            }
+        }
+        if self.heads[fl][sl].is_null() {
+            self.sl_bitmaps[fl] &= !(1 << sl);
+            if self.sl_bitmaps[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+    }
+
+    // Finds and unlinks a free block with a payload of at least `size` bytes
+    fn take(&mut self, size: usize) -> Option<*mut Block> {
+        let (fl, sl) = mapping_search(size);
+        if fl >= FL_COUNT {
+            return None;
+        }
+        let (fl, sl) = match self.sl_bitmaps[fl] as u32 & (u32::MAX << sl) {
+            0 => {
+                let fl_map = self.fl_bitmap & u32::MAX.checked_shl(fl as u32 + 1).unwrap_or(0);
+                if fl_map == 0 {
+                    return None;
+                }
+                let fl = fl_map.trailing_zeros() as usize;
+                (fl, self.sl_bitmaps[fl].trailing_zeros() as usize)
+            }
+            sl_map => (fl, sl_map.trailing_zeros() as usize),
+        };
+        let block = self.heads[fl][sl];
+        unsafe { self.remove(block) };
+        Some(block)
+    }
+
+    // Cuts the payload down to `size`, returning the rest to the free lists
+    unsafe fn split(&mut self, block: *mut Block, size: usize) {
+        unsafe {
+            let payload = (*block).size();
+            if payload < size + HEADER + MIN_PAYLOAD {
+                return;
+            }
+            let rest = (block as usize + HEADER + size) as *mut Block;
+            rest.write(Block {
+                prev_phys: block,
+                size: (payload - size - HEADER) | FREE,
+                next_free: ptr::null_mut(),
+                prev_free: ptr::null_mut(),
+            });
+            (*block).set_size(size);
+            let next = (*rest).next_phys();
+            (*next).prev_phys = rest;
+            (*next).size |= PREV_FREE;
+            self.insert(rest);
+        }
+    }
+
+    // Splits off the front of the block so that its payload is aligned, returning the new block
+    unsafe fn align_block(&mut self, block: *mut Block, align: usize) -> *mut Block {
+        unsafe {
+            let payload = block as usize + HEADER;
+            if payload.is_multiple_of(align) {
+                return block;
+            }
+            let aligned = (payload + HEADER + MIN_PAYLOAD).next_multiple_of(align);
+            let front_size = aligned - HEADER - payload;
+            let rest = (aligned - HEADER) as *mut Block;
+            rest.write(Block {
+                prev_phys: block,
+                size: ((*block).size() - front_size - HEADER) | PREV_FREE,
+                next_free: ptr::null_mut(),
+                prev_free: ptr::null_mut(),
+            });
+            (*(*rest).next_phys()).prev_phys = rest;
+            (*block).set_size(front_size);
+            (*block).size |= FREE;
+            self.insert(block);
+            rest
+        }
+    }
+
+    // Marks the block free and merges it with free neighbours
+    unsafe fn release(&mut self, block: *mut Block) {
+        unsafe {
+            let mut block = block;
+            (*block).size |= FREE;
+            let next = (*block).next_phys();
+            if (*next).is_free() {
+                self.remove(next);
+                (*block).set_size((*block).size() + HEADER + (*next).size());
+            }
+            if (*block).size & PREV_FREE != 0 {
+                let prev = (*block).prev_phys;
+                self.remove(prev);
+                (*prev).set_size((*prev).size() + HEADER + (*block).size());
+                block = prev;
+            }
+            let next = (*block).next_phys();
+            (*next).prev_phys = block;
+            (*next).size |= PREV_FREE;
+            self.insert(block);
+        }
+    }
+
+    // The old sentinel becomes the header of the new space, which merges with a free block below
+    fn extend(&mut self, size: usize) -> bool {
+        let Some(grow) = self.grow else {
+            return false;
+        };
+        let available = self.max_size - (self.top - self.start);
+        let Some(size) = grow_size(size + HEADER, available) else {
+            return false;
+        };
+        if !grow(self.top, size) {
+            return false;
+        }
+        unsafe {
+            let block = self.sentinel;
+            (*block).set_size(size - HEADER);
+            self.top += size;
+            self.sentinel = (self.top - HEADER) as *mut Block;
+            write_sentinel(self.sentinel, block, 0);
+            self.release(block);
+        }
+        true
+    }
+}
+
+impl HeapAllocator for TlsfAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        let start = heap_start.next_multiple_of(ALIGN);
+        let end = (heap_start + heap_size) & !(ALIGN - 1);
+        self.start = heap_start;
+        self.top = end;
+        self.max_size = heap_size;
+        if end < start + 2 * HEADER + MIN_PAYLOAD {
+            return;
+        }
+
+        let block = start as *mut Block;
+        self.sentinel = (end - HEADER) as *mut Block;
+        unsafe {
+            block.write(Block {
+                prev_phys: ptr::null_mut(),
+                size: (end - start - 2 * HEADER) | FREE,
+                next_free: ptr::null_mut(),
+                prev_free: ptr::null_mut(),
+            });
+            write_sentinel(self.sentinel, block, PREV_FREE);
+            self.insert(block);
+        }
+    }
+
+    fn set_growth(&mut self, max_size: usize, grow: GrowHeap) {
+        self.max_size = max_size.max(self.top - self.start);
+        self.grow = Some(grow);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = layout.size().max(MIN_PAYLOAD).next_multiple_of(ALIGN);
+        let align = layout.align();
+        // Room for a free block in front of the aligned payload
+        let search = if align > ALIGN {
+            size + align + HEADER + MIN_PAYLOAD
+        } else {
+            size
+        };
+        let block = match self.take(search) {
+            Some(block) => Some(block),
+            None if !self.sentinel.is_null() && self.extend(search) => self.take(search),
+            None => None,
+        };
+        let Some(block) = block else {
+            return ptr::null_mut();
+        };
+
+        unsafe {
+            let block = if align > ALIGN {
+                self.align_block(block, align)
+            } else {
+                block
+            };
+            self.split(block, size);
+            (*block).size &= !FREE;
+            let next = (*block).next_phys();
+            (*next).size &= !PREV_FREE;
+            self.used += HEADER + (*block).size();
+            (block as usize + HEADER) as *mut u8
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, _layout: Layout) {
+        let block = (ptr as usize - HEADER) as *mut Block;
+        unsafe {
+            self.used -= HEADER + (*block).size();
+            self.release(block);
+        }
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        // Only the highest class can hold the largest block, so walking its list is enough
+        let mut largest = 0;
+        if self.fl_bitmap != 0 {
+            let fl = (u32::BITS - 1 - self.fl_bitmap.leading_zeros()) as usize;
+            let sl = (u16::BITS - 1 - self.sl_bitmaps[fl].leading_zeros()) as usize;
+            let mut block = self.heads[fl][sl];
+            while !block.is_null() {
+                unsafe {
+                    largest = largest.max((*block).size());
+                    block = (*block).next_free;
+                }
+            }
+        }
+        AllocatorStats {
+            strategy: "tlsf",
+            heap_size: self.top - self.start,
+            heap_used: self.used,
+            heap_max_size: self.max_size,
+            largest_free: Some(largest),
+            ..AllocatorStats::default()
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use block::{Error, Result};
+use x86_64::instructions::port::Port;
+
+const DATA: u16 = 0;
+const ERROR: u16 = 1;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE_HEAD: u16 = 6;
+const STATUS_COMMAND: u16 = 7;
+
+// Device control register bits
+const NO_INTERRUPTS: u8 = 0x02;
+
+const POLL_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub u8);
+
+impl Status {
+    pub const ERR: u8 = 0x01;
+    pub const DRQ: u8 = 0x08;
+    pub const DF: u8 = 0x20;
+    pub const RDY: u8 = 0x40;
+    pub const BSY: u8 = 0x80;
+
+    pub fn contains(self, bits: u8) -> bool {
+        self.0 & bits != 0
+    }
+}
+
+pub struct Channel {
+    io_base: u16,
+    control_base: u16,
+    selected: Option<u8>,
+}
+
+impl Channel {
+    pub fn new(io_base: u16, control_base: u16) -> Self {
+        // The driver polls, and IRQ 14/15 have no handler in the IDT
+        unsafe { Port::new(control_base).write(NO_INTERRUPTS) };
+        Channel {
+            io_base,
+            control_base,
+            selected: None,
+        }
+    }
+
+    // A status of 0xFF means nothing is pulling the bus down: no drives are attached
+    pub fn is_floating(&self) -> bool {
+        self.status().0 == 0xFF
+    }
+
+    pub(crate) fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.io_base + register).read() }
+    }
+
+    pub(crate) fn write(&mut self, register: u16, value: u8) {
+        unsafe { Port::new(self.io_base + register).write(value) }
+    }
+
+    pub(crate) fn read_data(&self, buf: &mut [u8]) {
+        let mut port = Port::<u16>::new(self.io_base + DATA);
+        for word in buf.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { port.read() }.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn write_data(&mut self, buf: &[u8]) {
+        let mut port = Port::<u16>::new(self.io_base + DATA);
+        for word in buf.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        Status(self.read(STATUS_COMMAND))
+    }
+
+    fn alternate_status(&self) -> Status {
+        Status(unsafe { Port::new(self.control_base).read() })
+    }
+
+    // Reading the alternate status four times gives the drive the required 400ns to settle
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    pub(crate) fn select(&mut self, drive_head: u8) {
+        if self.selected != Some(drive_head) {
+            self.write(DRIVE_HEAD, drive_head);
+            self.delay();
+            self.selected = Some(drive_head);
+        }
+    }
+
+    pub(crate) fn set_lba28(&mut self, lba: u64, count: u8) {
+        self.write(SECTOR_COUNT, count);
+        self.write(LBA_LOW, lba as u8);
+        self.write(LBA_MID, (lba >> 8) as u8);
+        self.write(LBA_HIGH, (lba >> 16) as u8);
+    }
+
+    pub(crate) fn set_lba48(&mut self, lba: u64, count: u16) {
+        // High order bytes go first, the registers are two bytes deep
+        self.write(SECTOR_COUNT, (count >> 8) as u8);
+        self.write(LBA_LOW, (lba >> 24) as u8);
+        self.write(LBA_MID, (lba >> 32) as u8);
+        self.write(LBA_HIGH, (lba >> 40) as u8);
+        self.write(SECTOR_COUNT, count as u8);
+        self.write(LBA_LOW, lba as u8);
+        self.write(LBA_MID, (lba >> 8) as u8);
+        self.write(LBA_HIGH, (lba >> 16) as u8);
+    }
+
+    pub(crate) fn lba_signature(&self) -> (u8, u8) {
+        (self.read(LBA_MID), self.read(LBA_HIGH))
+    }
+
+    pub(crate) fn command(&mut self, command: u8) {
+        self.write(STATUS_COMMAND, command);
+        self.delay();
+    }
+
+    pub(crate) fn wait_not_busy(&self) -> Result<Status> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.status();
+            if !status.contains(Status::BSY) {
+                return Ok(status);
+            }
+            core::hint::spin_loop();
+        }
+        Err(Error::Timeout)
+    }
+
+    // Waits until the drive is ready to transfer a sector of data
+    pub(crate) fn wait_data(&self) -> Result<()> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.status();
+            if status.contains(Status::BSY) {
+                core::hint::spin_loop();
+                continue;
+            }
+            self.check_error(status)?;
+            if status.contains(Status::DRQ) {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    pub(crate) fn check_error(&self, status: Status) -> Result<()> {
+        if status.contains(Status::DF) {
+            return Err(Error::Device("Drive fault"));
+        }
+        if status.contains(Status::ERR) {
+            return Err(Error::Device(decode_error(self.read(ERROR))));
+        }
+        Ok(())
+    }
+}
+
+fn decode_error(error: u8) -> &'static str {
+    match error {
+        e if e & 0x80 != 0 => "Bad block detected",
+        e if e & 0x40 != 0 => "Uncorrectable data error",
+        e if e & 0x20 != 0 => "Media changed",
+        e if e & 0x10 != 0 => "Sector ID not found",
+        e if e & 0x08 != 0 => "Media change requested",
+        e if e & 0x04 != 0 => "Command aborted",
+        e if e & 0x02 != 0 => "Track zero not found",
+        e if e & 0x01 != 0 => "Address mark not found",
+        _ => "Unknown ATA error",
+    }
+}
@@ -0,0 +1,202 @@
+use crate::channel::Channel;
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+};
+use block::{BlockDevice, Error, Result, SECTOR_SIZE};
+use custom_types::spin_lock::SpinLock;
+
+const IDENTIFY: u8 = 0xEC;
+const READ_SECTORS: u8 = 0x20;
+const READ_SECTORS_EXT: u8 = 0x24;
+const WRITE_SECTORS: u8 = 0x30;
+const WRITE_SECTORS_EXT: u8 = 0x34;
+const CACHE_FLUSH: u8 = 0xE7;
+const CACHE_FLUSH_EXT: u8 = 0xEA;
+
+const LBA28_LIMIT: u64 = 1 << 28;
+const MAX_SECTORS_LBA28: u64 = 255;
+const MAX_SECTORS_LBA48: u64 = 65535;
+
+#[derive(Debug, Clone)]
+pub struct Identify {
+    pub model: String,
+    pub serial: String,
+    pub sectors: u64,
+    pub lba48: bool,
+}
+
+impl Identify {
+    fn parse(data: &[u8; SECTOR_SIZE]) -> Self {
+        let word = |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]);
+
+        let lba48 = word(83) & (1 << 10) != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0u64, |acc, i| acc | (word(100 + i) as u64) << (16 * i))
+        } else {
+            word(60) as u64 | (word(61) as u64) << 16
+        };
+
+        Identify {
+            model: ata_string(data, 27..47),
+            serial: ata_string(data, 10..20),
+            sectors,
+            lba48,
+        }
+    }
+}
+
+// Strings in the IDENTIFY data have the two bytes of every word swapped
+fn ata_string(data: &[u8; SECTOR_SIZE], words: core::ops::Range<usize>) -> String {
+    let mut string = String::new();
+    for index in words {
+        string.push(data[index * 2 + 1] as char);
+        string.push(data[index * 2] as char);
+    }
+    string.trim().to_string()
+}
+
+pub struct AtaDrive {
+    channel: Arc<SpinLock<Channel>>,
+    index: usize,
+    slave: bool,
+    info: Identify,
+}
+
+impl AtaDrive {
+    pub fn identify(channel: Arc<SpinLock<Channel>>, index: usize, slave: bool) -> Option<Self> {
+        let mut data = [0u8; SECTOR_SIZE];
+        {
+            let mut ch = channel.lock();
+            ch.select(0xA0 | (slave as u8) << 4);
+            ch.set_lba28(0, 0);
+            ch.command(IDENTIFY);
+
+            if ch.status().0 == 0 {
+                return None;
+            }
+            ch.wait_not_busy().ok()?;
+
+            // ATAPI and SATA devices identify themselves through the LBA registers
+            if ch.lba_signature() != (0, 0) {
+                return None;
+            }
+            ch.wait_data().ok()?;
+            ch.read_data(&mut data);
+        }
+
+        Some(AtaDrive {
+            channel,
+            index,
+            slave,
+            info: Identify::parse(&data),
+        })
+    }
+
+    pub fn info(&self) -> &Identify {
+        &self.info
+    }
+
+    // Legacy IDE naming: hda/hdb on the primary channel, hdc/hdd on the secondary one
+    pub fn name(&self) -> String {
+        let mut name = String::from("hd");
+        name.push((b'a' + self.index as u8) as char);
+        name
+    }
+
+    fn start_transfer(
+        &self,
+        channel: &mut Channel,
+        lba: u64,
+        count: u64,
+        write: bool,
+    ) -> Result<()> {
+        // LBA28 also takes 256 sectors, encoded as zero, but chunks stay below that
+        let use_lba48 = lba + count > LBA28_LIMIT || count > MAX_SECTORS_LBA28;
+        if use_lba48 && !self.info.lba48 {
+            return Err(Error::OutOfRange);
+        }
+
+        let slave = (self.slave as u8) << 4;
+        if use_lba48 {
+            channel.select(0x40 | slave);
+            channel.wait_not_busy()?;
+            channel.set_lba48(lba, count as u16);
+            channel.command(if write {
+                WRITE_SECTORS_EXT
+            } else {
+                READ_SECTORS_EXT
+            });
+        } else {
+            channel.select(0xE0 | slave | ((lba >> 24) as u8 & 0x0F));
+            channel.wait_not_busy()?;
+            channel.set_lba28(lba, count as u8);
+            channel.command(if write { WRITE_SECTORS } else { READ_SECTORS });
+        }
+        Ok(())
+    }
+
+    fn max_sectors(&self) -> u64 {
+        if self.info.lba48 {
+            MAX_SECTORS_LBA48
+        } else {
+            MAX_SECTORS_LBA28
+        }
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_count(&self) -> u64 {
+        self.info.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        self.check_request(lba, buf.len())?;
+
+        let mut channel = self.channel.lock();
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(self.max_sectors() as usize * SECTOR_SIZE) {
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.start_transfer(&mut channel, lba, count, false)?;
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                channel.wait_data()?;
+                channel.read_data(sector);
+            }
+            lba += count;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        self.check_request(lba, buf.len())?;
+
+        let mut channel = self.channel.lock();
+        let mut lba = lba;
+        for chunk in buf.chunks(self.max_sectors() as usize * SECTOR_SIZE) {
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.start_transfer(&mut channel, lba, count, true)?;
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                channel.wait_data()?;
+                channel.write_data(sector);
+            }
+            let status = channel.wait_not_busy()?;
+            channel.check_error(status)?;
+            lba += count;
+        }
+        drop(channel);
+        self.flush()
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut channel = self.channel.lock();
+        let slave = (self.slave as u8) << 4;
+        channel.select(0xE0 | slave);
+        channel.command(if self.info.lba48 {
+            CACHE_FLUSH_EXT
+        } else {
+            CACHE_FLUSH
+        });
+        let status = channel.wait_not_busy()?;
+        channel.check_error(status)
+    }
+}
@@ -0,0 +1,46 @@
+#![no_std]
+extern crate alloc;
+
+mod channel;
+mod drive;
+
+pub use channel::{Channel, Status};
+pub use drive::{AtaDrive, Identify};
+
+use alloc::{sync::Arc, vec::Vec};
+use custom_types::spin_lock::SpinLock;
+
+pub const PRIMARY_IO: u16 = 0x1F0;
+pub const PRIMARY_CONTROL: u16 = 0x3F6;
+pub const SECONDARY_IO: u16 = 0x170;
+pub const SECONDARY_CONTROL: u16 = 0x376;
+
+// Probes the master and slave drives of both legacy IDE channels
+pub fn probe() -> Vec<AtaDrive> {
+    probe_channels([
+        (PRIMARY_IO, PRIMARY_CONTROL),
+        (SECONDARY_IO, SECONDARY_CONTROL),
+    ])
+}
+
+// Probes the primary and secondary channel at the given I/O and control ports
+pub fn probe_channels(channels: [(u16, u16); 2]) -> Vec<AtaDrive> {
+    let mut drives = Vec::new();
+
+    for (index, (io_base, control_base)) in channels.into_iter().enumerate() {
+        let channel = Arc::new(SpinLock::new(Channel::new(io_base, control_base)));
+        if channel.lock().is_floating() {
+            continue;
+        }
+
+        for slave in [false, true] {
+            if let Some(drive) =
+                AtaDrive::identify(channel.clone(), index * 2 + slave as usize, slave)
+            {
+                drives.push(drive);
+            }
+        }
+    }
+
+    drives
+}
@@ -0,0 +1,261 @@
+use crate::{BlockDevice, Result};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use custom_types::{
+    fallible::{try_copy, try_with_capacity, try_zeroed},
+    spin_lock::SpinLock,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub read_ahead: u64,
+    pub write_backs: u64,
+    pub cached: usize,
+    pub dirty: usize,
+    pub capacity: usize,
+}
+
+struct Entry {
+    data: Box<[u8]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+struct State {
+    entries: BTreeMap<u64, Entry>,
+    // Block addresses ordered by last use, the oldest first
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    read_ahead: usize,
+    stats: CacheStats,
+}
+
+// Write-back LRU cache of whole blocks in front of another device
+pub struct BlockCache {
+    device: Arc<dyn BlockDevice>,
+    capacity: usize,
+    state: SpinLock<State>,
+}
+
+impl BlockCache {
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize, read_ahead: usize) -> Self {
+        let capacity = capacity.max(1);
+        BlockCache {
+            device,
+            capacity,
+            state: SpinLock::new(State {
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+                read_ahead: read_ahead.min(capacity / 2),
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    // Read-ahead is kept below half the cache so it cannot flush out the blocks being read
+    pub fn set_read_ahead(&self, blocks: usize) {
+        self.state.lock().read_ahead = blocks.min(self.capacity / 2);
+    }
+
+    pub fn read_ahead(&self) -> usize {
+        self.state.lock().read_ahead
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock();
+        CacheStats {
+            cached: state.entries.len(),
+            dirty: state.entries.values().filter(|entry| entry.dirty).count(),
+            capacity: self.capacity,
+            ..state.stats
+        }
+    }
+
+    // Drops clean blocks to give memory back to the heap, returning the bytes released.
+    // Gives up instead of waiting when the cache is busy
+    pub fn shrink(&self) -> usize {
+        let Some(mut state) = self.state.try_lock() else {
+            return 0;
+        };
+        let state = &mut *state;
+        let before = state.entries.len();
+        state.entries.retain(|_, entry| entry.dirty);
+        let entries = &state.entries;
+        state.lru.retain(|_, lba| entries.contains_key(lba));
+        (before - state.entries.len()) * self.block_size()
+    }
+
+    fn touch(state: &mut State, lba: u64) {
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(entry) = state.entries.get_mut(&lba) {
+            state.lru.remove(&entry.last_used);
+            entry.last_used = clock;
+            state.lru.insert(clock, lba);
+        }
+    }
+
+    fn insert(&self, state: &mut State, lba: u64, data: &[u8], dirty: bool) -> Result<()> {
+        // Data read from the device is never newer than what is already cached
+        if let Some(entry) = state.entries.get_mut(&lba) {
+            if dirty {
+                entry.data.copy_from_slice(data);
+                entry.dirty = true;
+            }
+            Self::touch(state, lba);
+            return Ok(());
+        }
+
+        if state.entries.len() >= self.capacity {
+            self.evict(state)?;
+        }
+        state.entries.insert(
+            lba,
+            Entry {
+                data: try_copy(data)?,
+                dirty,
+                last_used: 0,
+            },
+        );
+        Self::touch(state, lba);
+        Ok(())
+    }
+
+    fn evict(&self, state: &mut State) -> Result<()> {
+        let Some((&last_used, &lba)) = state.lru.iter().next() else {
+            return Ok(());
+        };
+        if state.entries[&lba].dirty {
+            self.device.write_blocks(lba, &state.entries[&lba].data)?;
+            state.stats.write_backs += 1;
+        }
+        state.lru.remove(&last_used);
+        state.entries.remove(&lba);
+        Ok(())
+    }
+
+    // Writes every dirty block back, joining neighbouring blocks into a single request
+    fn write_back(&self, state: &mut State) -> Result<()> {
+        let dirty: Vec<u64> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&lba, _)| lba)
+            .collect();
+
+        let mut start = 0;
+        while start < dirty.len() {
+            let mut end = start + 1;
+            while end < dirty.len() && dirty[end] == dirty[end - 1] + 1 {
+                end += 1;
+            }
+
+            let mut data = try_with_capacity((end - start) * self.block_size())?;
+            for lba in &dirty[start..end] {
+                data.extend_from_slice(&state.entries[lba].data);
+            }
+            self.device.write_blocks(dirty[start], &data)?;
+            for lba in &dirty[start..end] {
+                state.entries.get_mut(lba).unwrap().dirty = false;
+            }
+            state.stats.write_backs += (end - start) as u64;
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let count = self.check_request(lba, buf.len())? as usize;
+        let block_size = self.block_size();
+        let mut state = self.state.lock();
+
+        // Requests larger than the cache go straight to the device, with newer cached data on top
+        if count > self.capacity / 2 {
+            self.device.read_blocks(lba, buf)?;
+            for (&block, entry) in state.entries.range(lba..lba + count as u64) {
+                let offset = (block - lba) as usize * block_size;
+                buf[offset..offset + block_size].copy_from_slice(&entry.data);
+            }
+            state.stats.misses += count as u64;
+            return Ok(());
+        }
+
+        let mut index = 0;
+        while index < count {
+            let block = lba + index as u64;
+            let offset = index * block_size;
+            if let Some(entry) = state.entries.get(&block) {
+                buf[offset..offset + block_size].copy_from_slice(&entry.data);
+                Self::touch(&mut state, block);
+                state.stats.hits += 1;
+                index += 1;
+                continue;
+            }
+
+            // Fetch the whole run of missing blocks at once, followed by the read-ahead window
+            let mut run = 1;
+            while index + run < count && !state.entries.contains_key(&(block + run as u64)) {
+                run += 1;
+            }
+            let mut fetch = run;
+            while fetch < run + state.read_ahead
+                && block + (fetch as u64) < self.block_count()
+                && !state.entries.contains_key(&(block + fetch as u64))
+            {
+                fetch += 1;
+            }
+
+            let mut data = try_zeroed(fetch * block_size)?;
+            self.device.read_blocks(block, &mut data)?;
+            buf[offset..offset + run * block_size].copy_from_slice(&data[..run * block_size]);
+            for (i, chunk) in data.chunks_exact(block_size).enumerate() {
+                self.insert(&mut state, block + i as u64, chunk, false)?;
+            }
+
+            state.stats.misses += run as u64;
+            state.stats.read_ahead += (fetch - run) as u64;
+            index += run;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        let count = self.check_request(lba, buf.len())? as usize;
+        let block_size = self.block_size();
+        let mut state = self.state.lock();
+
+        if count > self.capacity / 2 {
+            self.device.write_blocks(lba, buf)?;
+            for (&block, entry) in state.entries.range_mut(lba..lba + count as u64) {
+                let offset = (block - lba) as usize * block_size;
+                entry
+                    .data
+                    .copy_from_slice(&buf[offset..offset + block_size]);
+                entry.dirty = false;
+            }
+            return Ok(());
+        }
+
+        for (i, chunk) in buf.chunks_exact(block_size).enumerate() {
+            self.insert(&mut state, lba + i as u64, chunk, true)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.write_back(&mut self.state.lock())?;
+        self.device.flush()
+    }
+}
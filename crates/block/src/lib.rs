@@ -0,0 +1,76 @@
+#![no_std]
+extern crate alloc;
+
+mod cache;
+
+pub use cache::{BlockCache, CacheStats};
+
+use core::fmt;
+use custom_types::fallible::OutOfMemory;
+
+pub const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    OutOfRange,
+    BufferSize,
+    NoDevice,
+    Timeout,
+    ReadOnly,
+    NoMemory,
+    Device(&'static str),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OutOfRange => f.write_str("Block address out of range"),
+            Error::BufferSize => f.write_str("Buffer is not a multiple of the block size"),
+            Error::NoDevice => f.write_str("No such device"),
+            Error::Timeout => f.write_str("Device timed out"),
+            Error::ReadOnly => f.write_str("Device is read-only"),
+            Error::NoMemory => f.write_str("Out of memory"),
+            Error::Device(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<OutOfMemory> for Error {
+    fn from(_: OutOfMemory) -> Self {
+        Error::NoMemory
+    }
+}
+
+pub trait BlockDevice: Send + Sync {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64;
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()>;
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()>;
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+
+    // Validates a request and returns the number of blocks it covers
+    fn check_request(&self, lba: u64, len: usize) -> Result<u64> {
+        if !len.is_multiple_of(self.block_size()) {
+            return Err(Error::BufferSize);
+        }
+        let count = (len / self.block_size()) as u64;
+        match lba.checked_add(count) {
+            Some(end) if end <= self.block_count() => Ok(count),
+            _ => Err(Error::OutOfRange),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::{
+    alloc::{Layout, alloc},
+    boxed::Box,
+    vec::Vec,
+};
+use core::fmt;
+
+// Allocation helpers that return an error instead of ending in the out-of-memory handler
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfMemory;
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Out of memory")
+    }
+}
+
+pub fn try_with_capacity<T>(capacity: usize) -> Result<Vec<T>, OutOfMemory> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(capacity).map_err(|_| OutOfMemory)?;
+    Ok(vec)
+}
+
+pub fn try_zeroed(len: usize) -> Result<Vec<u8>, OutOfMemory> {
+    let mut vec = try_with_capacity(len)?;
+    vec.resize(len, 0);
+    Ok(vec)
+}
+
+pub fn try_copy<T: Copy>(data: &[T]) -> Result<Box<[T]>, OutOfMemory> {
+    let mut vec = try_with_capacity(data.len())?;
+    vec.extend_from_slice(data);
+    Ok(vec.into_boxed_slice())
+}
+
+pub fn try_push<T>(vec: &mut Vec<T>, value: T) -> Result<(), OutOfMemory> {
+    vec.try_reserve(1).map_err(|_| OutOfMemory)?;
+    vec.push(value);
+    Ok(())
+}
+
+pub fn try_box<T>(value: T) -> Result<Box<T>, OutOfMemory> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(Box::new(value));
+    }
+    let ptr = unsafe { alloc(layout) } as *mut T;
+    if ptr.is_null() {
+        return Err(OutOfMemory);
+    }
+    unsafe {
+        ptr.write(value);
+        Ok(Box::from_raw(ptr))
+    }
+}
@@ -1,3 +1,5 @@
 #![no_std]
+extern crate alloc;
 
+pub mod fallible;
 pub mod spin_lock;
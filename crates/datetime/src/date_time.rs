@@ -1,5 +1,6 @@
 use alloc::format;
 use alloc::string::String;
+use core::fmt;
 use custom_types::spin_lock::SpinLock;
 
 #[derive(Debug, Clone, Copy)]
@@ -40,6 +41,49 @@ impl DateTime {
         )
     }
 
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        let mut days = timestamp / 86400;
+        let seconds = timestamp % 86400;
+
+        let mut year = 1970;
+        loop {
+            let days_in_year = if Self::is_leap_year(year) { 366 } else { 365 };
+            if days < days_in_year {
+                break;
+            }
+            days -= days_in_year;
+            year += 1;
+        }
+
+        let mut month = 1;
+        while days >= Self::days_in_month(month, year) as u64 {
+            days -= Self::days_in_month(month, year) as u64;
+            month += 1;
+        }
+
+        DateTime {
+            day: days as u8 + 1,
+            month,
+            year,
+            hours: (seconds / 3600) as u8,
+            minutes: (seconds % 3600 / 60) as u8,
+            seconds: (seconds % 60) as u8,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        let mut days = 0;
+        for year in 1970..self.year {
+            days += if Self::is_leap_year(year) { 366 } else { 365 };
+        }
+        for month in 1..self.month {
+            days += Self::days_in_month(month, self.year) as u64;
+        }
+        days += self.day as u64 - 1;
+
+        days * 86400 + self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64
+    }
+
     pub fn update(&mut self) {
         self.seconds += 1;
         if self.seconds < Self::MAX_SECONDS {
@@ -146,3 +190,13 @@ impl DateTime {
         (time.day, time.month, time.year)
     }
 }
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}.{:02}.{:04} {:02}:{:02}:{:02}",
+            self.day, self.month, self.year, self.hours, self.minutes, self.seconds
+        )
+    }
+}
@@ -8,7 +8,7 @@ pub use date_time::*;
 pub static TICKS: AtomicUsize = AtomicUsize::new(0);
 
 #[inline(always)]
-fn rdtsc() -> u64 {
+pub fn rdtsc() -> u64 {
     let hi: u32;
     let lo: u32;
     unsafe {
@@ -0,0 +1,89 @@
+#![no_std]
+
+// Just enough of ELF64 to load statically linked x86_64 executables
+
+pub const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3E;
+const SEGMENT_LOAD: u32 = 1;
+
+// Segment permission bits
+pub const EXECUTE: u32 = 1;
+pub const WRITE: u32 = 2;
+pub const READ: u32 = 4;
+
+pub struct Header {
+    pub entry: u64,
+    program_headers: u64,
+    program_header_count: u16,
+}
+
+impl Header {
+    pub fn parse(bytes: &[u8]) -> Result<Header, &'static str> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != *b"\x7fELF" {
+            return Err("Not an ELF file");
+        }
+        if bytes[4] != CLASS_64 || bytes[5] != LITTLE_ENDIAN {
+            return Err("Not a little-endian 64-bit ELF file");
+        }
+        if u16_at(bytes, 16) != TYPE_EXECUTABLE || u16_at(bytes, 18) != MACHINE_X86_64 {
+            return Err("Not an x86_64 executable");
+        }
+        if u16_at(bytes, 54) as usize != PROGRAM_HEADER_SIZE {
+            return Err("Unexpected program header size");
+        }
+        Ok(Header {
+            entry: u64_at(bytes, 24),
+            program_headers: u64_at(bytes, 32),
+            program_header_count: u16_at(bytes, 56),
+        })
+    }
+
+    // Where the program header table sits in the file, as (offset, length)
+    pub fn program_header_table(&self) -> (u64, usize) {
+        (
+            self.program_headers,
+            self.program_header_count as usize * PROGRAM_HEADER_SIZE,
+        )
+    }
+
+    // The loadable segments described by the program header table
+    pub fn segments<'a>(&self, table: &'a [u8]) -> impl Iterator<Item = Segment> + 'a {
+        table
+            .chunks_exact(PROGRAM_HEADER_SIZE)
+            .filter(|raw| u32_at(raw, 0) == SEGMENT_LOAD)
+            .map(|raw| Segment {
+                flags: u32_at(raw, 4),
+                offset: u64_at(raw, 8),
+                address: u64_at(raw, 16),
+                file_size: u64_at(raw, 32),
+                memory_size: u64_at(raw, 40),
+            })
+    }
+}
+
+// A range of the file mapped at `address`; memory past `file_size` is zero-filled
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub address: u64,
+    pub memory_size: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    pub flags: u32,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,58 @@
+use crate::superblock::{u16_at, u32_at};
+use alloc::{string::String, vec::Vec};
+use vfs::{Error, FileType, Result};
+
+const ENTRY_HEADER_SIZE: usize = 8;
+
+pub struct Entry {
+    pub inode: u32,
+    pub name: String,
+    // Only filled in when the volume stores types in directory entries
+    pub file_type: Option<FileType>,
+}
+
+// Entries never cross a block boundary, so every block is walked on its own
+pub fn parse_entries(data: &[u8], block_size: usize, has_file_type: bool) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for block in data.chunks(block_size) {
+        let mut offset = 0;
+        while offset + ENTRY_HEADER_SIZE <= block.len() {
+            let inode = u32_at(block, offset);
+            let record_len = u16_at(block, offset + 4) as usize;
+            let (name_len, type_byte) = match has_file_type {
+                true => (block[offset + 6] as usize, block[offset + 7]),
+                false => (u16_at(block, offset + 6) as usize, 0),
+            };
+
+            if record_len < ENTRY_HEADER_SIZE
+                || offset + record_len > block.len()
+                || ENTRY_HEADER_SIZE + name_len > record_len
+            {
+                return Err(Error::Io);
+            }
+
+            if inode != 0 {
+                let name =
+                    &block[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name_len];
+                entries.push(Entry {
+                    inode,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    file_type: has_file_type.then(|| entry_file_type(type_byte)).flatten(),
+                });
+            }
+            offset += record_len;
+        }
+    }
+    Ok(entries)
+}
+
+fn entry_file_type(type_byte: u8) -> Option<FileType> {
+    match type_byte {
+        1 => Some(FileType::File),
+        2 => Some(FileType::Directory),
+        3 => Some(FileType::CharDevice),
+        4 => Some(FileType::BlockDevice),
+        7 => Some(FileType::Symlink),
+        _ => None,
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{
+    Volume,
+    dir::{self, Entry},
+    superblock::{u16_at, u32_at},
+};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use custom_types::fallible::try_zeroed;
+use vfs::{DirEntry, Error, FileOps, FileType, Inode, Metadata, Result};
+
+const DIRECT_BLOCKS: u64 = 12;
+const INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+const BLOCK_POINTERS: usize = 15;
+// Short symlink targets are stored in place of the block pointers
+const FAST_SYMLINK_SIZE: usize = BLOCK_POINTERS * 4;
+
+// The on-disk inode fields the driver uses
+#[derive(Debug, Clone)]
+struct RawInode {
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    atime: u64,
+    ctime: u64,
+    mtime: u64,
+    sectors: u32,
+    file_acl: u32,
+    block: [u32; BLOCK_POINTERS],
+    inline: [u8; FAST_SYMLINK_SIZE],
+}
+
+impl RawInode {
+    fn parse(data: &[u8]) -> Self {
+        let mode = u16_at(data, 0);
+        // The high half of the size is only meaningful for regular files
+        let size_high = match file_type(mode) {
+            Some(FileType::File) => u32_at(data, 108) as u64,
+            _ => 0,
+        };
+
+        RawInode {
+            mode,
+            uid: u16_at(data, 2) as u32 | (u16_at(data, 120) as u32) << 16,
+            gid: u16_at(data, 24) as u32 | (u16_at(data, 122) as u32) << 16,
+            size: u32_at(data, 4) as u64 | size_high << 32,
+            atime: u32_at(data, 8) as u64,
+            ctime: u32_at(data, 12) as u64,
+            mtime: u32_at(data, 16) as u64,
+            sectors: u32_at(data, 28),
+            file_acl: u32_at(data, 104),
+            block: core::array::from_fn(|index| u32_at(data, 40 + index * 4)),
+            inline: data[40..40 + FAST_SYMLINK_SIZE].try_into().unwrap(),
+        }
+    }
+}
+
+fn file_type(mode: u16) -> Option<FileType> {
+    match mode & 0xF000 {
+        0x8000 => Some(FileType::File),
+        0x4000 => Some(FileType::Directory),
+        0xA000 => Some(FileType::Symlink),
+        0x2000 => Some(FileType::CharDevice),
+        0x6000 => Some(FileType::BlockDevice),
+        // FIFOs and sockets have no counterpart in the VFS
+        _ => None,
+    }
+}
+
+impl Volume {
+    fn read_inode(&self, number: u32) -> Result<RawInode> {
+        let superblock = &self.superblock;
+        if number == 0 || number > superblock.inodes_count {
+            return Err(Error::Io);
+        }
+
+        let index = number - 1;
+        let group = self
+            .groups
+            .get((index / superblock.inodes_per_group) as usize)
+            .ok_or(Error::Io)?;
+        let offset = group.inode_table as u64 * self.block_size() as u64
+            + (index % superblock.inodes_per_group) as u64 * superblock.inode_size as u64;
+
+        let mut data = [0u8; 128];
+        self.read_bytes(offset, &mut data)?;
+        Ok(RawInode::parse(&data))
+    }
+
+    // Maps a block of the file to a block on disk, 0 meaning a hole
+    fn map_block(&self, inode: &RawInode, index: u64) -> Result<u32> {
+        let per_block = (self.block_size() / 4) as u64;
+        let (mut block, mut index, depth) = if index < DIRECT_BLOCKS {
+            return Ok(inode.block[index as usize]);
+        } else if index - DIRECT_BLOCKS < per_block {
+            (inode.block[INDIRECT], index - DIRECT_BLOCKS, 1)
+        } else if index - DIRECT_BLOCKS - per_block < per_block.pow(2) {
+            (
+                inode.block[DOUBLE_INDIRECT],
+                index - DIRECT_BLOCKS - per_block,
+                2,
+            )
+        } else {
+            let index = index - DIRECT_BLOCKS - per_block - per_block.pow(2);
+            if index >= per_block.pow(3) {
+                return Err(Error::InvalidArgument);
+            }
+            (inode.block[TRIPLE_INDIRECT], index, 3)
+        };
+
+        for level in (0..depth).rev() {
+            if block == 0 {
+                return Ok(0);
+            }
+            let span = per_block.pow(level);
+            let mut pointer = [0u8; 4];
+            let offset = block as u64 * self.block_size() as u64 + (index / span) * 4;
+            self.read_bytes(offset, &mut pointer)?;
+            block = u32::from_le_bytes(pointer);
+            index %= span;
+        }
+        Ok(block)
+    }
+
+    fn read_data(&self, inode: &RawInode, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size() as u64;
+        let mut data = vec![0u8; block_size as usize];
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let start = (position % block_size) as usize;
+            let len = (buf.len() - done).min(block_size as usize - start);
+            let target = &mut buf[done..done + len];
+
+            match self.map_block(inode, position / block_size)? {
+                0 => target.fill(0),
+                block => {
+                    self.read_block(block, &mut data)?;
+                    target.copy_from_slice(&data[start..start + len]);
+                }
+            }
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+pub struct Ext2Inode {
+    volume: Arc<Volume>,
+    number: u32,
+    raw: RawInode,
+    file_type: FileType,
+}
+
+impl Ext2Inode {
+    pub(crate) fn load(volume: &Arc<Volume>, number: u32) -> Result<Arc<Self>> {
+        let raw = volume.read_inode(number)?;
+        let file_type = file_type(raw.mode).ok_or(Error::Unsupported)?;
+        Ok(Arc::new(Ext2Inode {
+            volume: volume.clone(),
+            number,
+            raw,
+            file_type,
+        }))
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>> {
+        if self.file_type != FileType::Directory {
+            return Err(Error::NotADirectory);
+        }
+        let data = self.read_whole()?;
+        let superblock = &self.volume.superblock;
+        dir::parse_entries(&data, superblock.block_size, superblock.has_file_type())
+    }
+
+    // Reads metadata kept in data blocks, like directories and long symlink targets. The size
+    // comes from the image, so it has to fit in the blocks the inode holds and on the volume
+    fn read_whole(&self) -> Result<Vec<u8>> {
+        let superblock = &self.volume.superblock;
+        let volume_size = superblock.blocks_count as u64 * superblock.block_size as u64;
+        let size = self.raw.size;
+        if size > self.raw.sectors as u64 * 512 || size > volume_size {
+            return Err(Error::Io);
+        }
+        let mut data = try_zeroed(size as usize).map_err(|_| Error::OutOfMemory)?;
+        self.volume.read_data(&self.raw, 0, &mut data)?;
+        Ok(data)
+    }
+
+    // A symlink without data blocks of its own keeps the target inline
+    fn is_fast_symlink(&self) -> bool {
+        let acl_sectors = match self.raw.file_acl {
+            0 => 0,
+            _ => (self.volume.block_size() / 512) as u32,
+        };
+        self.raw.sectors == acl_sectors
+    }
+}
+
+impl FileOps for Ext2Inode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        match self.file_type {
+            FileType::Directory => return Err(Error::IsADirectory),
+            FileType::File => {}
+            _ => return Err(Error::InvalidArgument),
+        }
+        if offset >= self.raw.size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((self.raw.size - offset) as usize);
+        self.volume.read_data(&self.raw, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        let raw = &self.raw;
+        Metadata {
+            inode: self.number as u64,
+            file_type: self.file_type,
+            size: raw.size,
+            mode: raw.mode as u32 & 0o7777,
+            uid: raw.uid,
+            gid: raw.gid,
+            atime: raw.atime,
+            mtime: raw.mtime,
+            ctime: raw.ctime,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let entry = self
+            .entries()?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or(Error::NotFound)?;
+        Ok(Ext2Inode::load(&self.volume, entry.inode)?)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for entry in self.entries()? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            // Without types in the entries the inode itself has to be read
+            let file_type = match entry.file_type {
+                Some(file_type) => Some(file_type),
+                None => file_type(self.volume.read_inode(entry.inode)?.mode),
+            };
+            if let Some(file_type) = file_type {
+                entries.push(DirEntry {
+                    name: entry.name,
+                    inode: entry.inode as u64,
+                    file_type,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_link(&self) -> Result<String> {
+        if self.file_type != FileType::Symlink {
+            return Err(Error::InvalidArgument);
+        }
+
+        let target = if self.is_fast_symlink() {
+            let size = self.raw.size as usize;
+            self.raw.inline.get(..size).ok_or(Error::Io)?.to_vec()
+        } else {
+            self.read_whole()?
+        };
+        String::from_utf8(target).map_err(|_| Error::InvalidArgument)
+    }
+}
@@ -0,0 +1,106 @@
+#![no_std]
+extern crate alloc;
+
+mod dir;
+mod inode;
+mod superblock;
+
+pub use inode::Ext2Inode;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use block::BlockDevice;
+use custom_types::fallible::try_zeroed;
+use superblock::{
+    GROUP_DESCRIPTOR_SIZE, GroupDescriptor, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE, Superblock,
+};
+use vfs::{Error, Filesystem, Inode, Result};
+
+const ROOT_INODE: u32 = 2;
+
+// Nothing on the volume changes while it is mounted, so it is shared without a lock
+pub struct Volume {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    groups: Vec<GroupDescriptor>,
+}
+
+impl Volume {
+    pub(crate) fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    pub(crate) fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<()> {
+        self.read_bytes(block as u64 * self.block_size() as u64, buf)
+    }
+
+    pub(crate) fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        read_bytes(self.device.as_ref(), offset, buf)
+    }
+}
+
+// Reads an arbitrary byte range, going through whole device blocks
+fn read_bytes(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let device_block = device.block_size() as u64;
+    let first = offset / device_block;
+    if offset.is_multiple_of(device_block) && (buf.len() as u64).is_multiple_of(device_block) {
+        return device.read_blocks(first, buf).map_err(|_| Error::Io);
+    }
+
+    let end = (offset + buf.len() as u64).div_ceil(device_block);
+    let mut data = vec![0u8; ((end - first) * device_block) as usize];
+    device
+        .read_blocks(first, &mut data)
+        .map_err(|_| Error::Io)?;
+    let start = (offset - first * device_block) as usize;
+    buf.copy_from_slice(&data[start..start + buf.len()]);
+    Ok(())
+}
+
+pub struct Ext2Fs {
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2Fs {
+    pub fn mount(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let mut data = vec![0u8; SUPERBLOCK_SIZE];
+        read_bytes(device.as_ref(), SUPERBLOCK_OFFSET, &mut data)?;
+        let superblock = Superblock::parse(&data)?;
+
+        let block_size = superblock.block_size as u64;
+        if !block_size.is_multiple_of(device.block_size() as u64)
+            || superblock.blocks_count as u64 * block_size > device.size()
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let group_count = superblock.group_count() as usize;
+        // The image picks the group count, so a corrupt one must not abort the kernel
+        let mut table =
+            try_zeroed(group_count * GROUP_DESCRIPTOR_SIZE).map_err(|_| Error::OutOfMemory)?;
+        let table_offset = superblock.group_table_block() as u64 * block_size;
+        read_bytes(device.as_ref(), table_offset, &mut table)?;
+        let groups = table
+            .chunks_exact(GROUP_DESCRIPTOR_SIZE)
+            .map(GroupDescriptor::parse)
+            .collect();
+
+        let volume = Arc::new(Volume {
+            device,
+            superblock,
+            groups,
+        });
+        Ok(Ext2Fs {
+            root: Ext2Inode::load(&volume, ROOT_INODE)?,
+        })
+    }
+}
+
+impl Filesystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
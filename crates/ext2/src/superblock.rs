@@ -0,0 +1,102 @@
+use vfs::{Error, Result};
+
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+const MAGIC: u16 = 0xEF53;
+const GOOD_OLD_REV: u32 = 0;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+// Incompatible features we understand; anything else changes the on-disk layout
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+
+#[derive(Debug, Clone)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub first_data_block: u32,
+    pub block_size: usize,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub inode_size: usize,
+    pub feature_incompat: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GroupDescriptor {
+    pub inode_table: u32,
+}
+
+pub fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+impl Superblock {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if u16_at(data, 56) != MAGIC {
+            return Err(Error::InvalidArgument);
+        }
+
+        let log_block_size = u32_at(data, 24);
+        if log_block_size > 6 {
+            return Err(Error::InvalidArgument);
+        }
+        let block_size = 1024 << log_block_size;
+
+        let (inode_size, feature_incompat) = match u32_at(data, 76) {
+            GOOD_OLD_REV => (GOOD_OLD_INODE_SIZE, 0),
+            _ => (u16_at(data, 88) as usize, u32_at(data, 96)),
+        };
+        if feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(Error::Unsupported);
+        }
+
+        let superblock = Superblock {
+            inodes_count: u32_at(data, 0),
+            blocks_count: u32_at(data, 4),
+            first_data_block: u32_at(data, 20),
+            block_size,
+            blocks_per_group: u32_at(data, 32),
+            inodes_per_group: u32_at(data, 40),
+            inode_size,
+            feature_incompat,
+        };
+        if superblock.blocks_per_group == 0
+            || superblock.inodes_per_group == 0
+            || !superblock.inode_size.is_power_of_two()
+            || superblock.inode_size < GOOD_OLD_INODE_SIZE
+            || superblock.inode_size > block_size
+            || superblock.first_data_block >= superblock.blocks_count
+        {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(superblock)
+    }
+
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    // The descriptor table starts in the block right after the superblock
+    pub fn group_table_block(&self) -> u32 {
+        self.first_data_block + 1
+    }
+
+    pub fn has_file_type(&self) -> bool {
+        self.feature_incompat & INCOMPAT_FILETYPE != 0
+    }
+}
+
+impl GroupDescriptor {
+    pub fn parse(data: &[u8]) -> Self {
+        GroupDescriptor {
+            inode_table: u32_at(data, 8),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use vfs::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+// BIOS parameter block together with the layout values derived from it
+#[derive(Debug, Clone)]
+pub struct Bpb {
+    pub fat_type: FatType,
+    pub bytes_per_sector: usize,
+    pub sectors_per_cluster: usize,
+    pub reserved_sectors: u64,
+    pub fat_count: u64,
+    pub total_sectors: u64,
+    pub fat_size: u64,
+    pub root_cluster: u32,
+    pub fs_info_sector: u64,
+    pub cluster_count: u32,
+    pub root_dir_start: u64,
+    pub root_dir_sectors: u64,
+    pub data_start: u64,
+}
+
+fn u16_at(sector: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([sector[offset], sector[offset + 1]])
+}
+
+fn u32_at(sector: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap())
+}
+
+impl Bpb {
+    pub fn parse(sector: &[u8]) -> Result<Self> {
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err(Error::InvalidArgument);
+        }
+
+        let bytes_per_sector = u16_at(sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as usize;
+        let reserved_sectors = u16_at(sector, 14) as u64;
+        let fat_count = sector[16] as u64;
+        let root_entry_count = u16_at(sector, 17) as usize;
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32) as u64,
+            count => count as u64,
+        };
+        let fat_size = match u16_at(sector, 22) {
+            0 => u32_at(sector, 36) as u64,
+            size => size as u64,
+        };
+
+        let root_dir_start = reserved_sectors + fat_count * fat_size;
+        let root_dir_sectors = (root_entry_count * 32).div_ceil(bytes_per_sector) as u64;
+        let data_start = root_dir_start + root_dir_sectors;
+        if data_start >= total_sectors {
+            return Err(Error::InvalidArgument);
+        }
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster as u64) as u32;
+
+        // The FAT type is determined by the cluster count alone
+        let fat_type = match cluster_count {
+            0..4085 => FatType::Fat12,
+            4085..65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        let (root_cluster, fs_info_sector) = match fat_type {
+            FatType::Fat32 => (u32_at(sector, 44), u16_at(sector, 48) as u64),
+            _ => (0, 0),
+        };
+
+        Ok(Bpb {
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            total_sectors,
+            fat_size,
+            root_cluster,
+            fs_info_sector,
+            cluster_count,
+            root_dir_start,
+            root_dir_sectors,
+            data_start,
+        })
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    pub fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster as u64
+    }
+
+    pub fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+}
@@ -0,0 +1,609 @@
+use crate::{Volume, bpb::FatType};
+use alloc::{string::String, vec, vec::Vec};
+use datetime::DateTime;
+use vfs::{Error, Result};
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+const END_OF_DIRECTORY: u8 = 0x00;
+const DELETED: u8 = 0xE5;
+const LAST_LONG_ENTRY: u8 = 0x40;
+const CHARS_PER_LONG_ENTRY: usize = 13;
+const DOT_DOT: [u8; 11] = *b"..         ";
+const LONG_NAME_OFFSETS: [usize; CHARS_PER_LONG_ENTRY] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// NT reserved byte flags used by Windows and Linux for all-lowercase short names
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXTENSION: u8 = 0x10;
+
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+const INVALID_SHORT_CHARS: &[u8] = b"\"*+,./:;<=>?[\\]| ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirRef {
+    FixedRoot,
+    Chain(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub short_name: [u8; 11],
+    pub attr: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+    pub ctime: u64,
+    pub mtime: u64,
+    pub atime: u64,
+    // Index of the short entry and the number of slots including long name entries
+    pub slot: usize,
+    pub slot_count: usize,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+// A directory loaded into memory, with the sector backing every part of it
+pub struct DirBuf {
+    pub dir: DirRef,
+    pub data: Vec<u8>,
+    sectors: Vec<u64>,
+    clusters: Vec<u32>,
+}
+
+impl DirBuf {
+    pub fn entries(&self) -> Vec<Entry> {
+        parse_entries(&self.data)
+    }
+
+    pub fn find(&self, name: &str) -> Option<Entry> {
+        self.entries()
+            .into_iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn location(&self, slot: usize, bytes_per_sector: usize) -> (u64, usize) {
+        let offset = slot * ENTRY_SIZE;
+        (
+            self.sectors[offset / bytes_per_sector],
+            offset % bytes_per_sector,
+        )
+    }
+
+    // Only "." and ".." remain
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    fn slot_count(&self) -> usize {
+        self.data.len() / ENTRY_SIZE
+    }
+
+    fn is_free(&self, slot: usize) -> bool {
+        matches!(self.data[slot * ENTRY_SIZE], END_OF_DIRECTORY | DELETED)
+    }
+}
+
+impl Volume {
+    pub(crate) fn load_dir(&self, dir: DirRef) -> Result<DirBuf> {
+        let bps = self.bpb.bytes_per_sector;
+        let (sectors, clusters): (Vec<u64>, Vec<u32>) = match dir {
+            DirRef::FixedRoot => {
+                let start = self.bpb.root_dir_start;
+                (
+                    (start..start + self.bpb.root_dir_sectors).collect(),
+                    Vec::new(),
+                )
+            }
+            DirRef::Chain(first) => {
+                let clusters = self.chain(first)?;
+                let sectors = clusters
+                    .iter()
+                    .flat_map(|&cluster| {
+                        let start = self.bpb.cluster_sector(cluster);
+                        start..start + self.bpb.sectors_per_cluster as u64
+                    })
+                    .collect();
+                (sectors, clusters)
+            }
+        };
+
+        let mut data = vec![0u8; sectors.len() * bps];
+        for (sector, chunk) in sectors.iter().zip(data.chunks_exact_mut(bps)) {
+            self.read_sectors(*sector, chunk)?;
+        }
+
+        Ok(DirBuf {
+            dir,
+            data,
+            sectors,
+            clusters,
+        })
+    }
+
+    fn store_slots(&self, buf: &DirBuf, first: usize, count: usize) -> Result<()> {
+        let bps = self.bpb.bytes_per_sector;
+        let first_sector = first * ENTRY_SIZE / bps;
+        let last_sector = ((first + count) * ENTRY_SIZE - 1) / bps;
+        for index in first_sector..=last_sector {
+            self.write_sectors(
+                buf.sectors[index],
+                &buf.data[index * bps..(index + 1) * bps],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn grow_dir(&mut self, buf: &mut DirBuf) -> Result<()> {
+        let DirRef::Chain(_) = buf.dir else {
+            // The FAT12/16 root directory has a fixed size
+            return Err(Error::NoSpace);
+        };
+
+        let cluster = self.allocate_cluster(buf.clusters.last().copied())?;
+        self.zero_cluster(cluster)?;
+
+        let start = self.bpb.cluster_sector(cluster);
+        buf.clusters.push(cluster);
+        buf.sectors
+            .extend(start..start + self.bpb.sectors_per_cluster as u64);
+        buf.data.resize(buf.data.len() + self.bpb.cluster_size(), 0);
+        Ok(())
+    }
+
+    pub(crate) fn zero_cluster(&self, cluster: u32) -> Result<()> {
+        let zeroes = vec![0u8; self.bpb.cluster_size()];
+        self.write_sectors(self.bpb.cluster_sector(cluster), &zeroes)
+    }
+
+    // Adds an entry to a directory and returns the location of its short entry. The name may
+    // still belong to the entry being replaced, which the caller removes afterwards
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn insert_entry(
+        &mut self,
+        dir: DirRef,
+        name: &str,
+        attr: u8,
+        first_cluster: u32,
+        size: u32,
+        times: (u64, u64),
+        replacing: Option<&Entry>,
+    ) -> Result<(u64, usize)> {
+        validate_name(name)?;
+        let mut buf = self.load_dir(dir)?;
+        let entries = buf.entries();
+        if entries.iter().any(|entry| {
+            entry.name.eq_ignore_ascii_case(name)
+                && replacing.is_none_or(|replaced| replaced.slot != entry.slot)
+        }) {
+            return Err(Error::AlreadyExists);
+        }
+
+        let (short_name, case_flags, needs_long_name) = short_name(name, |candidate| {
+            entries.iter().any(|entry| &entry.short_name == candidate)
+        });
+
+        let mut slots = Vec::new();
+        if needs_long_name {
+            slots.extend(long_entries(name, checksum(&short_name)));
+        }
+        let (ctime, mtime) = times;
+        slots.push(short_entry(
+            &short_name,
+            attr,
+            case_flags,
+            first_cluster,
+            size,
+            ctime,
+            mtime,
+        ));
+
+        let first = loop {
+            if let Some(first) = find_free_run(&buf, slots.len()) {
+                break first;
+            }
+            self.grow_dir(&mut buf)?;
+        };
+
+        for (i, slot) in slots.iter().enumerate() {
+            let offset = (first + i) * ENTRY_SIZE;
+            buf.data[offset..offset + ENTRY_SIZE].copy_from_slice(slot);
+        }
+        self.store_slots(&buf, first, slots.len())?;
+        Ok(buf.location(first + slots.len() - 1, self.bpb.bytes_per_sector))
+    }
+
+    pub(crate) fn remove_entry(&self, dir: DirRef, entry: &Entry) -> Result<()> {
+        let mut buf = self.load_dir(dir)?;
+        let first = entry.slot + 1 - entry.slot_count;
+        for slot in first..=entry.slot {
+            buf.data[slot * ENTRY_SIZE] = DELETED;
+        }
+        self.store_slots(&buf, first, entry.slot_count)
+    }
+
+    pub(crate) fn write_dot_entries(
+        &self,
+        cluster: u32,
+        parent_cluster: u32,
+        time: u64,
+    ) -> Result<()> {
+        let mut data = vec![0u8; self.bpb.bytes_per_sector];
+        let dot = *b".          ";
+        let parent_cluster = self.dot_dot_cluster(parent_cluster);
+        data[..ENTRY_SIZE].copy_from_slice(&short_entry(
+            &dot,
+            ATTR_DIRECTORY,
+            0,
+            cluster,
+            0,
+            time,
+            time,
+        ));
+        data[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&short_entry(
+            &DOT_DOT,
+            ATTR_DIRECTORY,
+            0,
+            parent_cluster,
+            0,
+            time,
+            time,
+        ));
+        self.write_sectors(self.bpb.cluster_sector(cluster), &data)
+    }
+
+    // Points ".." of a moved directory at its new parent
+    pub(crate) fn set_dot_dot(&self, cluster: u32, parent_cluster: u32) -> Result<()> {
+        let sector = self.bpb.cluster_sector(cluster);
+        let mut data = self.read_sector(sector)?;
+        // Some writers put long name slots in front of the dot entries, so ".." is searched for
+        let Some(raw) = data
+            .chunks_exact_mut(ENTRY_SIZE)
+            .find(|raw| raw[..11] == DOT_DOT && raw[11] & ATTR_DIRECTORY != 0)
+        else {
+            return Ok(());
+        };
+        let parent_cluster = self.dot_dot_cluster(parent_cluster);
+        raw[20..22].copy_from_slice(&((parent_cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(parent_cluster as u16).to_le_bytes());
+        self.write_sectors(sector, &data)
+    }
+
+    // ".." pointing at the root directory is always stored as cluster 0
+    fn dot_dot_cluster(&self, parent_cluster: u32) -> u32 {
+        match self.bpb.fat_type {
+            FatType::Fat32 if parent_cluster == self.bpb.root_cluster => 0,
+            _ => parent_cluster,
+        }
+    }
+}
+
+fn find_free_run(buf: &DirBuf, len: usize) -> Option<usize> {
+    let mut run = 0;
+    for slot in 0..buf.slot_count() {
+        if buf.is_free(slot) {
+            run += 1;
+            if run == len {
+                return Some(slot + 1 - len);
+            }
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+pub fn parse_entries(data: &[u8]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut long_name: Vec<u16> = Vec::new();
+    let mut long_checksum = None;
+    let mut long_slots = 0;
+
+    for (slot, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        match raw[0] {
+            END_OF_DIRECTORY => break,
+            DELETED => {
+                long_name.clear();
+                long_slots = 0;
+                continue;
+            }
+            _ => {}
+        }
+
+        let attr = raw[11];
+        if attr & 0x3F == ATTR_LONG_NAME {
+            // Long entries are stored in reverse order, the last one comes first
+            if raw[0] & LAST_LONG_ENTRY != 0 {
+                long_name.clear();
+                long_slots = 0;
+                long_checksum = Some(raw[13]);
+            }
+            let sequence = (raw[0] & 0x1F) as usize;
+            if sequence == 0 {
+                continue;
+            }
+            let start = (sequence - 1) * CHARS_PER_LONG_ENTRY;
+            if long_name.len() < start + CHARS_PER_LONG_ENTRY {
+                long_name.resize(start + CHARS_PER_LONG_ENTRY, 0xFFFF);
+            }
+            for (i, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                long_name[start + i] = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+            }
+            long_slots += 1;
+            continue;
+        }
+
+        let mut short_name = [0u8; 11];
+        short_name.copy_from_slice(&raw[..11]);
+        let valid_long_name = long_slots > 0 && long_checksum == Some(checksum(&short_name));
+
+        if attr & ATTR_VOLUME_ID == 0 && short_name[0] != b'.' {
+            let name = if valid_long_name {
+                decode_long_name(&long_name)
+            } else {
+                decode_short_name(&short_name, raw[12])
+            };
+
+            let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+            let u32_at =
+                |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+            entries.push(Entry {
+                name,
+                short_name,
+                attr,
+                first_cluster: (u16_at(20) as u32) << 16 | u16_at(26) as u32,
+                size: u32_at(28),
+                ctime: unix_time(u16_at(16), u16_at(14)),
+                mtime: unix_time(u16_at(24), u16_at(22)),
+                atime: unix_time(u16_at(18), 0),
+                slot,
+                slot_count: if valid_long_name { long_slots + 1 } else { 1 },
+            });
+        }
+
+        long_name.clear();
+        long_slots = 0;
+        long_checksum = None;
+    }
+
+    entries
+}
+
+fn decode_long_name(chars: &[u16]) -> String {
+    let len = chars
+        .iter()
+        .position(|&c| c == 0 || c == 0xFFFF)
+        .unwrap_or(chars.len());
+    char::decode_utf16(chars[..len].iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn decode_short_name(short_name: &[u8; 11], case_flags: u8) -> String {
+    let decode = |bytes: &[u8], lowercase: bool| -> String {
+        bytes
+            .iter()
+            .take_while(|&&byte| byte != b' ')
+            .enumerate()
+            // 0x05 stands in for a leading 0xE5, which would mark the entry as deleted
+            .map(|(i, &byte)| if i == 0 && byte == 0x05 { 0xE5 } else { byte })
+            .map(|byte| if lowercase { byte.to_ascii_lowercase() } else { byte } as char)
+            .collect()
+    };
+
+    let mut name = decode(&short_name[..8], case_flags & LOWERCASE_BASE != 0);
+    let extension = decode(&short_name[8..], case_flags & LOWERCASE_EXTENSION != 0);
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(&extension);
+    }
+    name
+}
+
+pub fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Error::InvalidArgument);
+    }
+    if name.encode_utf16().count() > 255 {
+        return Err(Error::NameTooLong);
+    }
+    if name.chars().any(|c| c < ' ' || INVALID_CHARS.contains(&c)) {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
+// Returns the 8.3 name, the case flags for it, and whether long name entries are needed
+fn short_name(name: &str, exists: impl Fn(&[u8; 11]) -> bool) -> ([u8; 11], u8, bool) {
+    let (base, extension) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+        _ => (name, ""),
+    };
+
+    let is_short_char = |byte: &u8| byte.is_ascii_graphic() && !INVALID_SHORT_CHARS.contains(byte);
+    let fits = (1..=8).contains(&base.len())
+        && extension.len() <= 3
+        && base.bytes().all(|byte| is_short_char(&byte))
+        && extension.bytes().all(|byte| is_short_char(&byte));
+
+    // A part can be stored in the short entry as is when it is entirely upper or lower case
+    let case_flag = |part: &str, flag: u8| -> Option<u8> {
+        match (
+            part.bytes().any(|byte| byte.is_ascii_lowercase()),
+            part.bytes().any(|byte| byte.is_ascii_uppercase()),
+        ) {
+            (true, true) => None,
+            (true, false) => Some(flag),
+            _ => Some(0),
+        }
+    };
+
+    if fits {
+        let mut short_name = [b' '; 11];
+        pack(&mut short_name[..8], base.bytes());
+        pack(&mut short_name[8..], extension.bytes());
+        if let (Some(base_flag), Some(extension_flag)) = (
+            case_flag(base, LOWERCASE_BASE),
+            case_flag(extension, LOWERCASE_EXTENSION),
+        ) && !exists(&short_name)
+        {
+            return (short_name, base_flag | extension_flag, false);
+        }
+    }
+
+    // Generate a unique "BASIS~N.EXT" alias and keep the real name in long entries
+    let to_short = |byte: u8| match byte {
+        byte if is_short_char(&byte) => byte.to_ascii_uppercase(),
+        _ => b'_',
+    };
+    let basis: Vec<u8> = base
+        .bytes()
+        .filter(|&byte| byte != b' ' && byte != b'.')
+        .map(to_short)
+        .collect();
+    let extension: Vec<u8> = extension
+        .bytes()
+        .filter(|&byte| byte != b' ')
+        .map(to_short)
+        .take(3)
+        .collect();
+
+    let mut short_name = [b' '; 11];
+    for number in 1..1_000_000u32 {
+        let mut digits = [0u8; 7];
+        let mut len = 0;
+        let mut n = number;
+        while n > 0 {
+            digits[len] = b'0' + (n % 10) as u8;
+            n /= 10;
+            len += 1;
+        }
+
+        let keep = basis.len().min(8 - len - 1);
+        short_name = [b' '; 11];
+        short_name[..keep].copy_from_slice(&basis[..keep]);
+        short_name[keep] = b'~';
+        for i in 0..len {
+            short_name[keep + 1 + i] = digits[len - 1 - i];
+        }
+        pack(&mut short_name[8..], extension.iter().copied());
+
+        if !exists(&short_name) {
+            break;
+        }
+    }
+    (short_name, 0, true)
+}
+
+fn pack(field: &mut [u8], bytes: impl Iterator<Item = u8>) {
+    for (slot, byte) in field.iter_mut().zip(bytes) {
+        *slot = byte.to_ascii_uppercase();
+    }
+    if field[0] == 0xE5 {
+        field[0] = 0x05;
+    }
+}
+
+fn long_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(CHARS_PER_LONG_ENTRY);
+    // The name is terminated with a NUL when it doesn't fill the last entry, then padded
+    if !chars.len().is_multiple_of(CHARS_PER_LONG_ENTRY) {
+        chars.push(0);
+    }
+    chars.resize(count * CHARS_PER_LONG_ENTRY, 0xFFFF);
+
+    (1..=count)
+        .rev()
+        .map(|sequence| {
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = sequence as u8
+                | if sequence == count {
+                    LAST_LONG_ENTRY
+                } else {
+                    0
+                };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = checksum;
+            let part =
+                &chars[(sequence - 1) * CHARS_PER_LONG_ENTRY..sequence * CHARS_PER_LONG_ENTRY];
+            for (&offset, c) in LONG_NAME_OFFSETS.iter().zip(part) {
+                raw[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
+
+pub fn short_entry(
+    short_name: &[u8; 11],
+    attr: u8,
+    case_flags: u8,
+    first_cluster: u32,
+    size: u32,
+    ctime: u64,
+    mtime: u64,
+) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0u8; ENTRY_SIZE];
+    raw[..11].copy_from_slice(short_name);
+    raw[11] = attr;
+    raw[12] = case_flags;
+    let (create_date, create_time) = fat_time(ctime);
+    raw[14..16].copy_from_slice(&create_time.to_le_bytes());
+    raw[16..18].copy_from_slice(&create_date.to_le_bytes());
+    update_entry(&mut raw, attr, first_cluster, size, mtime);
+    raw
+}
+
+// Rewrites the mutable fields of a short entry in place
+pub fn update_entry(raw: &mut [u8], attr: u8, first_cluster: u32, size: u32, mtime: u64) {
+    let (date, time) = fat_time(mtime);
+    raw[11] = attr;
+    raw[18..20].copy_from_slice(&date.to_le_bytes());
+    raw[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+    raw[22..24].copy_from_slice(&time.to_le_bytes());
+    raw[24..26].copy_from_slice(&date.to_le_bytes());
+    raw[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+pub fn fat_time(timestamp: u64) -> (u16, u16) {
+    let time = DateTime::from_timestamp(timestamp);
+    if time.year < 1980 {
+        return (0x21, 0);
+    }
+    let date = (time.year - 1980) << 9 | (time.month as u16) << 5 | time.day as u16;
+    let time = (time.hours as u16) << 11 | (time.minutes as u16) << 5 | ((time.seconds as u16) / 2);
+    (date, time)
+}
+
+pub fn unix_time(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    DateTime {
+        day: (date & 0x1F).max(1) as u8,
+        month: ((date >> 5) & 0x0F).clamp(1, 12) as u8,
+        year: 1980 + (date >> 9),
+        hours: (time >> 11) as u8,
+        minutes: ((time >> 5) & 0x3F) as u8,
+        seconds: ((time & 0x1F) * 2) as u8,
+    }
+    .timestamp()
+}
@@ -0,0 +1,530 @@
+use crate::{
+    Volume,
+    bpb::FatType,
+    dir::{self, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DirRef, ENTRY_SIZE, Entry},
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::any::Any;
+use custom_types::spin_lock::{Guard, SpinLock};
+use datetime::CURRENT_TIME;
+use vfs::{DirEntry, Error, FileOps, FileType, Inode, Metadata, Result};
+
+const ROOT_INODE: u64 = 1;
+// FAT stores sizes in 32 bits
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+struct State {
+    attr: u8,
+    first_cluster: u32,
+    size: u32,
+    ctime: u64,
+    mtime: u64,
+    atime: u64,
+    // Location of the short directory entry, the root directory has none
+    location: Option<(u64, usize)>,
+    clusters: Option<Vec<u32>>,
+    deleted: bool,
+}
+
+impl State {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+pub struct FatInode {
+    volume: Arc<SpinLock<Volume>>,
+    number: u64,
+    // Taken after the volume lock whenever both are held
+    state: SpinLock<State>,
+}
+
+fn now() -> u64 {
+    CURRENT_TIME.lock().timestamp()
+}
+
+fn inode_number(volume: &Volume, (sector, offset): (u64, usize)) -> u64 {
+    sector * (volume.bpb.bytes_per_sector / ENTRY_SIZE) as u64 + (offset / ENTRY_SIZE) as u64
+}
+
+impl FatInode {
+    pub(crate) fn root(volume: &Arc<SpinLock<Volume>>, root_cluster: u32) -> Arc<Self> {
+        Arc::new(FatInode {
+            volume: volume.clone(),
+            number: ROOT_INODE,
+            state: SpinLock::new(State {
+                attr: ATTR_DIRECTORY,
+                first_cluster: root_cluster,
+                size: 0,
+                ctime: 0,
+                mtime: 0,
+                atime: 0,
+                location: None,
+                clusters: None,
+                deleted: false,
+            }),
+        })
+    }
+
+    // Returns the cached inode for a directory entry or creates a new one
+    fn get(
+        handle: &Arc<SpinLock<Volume>>,
+        volume: &mut Volume,
+        entry: &Entry,
+        location: (u64, usize),
+    ) -> Arc<FatInode> {
+        if let Some(inode) = volume
+            .inodes
+            .get(&location)
+            .and_then(|inode| inode.upgrade())
+        {
+            return inode;
+        }
+
+        volume.inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(FatInode {
+            volume: handle.clone(),
+            number: inode_number(volume, location),
+            state: SpinLock::new(State {
+                attr: entry.attr,
+                first_cluster: entry.first_cluster,
+                size: entry.size,
+                ctime: entry.ctime,
+                mtime: entry.mtime,
+                atime: entry.atime,
+                location: Some(location),
+                clusters: None,
+                deleted: false,
+            }),
+        });
+        volume.inodes.insert(location, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn dir_ref(&self, volume: &Volume) -> Result<DirRef> {
+        let state = self.state.lock();
+        if !state.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        if state.deleted {
+            return Err(Error::NotFound);
+        }
+        Ok(match (state.location, volume.bpb.fat_type) {
+            (None, FatType::Fat12 | FatType::Fat16) => DirRef::FixedRoot,
+            _ => DirRef::Chain(state.first_cluster),
+        })
+    }
+
+    fn file_state(&self) -> Result<Guard<'_, State>> {
+        let state = self.state.lock();
+        if state.deleted {
+            return Err(Error::NotFound);
+        }
+        if state.is_dir() {
+            return Err(Error::IsADirectory);
+        }
+        Ok(state)
+    }
+}
+
+impl Volume {
+    fn clusters<'a>(&self, state: &'a mut State) -> Result<&'a mut Vec<u32>> {
+        if state.clusters.is_none() {
+            let chain = match state.first_cluster {
+                0 => Vec::new(),
+                first => self.chain(first)?,
+            };
+            state.clusters = Some(chain);
+        }
+        Ok(state.clusters.as_mut().unwrap())
+    }
+
+    fn resize_chain(&mut self, state: &mut State, count: usize) -> Result<()> {
+        let mut clusters = core::mem::take(self.clusters(state)?);
+
+        while clusters.len() < count {
+            match self.allocate_cluster(clusters.last().copied()) {
+                Ok(cluster) => clusters.push(cluster),
+                Err(error) => {
+                    state.first_cluster = clusters.first().copied().unwrap_or(0);
+                    state.clusters = Some(clusters);
+                    return Err(error);
+                }
+            }
+        }
+
+        if clusters.len() > count {
+            match count {
+                0 => self.free_chain(clusters[0])?,
+                _ => {
+                    self.set_fat_entry(clusters[count - 1], self.bpb.end_of_chain())?;
+                    self.free_chain(clusters[count])?;
+                }
+            }
+            clusters.truncate(count);
+        }
+
+        state.first_cluster = clusters.first().copied().unwrap_or(0);
+        state.clusters = Some(clusters);
+        Ok(())
+    }
+
+    // Maps a byte position within a file to a sector and an offset inside it
+    fn locate(&self, clusters: &[u32], position: u64) -> (u64, usize) {
+        let bps = self.bpb.bytes_per_sector;
+        let cluster_size = self.bpb.cluster_size() as u64;
+        let cluster = clusters[(position / cluster_size) as usize];
+        let within_cluster = (position % cluster_size) as usize;
+        (
+            self.bpb.cluster_sector(cluster) + (within_cluster / bps) as u64,
+            within_cluster % bps,
+        )
+    }
+
+    fn read_data(&self, clusters: &[u32], offset: u64, buf: &mut [u8]) -> Result<()> {
+        let bps = self.bpb.bytes_per_sector;
+        let mut sector_buf = vec![0u8; bps];
+
+        let mut done = 0;
+        while done < buf.len() {
+            let (sector, within) = self.locate(clusters, offset + done as u64);
+            let len = (bps - within).min(buf.len() - done);
+            if len == bps {
+                self.read_sectors(sector, &mut buf[done..done + len])?;
+            } else {
+                self.read_sectors(sector, &mut sector_buf)?;
+                buf[done..done + len].copy_from_slice(&sector_buf[within..within + len]);
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_data(&self, clusters: &[u32], offset: u64, buf: &[u8]) -> Result<()> {
+        let bps = self.bpb.bytes_per_sector;
+        let mut sector_buf = vec![0u8; bps];
+
+        let mut done = 0;
+        while done < buf.len() {
+            let (sector, within) = self.locate(clusters, offset + done as u64);
+            let len = (bps - within).min(buf.len() - done);
+            if len == bps {
+                self.write_sectors(sector, &buf[done..done + len])?;
+            } else {
+                self.read_sectors(sector, &mut sector_buf)?;
+                sector_buf[within..within + len].copy_from_slice(&buf[done..done + len]);
+                self.write_sectors(sector, &sector_buf)?;
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_zeroes(&mut self, state: &mut State, from: u64, to: u64) -> Result<()> {
+        let zeroes = vec![0u8; self.bpb.cluster_size()];
+        let clusters = self.clusters(state)?.clone();
+        let mut position = from;
+        while position < to {
+            let len = (to - position).min(zeroes.len() as u64) as usize;
+            self.write_data(&clusters, position, &zeroes[..len])?;
+            position += len as u64;
+        }
+        Ok(())
+    }
+
+    fn flush_entry(&self, state: &State) -> Result<()> {
+        let Some((sector, offset)) = state.location else {
+            return Ok(());
+        };
+        let mut data = self.read_sector(sector)?;
+        let size = if state.is_dir() { 0 } else { state.size };
+        dir::update_entry(
+            &mut data[offset..offset + ENTRY_SIZE],
+            state.attr,
+            state.first_cluster,
+            size,
+            state.mtime,
+        );
+        self.write_sectors(sector, &data)
+    }
+}
+
+impl FileOps for FatInode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let volume = self.volume.lock();
+        let mut state = self.file_state()?;
+        if offset >= state.size as u64 {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((state.size as u64 - offset) as usize);
+        let clusters = volume.clusters(&mut state)?.clone();
+        volume.read_data(&clusters, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut volume = self.volume.lock();
+        let mut state = self.file_state()?;
+        if state.attr & ATTR_READ_ONLY != 0 {
+            return Err(Error::PermissionDenied);
+        }
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(Error::NoSpace)?;
+
+        let cluster_size = volume.bpb.cluster_size() as u64;
+        let needed = end.div_ceil(cluster_size) as usize;
+        if needed > volume.clusters(&mut state)?.len() {
+            volume.resize_chain(&mut state, needed)?;
+        }
+
+        // Fill a gap left by seeking past the end so stale cluster contents never show up
+        let size = state.size as u64;
+        if offset > size {
+            volume.write_zeroes(&mut state, size, offset)?;
+        }
+
+        let clusters = volume.clusters(&mut state)?.clone();
+        volume.write_data(&clusters, offset, buf)?;
+
+        state.size = state.size.max(end as u32);
+        state.mtime = now();
+        state.attr |= ATTR_ARCHIVE;
+        volume.flush_entry(&state)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        let mut volume = self.volume.lock();
+        let mut state = self.file_state()?;
+        if state.attr & ATTR_READ_ONLY != 0 {
+            return Err(Error::PermissionDenied);
+        }
+        if size > MAX_FILE_SIZE {
+            return Err(Error::NoSpace);
+        }
+
+        let cluster_size = volume.bpb.cluster_size() as u64;
+        volume.resize_chain(&mut state, size.div_ceil(cluster_size) as usize)?;
+        let old_size = state.size as u64;
+        if size > old_size {
+            volume.write_zeroes(&mut state, old_size, size)?;
+        }
+
+        state.size = size as u32;
+        state.mtime = now();
+        volume.flush_entry(&state)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let (file_type, mode) = match state.is_dir() {
+            true => (FileType::Directory, 0o755),
+            false => (FileType::File, 0o644),
+        };
+        let mode = match state.attr & ATTR_READ_ONLY {
+            0 => mode,
+            _ => mode & !0o222,
+        };
+
+        Metadata {
+            inode: self.number,
+            file_type,
+            size: state.size as u64,
+            mode,
+            uid: 0,
+            gid: 0,
+            atime: state.atime,
+            mtime: state.mtime,
+            ctime: state.ctime,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let mut volume = self.volume.lock();
+        let dir = volume.load_dir(self.dir_ref(&volume)?)?;
+        let entry = dir.find(name).ok_or(Error::NotFound)?;
+        let location = dir.location(entry.slot, volume.bpb.bytes_per_sector);
+        Ok(FatInode::get(&self.volume, &mut volume, &entry, location))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let volume = self.volume.lock();
+        let dir = volume.load_dir(self.dir_ref(&volume)?)?;
+        Ok(dir
+            .entries()
+            .into_iter()
+            .map(|entry| DirEntry {
+                inode: inode_number(
+                    &volume,
+                    dir.location(entry.slot, volume.bpb.bytes_per_sector),
+                ),
+                file_type: match entry.is_dir() {
+                    true => FileType::Directory,
+                    false => FileType::File,
+                },
+                name: entry.name,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<Arc<dyn Inode>> {
+        let mut volume = self.volume.lock();
+        let parent = self.dir_ref(&volume)?;
+        let time = now();
+        let read_only = if mode & 0o222 == 0 { ATTR_READ_ONLY } else { 0 };
+
+        let (attr, first_cluster) = match file_type {
+            FileType::File => (ATTR_ARCHIVE | read_only, 0),
+            FileType::Directory => {
+                let cluster = volume.allocate_cluster(None)?;
+                let parent_cluster = match parent {
+                    DirRef::FixedRoot => 0,
+                    DirRef::Chain(cluster) => cluster,
+                };
+                volume
+                    .zero_cluster(cluster)
+                    .and_then(|_| volume.write_dot_entries(cluster, parent_cluster, time))
+                    .inspect_err(|_| {
+                        let _ = volume.free_chain(cluster);
+                    })?;
+                (ATTR_DIRECTORY | read_only, cluster)
+            }
+            _ => return Err(Error::Unsupported),
+        };
+
+        let location =
+            match volume.insert_entry(parent, name, attr, first_cluster, 0, (time, time), None) {
+                Ok(location) => location,
+                Err(error) => {
+                    if first_cluster != 0 {
+                        volume.free_chain(first_cluster)?;
+                    }
+                    return Err(error);
+                }
+            };
+
+        let dir = volume.load_dir(parent)?;
+        let entry = dir.find(name).ok_or(Error::Io)?;
+        Ok(FatInode::get(&self.volume, &mut volume, &entry, location))
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut volume = self.volume.lock();
+        let parent = self.dir_ref(&volume)?;
+        let dir = volume.load_dir(parent)?;
+        let entry = dir.find(name).ok_or(Error::NotFound)?;
+
+        if entry.is_dir()
+            && !volume
+                .load_dir(DirRef::Chain(entry.first_cluster))?
+                .is_empty()
+        {
+            return Err(Error::NotEmpty);
+        }
+
+        volume.remove_entry(parent, &entry)?;
+        if entry.first_cluster != 0 {
+            volume.free_chain(entry.first_cluster)?;
+        }
+
+        let location = dir.location(entry.slot, volume.bpb.bytes_per_sector);
+        if let Some(inode) = volume
+            .inodes
+            .remove(&location)
+            .and_then(|inode| inode.upgrade())
+        {
+            let mut state = inode.state.lock();
+            state.deleted = true;
+            state.location = None;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &dyn Inode, new_name: &str) -> Result<()> {
+        let new_parent = (new_parent as &dyn Any)
+            .downcast_ref::<FatInode>()
+            .filter(|parent| Arc::ptr_eq(&parent.volume, &self.volume))
+            .ok_or(Error::CrossDevice)?;
+
+        let mut volume = self.volume.lock();
+        let bps = volume.bpb.bytes_per_sector;
+        let old_dir_ref = self.dir_ref(&volume)?;
+        let new_dir_ref = new_parent.dir_ref(&volume)?;
+
+        let old_dir = volume.load_dir(old_dir_ref)?;
+        let entry = old_dir.find(old_name).ok_or(Error::NotFound)?;
+        let old_location = old_dir.location(entry.slot, bps);
+
+        // An existing target is replaced, unless it is the entry itself changing case
+        let new_dir = volume.load_dir(new_dir_ref)?;
+        let target = new_dir.find(new_name);
+        let renames_itself = target
+            .as_ref()
+            .is_some_and(|target| new_dir.location(target.slot, bps) == old_location);
+
+        if let Some(target) = target.as_ref().filter(|_| !renames_itself) {
+            match (entry.is_dir(), target.is_dir()) {
+                (true, false) => return Err(Error::NotADirectory),
+                (false, true) => return Err(Error::IsADirectory),
+                (true, true)
+                    if !volume
+                        .load_dir(DirRef::Chain(target.first_cluster))?
+                        .is_empty() =>
+                {
+                    return Err(Error::NotEmpty);
+                }
+                _ => {}
+            }
+        }
+
+        // The new entry is written first, so running out of space loses neither file
+        let new_location = volume.insert_entry(
+            new_dir_ref,
+            new_name,
+            entry.attr,
+            entry.first_cluster,
+            entry.size,
+            (entry.ctime, entry.mtime),
+            target.as_ref(),
+        )?;
+        volume.remove_entry(old_dir_ref, &entry)?;
+
+        if let Some(target) = target.filter(|_| !renames_itself) {
+            volume.remove_entry(new_dir_ref, &target)?;
+            if target.first_cluster != 0 {
+                volume.free_chain(target.first_cluster)?;
+            }
+            let target_location = new_dir.location(target.slot, bps);
+            if let Some(inode) = volume
+                .inodes
+                .remove(&target_location)
+                .and_then(|inode| inode.upgrade())
+            {
+                let mut state = inode.state.lock();
+                state.deleted = true;
+                state.location = None;
+            }
+        }
+
+        if entry.is_dir() && old_dir_ref != new_dir_ref {
+            let parent_cluster = match new_dir_ref {
+                DirRef::FixedRoot => 0,
+                DirRef::Chain(cluster) => cluster,
+            };
+            volume.set_dot_dot(entry.first_cluster, parent_cluster)?;
+        }
+
+        if let Some(inode) = volume.inodes.remove(&old_location) {
+            volume.inodes.insert(new_location, inode.clone());
+            if let Some(inode) = inode.upgrade() {
+                inode.state.lock().location = Some(new_location);
+            }
+        }
+        Ok(())
+    }
+}
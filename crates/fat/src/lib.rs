@@ -0,0 +1,112 @@
+#![no_std]
+extern crate alloc;
+
+mod bpb;
+mod dir;
+mod inode;
+mod table;
+
+pub use bpb::FatType;
+pub use inode::FatInode;
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use block::BlockDevice;
+use bpb::Bpb;
+use custom_types::spin_lock::SpinLock;
+use vfs::{Error, Filesystem, Inode, Result};
+
+pub struct Volume {
+    device: Arc<dyn BlockDevice>,
+    bpb: Bpb,
+    blocks_per_sector: u64,
+    next_free: u32,
+    free_count: Option<u32>,
+    fs_info_dirty: bool,
+    // Open inodes by the location of their directory entry, so every user sees the same state
+    inodes: BTreeMap<(u64, usize), Weak<FatInode>>,
+}
+
+impl Volume {
+    pub(crate) fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<()> {
+        self.device
+            .read_blocks(sector * self.blocks_per_sector, buf)
+            .map_err(|_| Error::Io)
+    }
+
+    pub(crate) fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<()> {
+        self.device
+            .write_blocks(sector * self.blocks_per_sector, buf)
+            .map_err(|_| Error::Io)
+    }
+
+    pub(crate) fn read_sector(&self, sector: u64) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; self.bpb.bytes_per_sector];
+        self.read_sectors(sector, &mut data)?;
+        Ok(data)
+    }
+}
+
+pub struct FatFs {
+    volume: Arc<SpinLock<Volume>>,
+    root: Arc<FatInode>,
+}
+
+impl FatFs {
+    pub fn mount(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let block_size = device.block_size();
+        let mut boot_sector = vec![0u8; block_size.max(512)];
+        device
+            .read_blocks(0, &mut boot_sector)
+            .map_err(|_| Error::Io)?;
+
+        let bpb = Bpb::parse(&boot_sector)?;
+        if bpb.bytes_per_sector % block_size != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let blocks_per_sector = (bpb.bytes_per_sector / block_size) as u64;
+        if bpb.total_sectors * blocks_per_sector > device.block_count() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut volume = Volume {
+            device,
+            blocks_per_sector,
+            next_free: 2,
+            free_count: None,
+            fs_info_dirty: false,
+            inodes: BTreeMap::new(),
+            bpb,
+        };
+        volume.load_fs_info()?;
+
+        let root_cluster = volume.bpb.root_cluster;
+        let volume = Arc::new(SpinLock::new(volume));
+        let root = FatInode::root(&volume, root_cluster);
+        Ok(FatFs { volume, root })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.volume.lock().bpb.fat_type
+    }
+}
+
+impl Filesystem for FatFs {
+    fn name(&self) -> &'static str {
+        "vfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        let mut volume = self.volume.lock();
+        volume.sync_fs_info()?;
+        volume.device.flush().map_err(|_| Error::Io)
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{Volume, bpb::FatType};
+use alloc::{vec, vec::Vec};
+use vfs::{Error, Result};
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+impl Volume {
+    // Reads bytes at `offset` from the start of the first FAT, which may straddle two sectors
+    fn read_fat_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let bps = self.bpb.bytes_per_sector;
+        let sector = self.bpb.reserved_sectors + (offset / bps) as u64;
+        let within = offset % bps;
+
+        let mut data = vec![0u8; (within + buf.len()).div_ceil(bps) * bps];
+        self.read_sectors(sector, &mut data)?;
+        buf.copy_from_slice(&data[within..within + buf.len()]);
+        Ok(())
+    }
+
+    // Writes bytes at `offset` into every copy of the FAT
+    fn write_fat_bytes(&self, offset: usize, bytes: &[u8]) -> Result<()> {
+        let bps = self.bpb.bytes_per_sector;
+        let within = offset % bps;
+
+        let mut data = vec![0u8; (within + bytes.len()).div_ceil(bps) * bps];
+        for fat in 0..self.bpb.fat_count {
+            let sector =
+                self.bpb.reserved_sectors + fat * self.bpb.fat_size + (offset / bps) as u64;
+            self.read_sectors(sector, &mut data)?;
+            data[within..within + bytes.len()].copy_from_slice(bytes);
+            self.write_sectors(sector, &data)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let cluster = cluster as usize;
+        match self.bpb.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(cluster + cluster / 2, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes) as u32;
+                Ok(if cluster & 1 == 1 {
+                    value >> 4
+                } else {
+                    value & 0xFFF
+                })
+            }
+            FatType::Fat16 => {
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(cluster * 2, &mut bytes)?;
+                Ok(u16::from_le_bytes(bytes) as u32)
+            }
+            FatType::Fat32 => {
+                let mut bytes = [0u8; 4];
+                self.read_fat_bytes(cluster * 4, &mut bytes)?;
+                Ok(u32::from_le_bytes(bytes) & 0x0FFF_FFFF)
+            }
+        }
+    }
+
+    pub(crate) fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        let cluster = cluster as usize;
+        match self.bpb.fat_type {
+            FatType::Fat12 => {
+                // Two entries share three bytes, keep the neighbouring nibble
+                let offset = cluster + cluster / 2;
+                let mut bytes = [0u8; 2];
+                self.read_fat_bytes(offset, &mut bytes)?;
+                let old = u16::from_le_bytes(bytes);
+                let value = value as u16 & 0xFFF;
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | (value << 4)
+                } else {
+                    (old & 0xF000) | value
+                };
+                self.write_fat_bytes(offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => self.write_fat_bytes(cluster * 2, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                // The top four bits are reserved and must be preserved
+                let mut bytes = [0u8; 4];
+                self.read_fat_bytes(cluster * 4, &mut bytes)?;
+                let old = u32::from_le_bytes(bytes);
+                let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                self.write_fat_bytes(cluster * 4, &new.to_le_bytes())
+            }
+        }
+    }
+
+    pub(crate) fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while self.bpb.is_valid_cluster(cluster) {
+            // A chain longer than the volume means the FAT contains a loop
+            if clusters.len() > self.bpb.cluster_count as usize {
+                return Err(Error::Io);
+            }
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    pub(crate) fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32> {
+        let count = self.bpb.cluster_count;
+        let start = if self.bpb.is_valid_cluster(self.next_free) {
+            self.next_free
+        } else {
+            2
+        };
+
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+            if self.fat_entry(cluster)? != 0 {
+                continue;
+            }
+
+            self.set_fat_entry(cluster, self.bpb.end_of_chain())?;
+            if let Some(previous) = previous {
+                self.set_fat_entry(previous, cluster)?;
+            }
+            self.next_free = cluster + 1;
+            self.free_count = self.free_count.map(|free| free.saturating_sub(1));
+            self.fs_info_dirty = true;
+            return Ok(cluster);
+        }
+        Err(Error::NoSpace)
+    }
+
+    pub(crate) fn free_chain(&mut self, first: u32) -> Result<()> {
+        for cluster in self.chain(first)? {
+            self.set_fat_entry(cluster, 0)?;
+            self.free_count = self.free_count.map(|free| free + 1);
+        }
+        self.fs_info_dirty = true;
+        Ok(())
+    }
+
+    pub(crate) fn load_fs_info(&mut self) -> Result<()> {
+        if self.bpb.fat_type != FatType::Fat32 || self.bpb.fs_info_sector == 0 {
+            return Ok(());
+        }
+
+        let sector = self.read_sector(self.bpb.fs_info_sector)?;
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+        if u32_at(0) != FS_INFO_LEAD_SIGNATURE || u32_at(484) != FS_INFO_STRUCT_SIGNATURE {
+            return Ok(());
+        }
+
+        let free_count = u32_at(488);
+        if free_count <= self.bpb.cluster_count {
+            self.free_count = Some(free_count);
+        }
+        self.next_free = u32_at(492);
+        Ok(())
+    }
+
+    pub(crate) fn sync_fs_info(&mut self) -> Result<()> {
+        if !self.fs_info_dirty {
+            return Ok(());
+        }
+        self.fs_info_dirty = false;
+        if self.bpb.fat_type != FatType::Fat32 || self.bpb.fs_info_sector == 0 {
+            return Ok(());
+        }
+
+        let mut sector = self.read_sector(self.bpb.fs_info_sector)?;
+        let lead = u32::from_le_bytes(sector[0..4].try_into().unwrap());
+        if lead != FS_INFO_LEAD_SIGNATURE {
+            return Ok(());
+        }
+        let free_count = self.free_count.unwrap_or(FS_INFO_UNKNOWN);
+        sector[488..492].copy_from_slice(&free_count.to_le_bytes());
+        sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        self.write_sectors(self.bpb.fs_info_sector, &sector)
+    }
+}
@@ -10,32 +10,34 @@ use x86_64::{
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+const BOOT_STACK_SIZE: usize = 4096 * 5;
 
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+// Small stacks to get through boot; they have no guard page and are replaced
+// once virtual memory is up
+static mut DOUBLE_FAULT_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
+static mut PAGE_FAULT_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
+static mut PRIVILEGE_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
 
-            stack_start + STACK_SIZE as u64
-        };
-        tss
-    };
-}
+// The CPU reads the stack pointers from here, so it stays mutable after the TSS is loaded
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+        let tss = unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) };
+        let tss_selector = gdt.append(tss);
+        let user_data_selector = gdt.append(Descriptor::user_data_segment());
+        let user_code_selector = gdt.append(Descriptor::user_code_segment());
         (
             gdt,
             Selectors {
                 code_selector,
                 tss_selector,
+                user_code_selector,
+                user_data_selector,
             },
         )
     };
@@ -44,6 +46,8 @@ lazy_static! {
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
 }
 
 pub fn init() {
@@ -52,9 +56,38 @@ pub fn init() {
         tables::load_tss,
     };
 
+    let top =
+        |stack: *const [u8; BOOT_STACK_SIZE]| VirtAddr::from_ptr(stack) + BOOT_STACK_SIZE as u64;
+    set_interrupt_stack(DOUBLE_FAULT_IST_INDEX, top(&raw const DOUBLE_FAULT_STACK));
+    set_interrupt_stack(PAGE_FAULT_IST_INDEX, top(&raw const PAGE_FAULT_STACK));
+    set_privilege_stack(top(&raw const PRIVILEGE_STACK));
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+// Code and stack segment selectors for entering user mode
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
+
+// The new stack is used from the next interrupt that switches to it
+pub fn set_interrupt_stack(index: u16, top: VirtAddr) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        TSS.interrupt_stack_table[index as usize] = top;
+    });
+}
+
+pub fn privilege_stack() -> VirtAddr {
+    unsafe { TSS.privilege_stack_table[0] }
+}
+
+// Interrupts and system calls from user mode switch to this stack
+pub fn set_privilege_stack(top: VirtAddr) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        TSS.privilege_stack_table[0] = top;
+    });
+}
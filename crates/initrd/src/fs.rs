@@ -0,0 +1,105 @@
+use crate::{Initrd, Node, NodeKind};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use vfs::{DirEntry, Error, FileOps, FileType, Filesystem, Inode, Metadata, Result};
+
+pub struct InitrdFs {
+    root: &'static Node,
+}
+
+impl InitrdFs {
+    pub fn new(initrd: &'static Initrd) -> Self {
+        InitrdFs {
+            root: initrd.root(),
+        }
+    }
+}
+
+impl Filesystem for InitrdFs {
+    fn name(&self) -> &'static str {
+        "initrd"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(InitrdInode(self.root))
+    }
+}
+
+struct InitrdInode(&'static Node);
+
+impl InitrdInode {
+    // Nodes never move once the archive is parsed, so their address is a stable inode number
+    fn number(node: &Node) -> u64 {
+        node as *const Node as u64
+    }
+}
+
+fn file_type(node: &Node) -> FileType {
+    match node.kind {
+        NodeKind::File(_) => FileType::File,
+        NodeKind::Directory(_) => FileType::Directory,
+        NodeKind::Symlink(_) => FileType::Symlink,
+    }
+}
+
+impl FileOps for InitrdInode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = match &self.0.kind {
+            NodeKind::File(data) => data,
+            NodeKind::Directory(_) => return Err(Error::IsADirectory),
+            NodeKind::Symlink(_) => return Err(Error::InvalidArgument),
+        };
+
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+}
+
+impl Inode for InitrdInode {
+    fn metadata(&self) -> Metadata {
+        let node = self.0;
+        Metadata {
+            inode: Self::number(node),
+            file_type: file_type(node),
+            size: node.size() as u64,
+            mode: node.mode & 0o7777,
+            uid: node.uid,
+            gid: node.gid,
+            atime: node.mtime,
+            mtime: node.mtime,
+            ctime: node.mtime,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        if !self.0.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        let child = self.0.child(name).ok_or(Error::NotFound)?;
+        Ok(Arc::new(InitrdInode(child)))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        if !self.0.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        Ok(self
+            .0
+            .children()
+            .iter()
+            .map(|child| DirEntry {
+                name: child.name.clone(),
+                inode: Self::number(child),
+                file_type: file_type(child),
+            })
+            .collect())
+    }
+
+    fn read_link(&self) -> Result<String> {
+        match &self.0.kind {
+            NodeKind::Symlink(target) => Ok(target.clone()),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+#![no_std]
+extern crate alloc;
+
+mod fs;
+mod node;
+mod tar;
+
+pub use fs::InitrdFs;
+pub use node::{Node, NodeKind};
+pub use tar::{Entries, Entry};
+
+use alloc::{string::ToString, vec::Vec};
+
+pub struct Initrd {
+    root: Node,
+}
+
+impl Initrd {
+    pub fn parse(archive: &'static [u8]) -> Result<Self, &'static str> {
+        let mut root = Node::directory("");
+
+        for entry in Entries::new(archive) {
+            let entry = entry?;
+            let kind = match entry.typeflag {
+                tar::REGULAR | tar::REGULAR_OLD => NodeKind::File(entry.data),
+                tar::DIRECTORY => NodeKind::Directory(Vec::new()),
+                tar::SYMLINK => NodeKind::Symlink(entry.link_name.to_string()),
+                // The target comes earlier in the archive; the link shares its data
+                tar::HARD_LINK => match find(&root, entry.link_name).map(|node| &node.kind) {
+                    Some(NodeKind::File(data)) => NodeKind::File(data),
+                    _ => return Err("USTAR hard link to a missing file"),
+                },
+                // Device nodes and FIFOs have no place in the ramdisk
+                _ => continue,
+            };
+
+            let components: Vec<&str> = split_path(&entry.path).collect();
+            let Some((name, parents)) = components.split_last() else {
+                // The archive root itself ("./")
+                root.mode = entry.mode;
+                root.mtime = entry.mtime;
+                continue;
+            };
+
+            let mut dir = &mut root;
+            for component in parents {
+                dir = dir.child_or_insert_dir(component)?;
+            }
+
+            dir.insert(Node {
+                name: name.to_string(),
+                kind,
+                mode: entry.mode,
+                uid: entry.uid,
+                gid: entry.gid,
+                mtime: entry.mtime,
+            })?;
+        }
+
+        Ok(Initrd { root })
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn lookup(&self, path: &str) -> Option<&Node> {
+        find(&self.root, path)
+    }
+}
+
+fn find<'a>(root: &'a Node, path: &str) -> Option<&'a Node> {
+    let mut node = root;
+    for component in split_path(path) {
+        node = node.child(component)?;
+    }
+    Some(node)
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
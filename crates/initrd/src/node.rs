@@ -0,0 +1,97 @@
+use alloc::{string::String, vec::Vec};
+use core::mem;
+
+#[derive(Debug)]
+pub enum NodeKind {
+    File(&'static [u8]),
+    Directory(Vec<Node>),
+    Symlink(String),
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub name: String,
+    pub kind: NodeKind,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u64,
+}
+
+impl Node {
+    pub(crate) fn directory(name: &str) -> Self {
+        Node {
+            name: String::from(name),
+            kind: NodeKind::Directory(Vec::new()),
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            mtime: 0,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, NodeKind::Directory(_))
+    }
+
+    pub fn size(&self) -> usize {
+        match &self.kind {
+            NodeKind::File(data) => data.len(),
+            NodeKind::Directory(_) => 0,
+            NodeKind::Symlink(target) => target.len(),
+        }
+    }
+
+    pub fn data(&self) -> Option<&'static [u8]> {
+        match self.kind {
+            NodeKind::File(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match &self.kind {
+            NodeKind::Directory(children) => children,
+            _ => &[],
+        }
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children().iter().find(|node| node.name == name)
+    }
+
+    pub(crate) fn child_or_insert_dir(&mut self, name: &str) -> Result<&mut Node, &'static str> {
+        let NodeKind::Directory(children) = &mut self.kind else {
+            return Err("Parent is not a directory");
+        };
+
+        match children.iter().position(|node| node.name == name) {
+            Some(index) if children[index].is_dir() => Ok(&mut children[index]),
+            Some(_) => Err("Path component is not a directory"),
+            None => {
+                children.push(Node::directory(name));
+                Ok(children.last_mut().unwrap())
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, mut node: Node) -> Result<(), &'static str> {
+        let NodeKind::Directory(children) = &mut self.kind else {
+            return Err("Parent is not a directory");
+        };
+
+        match children.iter_mut().find(|child| child.name == node.name) {
+            Some(existing) => {
+                // Directories can be listed after their contents, keep what's inside
+                if let (NodeKind::Directory(old), NodeKind::Directory(new)) =
+                    (&mut existing.kind, &mut node.kind)
+                {
+                    mem::swap(old, new);
+                }
+                *existing = node;
+            }
+            None => children.push(node),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use alloc::{format, string::String};
+
+const BLOCK_SIZE: usize = 512;
+
+pub const REGULAR: u8 = b'0';
+pub const REGULAR_OLD: u8 = 0;
+pub const HARD_LINK: u8 = b'1';
+pub const SYMLINK: u8 = b'2';
+pub const DIRECTORY: u8 = b'5';
+
+pub struct Entry {
+    pub path: String,
+    pub typeflag: u8,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u64,
+    pub link_name: &'static str,
+    pub data: &'static [u8],
+}
+
+pub struct Entries {
+    archive: &'static [u8],
+    offset: usize,
+}
+
+impl Entries {
+    pub fn new(archive: &'static [u8]) -> Self {
+        Entries { archive, offset: 0 }
+    }
+
+    fn parse_header(&self, header: &'static [u8]) -> Result<Entry, &'static str> {
+        // Both POSIX ("ustar\0") and GNU ("ustar ") magic are accepted
+        if &header[257..262] != b"ustar" {
+            return Err("Invalid USTAR magic");
+        }
+
+        let checksum = parse_octal(&header[148..156])?;
+        let computed: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as u64)
+            .sum();
+        if checksum != computed {
+            return Err("Invalid USTAR header checksum");
+        }
+
+        let name = parse_str(&header[0..100])?;
+        let prefix = parse_str(&header[345..500])?;
+        let path = if prefix.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        let size = parse_octal(&header[124..136])? as usize;
+        let data_start = self.offset + BLOCK_SIZE;
+        let data_end = data_start
+            .checked_add(size)
+            .ok_or("Invalid USTAR entry size")?;
+        let data = self
+            .archive
+            .get(data_start..data_end)
+            .ok_or("Truncated USTAR entry")?;
+
+        Ok(Entry {
+            path,
+            typeflag: header[156],
+            mode: parse_octal(&header[100..108])? as u32,
+            uid: parse_octal(&header[108..116])? as u32,
+            gid: parse_octal(&header[116..124])? as u32,
+            mtime: parse_octal(&header[136..148])?,
+            link_name: parse_str(&header[157..257])?,
+            data,
+        })
+    }
+}
+
+impl Iterator for Entries {
+    type Item = Result<Entry, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.archive.get(self.offset..self.offset + BLOCK_SIZE)?;
+
+        // The archive ends with two zero blocks
+        if header.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+
+        let entry = self.parse_header(header);
+        match &entry {
+            Ok(entry) => {
+                self.offset += BLOCK_SIZE + entry.data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            }
+            Err(_) => self.offset = self.archive.len(),
+        }
+        Some(entry)
+    }
+}
+
+fn parse_str(field: &'static [u8]) -> Result<&'static str, &'static str> {
+    let len = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).map_err(|_| "Invalid UTF-8 in USTAR header")
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64, &'static str> {
+    field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ')
+        .try_fold(0u64, |acc, &byte| match byte {
+            b'0'..=b'7' => Ok(acc * 8 + (byte - b'0') as u64),
+            _ => Err("Invalid octal number in USTAR header"),
+        })
+}
@@ -0,0 +1,247 @@
+use crate::phys_to_virt;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    PhysAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+};
+
+const FRAME_SIZE: u64 = 4096;
+// Blocks go up to 2^MAX_ORDER frames, 4 MiB
+pub const MAX_ORDER: usize = 10;
+const ORDERS: usize = MAX_ORDER + 1;
+const NONE: u64 = u64::MAX;
+// Per-frame state: set on the first frame of a free block, together with the block's order
+const FREE: u8 = 0x80;
+// Allocated frames use the rest of their state to count extra owners, for copy-on-write sharing
+const MAX_SHARES: u8 = FREE - 1;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub free_blocks: [usize; ORDERS],
+}
+
+impl FrameStats {
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
+// Free blocks are linked through their own first frame, reached via the physical memory window
+#[repr(C)]
+struct Link {
+    next: u64,
+    prev: u64,
+}
+
+// Binary buddy allocator over every usable frame in the bootloader's memory map
+pub struct BuddyFrameAllocator {
+    heads: [u64; ORDERS],
+    free_blocks: [usize; ORDERS],
+    states: &'static mut [u8],
+    total: usize,
+    free: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Carves the state array out of the first usable region that can hold it.
+    ///
+    /// # Safety
+    /// The memory map must be accurate, its usable frames unused so far and reachable through
+    /// the physical memory window.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|region| region.region_type == MemoryRegionType::Usable)
+        };
+        let frame_count = usable()
+            .map(|region| region.range.end_frame_number)
+            .max()
+            .unwrap_or(0);
+        let state_frames = frame_count.div_ceil(FRAME_SIZE);
+        let state_region = usable()
+            .find(|region| {
+                region.range.end_frame_number - region.range.start_frame_number >= state_frames
+            })
+            .expect("No usable region can hold the frame allocator state")
+            .range
+            .start_frame_number;
+
+        let states = unsafe {
+            let start = phys_to_virt(PhysAddr::new(state_region * FRAME_SIZE));
+            core::slice::from_raw_parts_mut(start.as_mut_ptr::<u8>(), frame_count as usize)
+        };
+        states.fill(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            heads: [NONE; ORDERS],
+            free_blocks: [0; ORDERS],
+            states,
+            total: 0,
+            free: 0,
+        };
+        for region in usable() {
+            let mut start = region.range.start_frame_number;
+            if start == state_region {
+                start += state_frames;
+            }
+            // Frame zero stays out, a null physical address is too easy to mistake for "none"
+            let start = start.max(1);
+            let end = region.range.end_frame_number;
+            if start < end {
+                allocator.free_range(start, end - start);
+                allocator.total += (end - start) as usize;
+            }
+        }
+        allocator
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            free: self.free,
+            free_blocks: self.free_blocks,
+        }
+    }
+
+    // Allocates 2^order frames aligned to their size, splitting a larger block if needed
+    pub fn allocate_order(&mut self, order: usize) -> Option<PhysFrame> {
+        let found = (order..ORDERS).find(|&order| self.heads[order] != NONE)?;
+        let frame = self.heads[found];
+        self.remove(frame, found);
+        for order in (order..found).rev() {
+            self.push(frame + (1 << order), order);
+        }
+        self.free -= 1 << order;
+        Some(frame_at(frame))
+    }
+
+    // Rounds up to a power of two for the allocation and gives the unused tail back
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+        let order = count.next_power_of_two().trailing_zeros() as usize;
+        if order > MAX_ORDER {
+            return None;
+        }
+        let frame = self.allocate_order(order)?;
+        let start = number(frame);
+        self.free_range(start + count as u64, (1 << order) - count as u64);
+        Some(frame)
+    }
+
+    // Adds an owner to an allocated frame, which is then only freed once every owner let go of it
+    pub fn share(&mut self, frame: PhysFrame) -> bool {
+        match self.states.get_mut(number(frame) as usize) {
+            Some(state) if *state < MAX_SHARES => {
+                *state += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_shared(&self, frame: PhysFrame) -> bool {
+        let state = self.states.get(number(frame) as usize).copied();
+        matches!(state, Some(state) if state & FREE == 0 && state > 0)
+    }
+
+    pub fn deallocate_contiguous(&mut self, frame: PhysFrame, count: usize) {
+        self.free_range(number(frame), count as u64);
+    }
+
+    // Splits the range into the largest aligned blocks, which then merge with their buddies
+    fn free_range(&mut self, start: u64, count: u64) {
+        let (mut start, end) = (start, start + count);
+        while start < end {
+            let mut order = (start.trailing_zeros() as usize).min(MAX_ORDER);
+            while start + (1 << order) > end {
+                order -= 1;
+            }
+            self.free_block(start, order);
+            self.free += 1 << order;
+            start += 1 << order;
+        }
+    }
+
+    fn free_block(&mut self, frame: u64, order: usize) {
+        assert!(
+            self.states[frame as usize] & FREE == 0,
+            "Frame {:#x} freed twice",
+            frame * FRAME_SIZE
+        );
+
+        let (mut frame, mut order) = (frame, order);
+        while order < MAX_ORDER {
+            let buddy = frame ^ (1 << order);
+            if self.states.get(buddy as usize) != Some(&(FREE | order as u8)) {
+                break;
+            }
+            self.remove(buddy, order);
+            frame = frame.min(buddy);
+            order += 1;
+        }
+        self.push(frame, order);
+    }
+
+    fn link(&self, frame: u64) -> *mut Link {
+        phys_to_virt(PhysAddr::new(frame * FRAME_SIZE)).as_mut_ptr()
+    }
+
+    fn push(&mut self, frame: u64, order: usize) {
+        let head = self.heads[order];
+        unsafe {
+            self.link(frame).write(Link {
+                next: head,
+                prev: NONE,
+            });
+            if head != NONE {
+                (*self.link(head)).prev = frame;
+            }
+        }
+        self.heads[order] = frame;
+        self.states[frame as usize] = FREE | order as u8;
+        self.free_blocks[order] += 1;
+    }
+
+    fn remove(&mut self, frame: u64, order: usize) {
+        let Link { next, prev } = unsafe { self.link(frame).read() };
+        if prev != NONE {
+            unsafe { (*self.link(prev)).next = next };
+        } else {
+            self.heads[order] = next;
+        }
+        if next != NONE {
+            unsafe { (*self.link(next)).prev = prev };
+        }
+        self.states[frame as usize] = 0;
+        self.free_blocks[order] -= 1;
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_order(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if self.is_shared(frame) {
+            self.states[number(frame) as usize] -= 1;
+            return;
+        }
+        self.free_range(number(frame), 1);
+    }
+}
+
+fn number(frame: PhysFrame) -> u64 {
+    frame.start_address().as_u64() / FRAME_SIZE
+}
+
+fn frame_at(number: u64) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(number * FRAME_SIZE))
+}
@@ -0,0 +1,54 @@
+use crate::{FRAME_ALLOCATOR, MAPPER, phys_to_virt};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate},
+};
+
+const PAGE_SIZE: u64 = 4096;
+
+// Zeroed, physically contiguous frames for devices that access memory on their own
+pub fn allocate_dma(pages: usize) -> Option<PhysAddr> {
+    let frame = FRAME_ALLOCATOR
+        .lock()
+        .as_mut()?
+        .allocate_contiguous(pages)?;
+    let address = frame.start_address();
+    unsafe {
+        core::ptr::write_bytes(
+            phys_to_virt(address).as_mut_ptr::<u8>(),
+            0,
+            pages * PAGE_SIZE as usize,
+        )
+    };
+    Some(address)
+}
+
+/// # Safety
+/// The device must be done with the memory, and nothing may use it afterwards.
+pub unsafe fn free_dma(address: PhysAddr, pages: usize) {
+    if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+        frame_allocator.deallocate_contiguous(PhysFrame::containing_address(address), pages);
+    }
+}
+
+// Makes device memory reachable through the physical memory window, which only covers RAM
+pub fn map_mmio(address: PhysAddr, size: u64) -> Option<VirtAddr> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut()?;
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut()?;
+
+    let first = PhysFrame::<Size4KiB>::containing_address(address);
+    let last = PhysFrame::<Size4KiB>::containing_address(address + size.max(1) - 1u64);
+    for frame in PhysFrame::range_inclusive(first, last) {
+        let page = Page::containing_address(phys_to_virt(frame.start_address()));
+        if mapper.translate_addr(page.start_address()).is_some() {
+            continue;
+        }
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+            .ok()?
+            .flush();
+    }
+    Some(phys_to_virt(address))
+}
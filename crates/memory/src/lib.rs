@@ -1,14 +1,42 @@
 #![no_std]
+extern crate alloc;
+
+mod buddy;
+mod dma;
+mod stack;
+mod table;
+mod vma;
+mod walk;
+
+pub use buddy::{BuddyFrameAllocator, FrameStats, MAX_ORDER};
+pub use dma::{allocate_dma, free_dma, map_mmio};
+pub use stack::{allocate_stack, free_stack, stack_guard};
+pub use table::COPY_ON_WRITE;
+pub use vma::{
+    Access, AddressSpace, Backing, FilePage, Segfault, USER_END, USER_START, Vma, VmaFlags, VmaKind,
+};
+pub use walk::{Region, regions, translate};
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
+use custom_types::spin_lock::SpinLock;
 use x86_64::{
     PhysAddr, VirtAddr,
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, Translate,
+        mapper::{MapToError, MappedFrame, TranslateResult},
     },
 };
 
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+// The bootloader's level 4 table, which the kernel keeps using for itself
+static KERNEL_TABLE: AtomicU64 = AtomicU64::new(0);
+static MAPPER: SpinLock<Option<OffsetPageTable<'static>>> = SpinLock::new(None);
+static FRAME_ALLOCATOR: SpinLock<Option<BuddyFrameAllocator>> = SpinLock::new(None);
+
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
@@ -27,6 +55,162 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     }
 }
 
+// Hands the boot-time mapper and frame allocator over to the kernel once the heap is set up
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    let (table, _) = x86_64::registers::control::Cr3::read();
+    KERNEL_TABLE.store(table.start_address().as_u64(), Ordering::Relaxed);
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()
+}
+
+/// # Safety
+/// The frame must be unused, in particular no longer mapped anywhere.
+pub unsafe fn deallocate_frame(frame: PhysFrame) {
+    if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+pub fn frame_stats() -> Option<FrameStats> {
+    Some(FRAME_ALLOCATOR.lock().as_ref()?.stats())
+}
+
+// Backs the pages with fresh frames; on failure the pages mapped so far are released again
+pub fn map_pages(
+    start: Page,
+    count: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator
+        .as_mut()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    for (i, page) in (start..start + count).enumerate() {
+        let mapped = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| {
+                unsafe { mapper.map_to(page, frame, flags, frame_allocator) }.inspect_err(|_| {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                })
+            });
+        match mapped {
+            Ok(flush) => flush.flush(),
+            Err(error) => {
+                for page in start..start + i as u64 {
+                    if let Ok((frame, flush)) = mapper.unmap(page) {
+                        flush.flush();
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                    }
+                }
+                return Err(error);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Backs a single page with a zeroed frame. Parent tables are created writable and user-accessible,
+// so that `flags` alone decide what the page allows
+pub fn map_zeroed(page: Page, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let frame = allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+    let contents = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::write_bytes(contents, 0, 4096) };
+    let mapped = map_frame(page, frame, flags);
+    if mapped.is_err() {
+        unsafe { deallocate_frame(frame) };
+    }
+    mapped
+}
+
+// Maps a frame the caller owns; on failure the frame stays with the caller
+pub(crate) fn map_frame(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator
+        .as_mut()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    let parent_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    unsafe { mapper.map_to_with_table_flags(page, frame, flags, parent_flags, frame_allocator) }?
+        .flush();
+    Ok(())
+}
+
+// Changes the flags of whatever is mapped in the range. Frames shared with another address
+// space never become writable, they stay copy-on-write instead
+pub(crate) fn protect_pages(start: Page, count: u64, flags: PageTableFlags) {
+    let mut mapper = MAPPER.lock();
+    let Some(mapper) = mapper.as_mut() else {
+        return;
+    };
+    let frame_allocator = FRAME_ALLOCATOR.lock();
+    for page in start..start + count {
+        let TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags: old_flags,
+            ..
+        } = mapper.translate(page.start_address())
+        else {
+            continue;
+        };
+        let shared = frame_allocator
+            .as_ref()
+            .is_some_and(|frame_allocator| frame_allocator.is_shared(frame));
+        let flags = match flags.contains(PageTableFlags::WRITABLE)
+            && (shared || old_flags.contains(COPY_ON_WRITE))
+        {
+            true => (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE,
+            false => flags,
+        };
+        if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+            flush.flush();
+        }
+    }
+}
+
+// Unmaps whatever is mapped in the range and frees the frames behind it
+pub fn unmap_pages(start: Page, count: u64) {
+    let mut mapper = MAPPER.lock();
+    let Some(mapper) = mapper.as_mut() else {
+        return;
+    };
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    for page in start..start + count {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            if let Some(frame_allocator) = frame_allocator.as_mut() {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+}
+
+pub(crate) fn translate_page(page: Page) -> Option<PhysFrame> {
+    MAPPER.lock().as_ref()?.translate_page(page).ok()
+}
+
+pub(crate) fn kernel_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_TABLE.load(Ordering::Relaxed)))
+}
+
+pub fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + address.as_u64())
+}
+
 pub fn create_example_mapping(
     page: Page,
     mapper: &mut OffsetPageTable,
@@ -0,0 +1,88 @@
+use crate::{map_pages, unmap_pages};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use custom_types::spin_lock::SpinLock;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageTableFlags, Size4KiB, mapper::MapToError},
+};
+
+// Kernel stacks are carved out of this range, each with an unmapped guard page below it
+const STACKS_START: u64 = 0x0000_5000_0000_0000;
+const STACKS_END: u64 = 0x0000_5080_0000_0000;
+
+struct Stack {
+    name: &'static str,
+    guard: Page,
+    pages: u64,
+    in_use: bool,
+}
+
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACKS_START);
+static STACKS: SpinLock<Vec<Stack>> = SpinLock::new(Vec::new());
+
+// Maps a stack of `pages` pages and returns its top, reusing the range of a freed stack if one fits
+pub fn allocate_stack(name: &'static str, pages: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let reused = STACKS
+        .lock()
+        .iter_mut()
+        .find(|stack| !stack.in_use && stack.pages == pages)
+        .map(|stack| {
+            stack.name = name;
+            stack.in_use = true;
+            stack.guard
+        });
+    if let Some(guard) = reused {
+        map_pages(guard + 1, pages, flags).inspect_err(|_| release(guard))?;
+        return Ok((guard + 1 + pages).start_address());
+    }
+
+    let size = (pages + 1) * 4096;
+    let start = NEXT_STACK.fetch_add(size, Ordering::Relaxed);
+    if start + size > STACKS_END {
+        return Err(MapToError::FrameAllocationFailed);
+    }
+    let guard = Page::containing_address(VirtAddr::new(start));
+    map_pages(guard + 1, pages, flags)?;
+    STACKS.lock().push(Stack {
+        name,
+        guard,
+        pages,
+        in_use: true,
+    });
+    Ok((guard + 1 + pages).start_address())
+}
+
+/// Unmaps a stack returned by `allocate_stack`, keeping its range for the next stack of its size.
+///
+/// # Safety
+/// Nothing may run on the stack or point into it anymore.
+pub unsafe fn free_stack(top: VirtAddr) {
+    let found = STACKS
+        .lock()
+        .iter()
+        .find(|stack| stack.in_use && (stack.guard + 1 + stack.pages).start_address() == top)
+        .map(|stack| (stack.guard, stack.pages));
+    if let Some((guard, pages)) = found {
+        unmap_pages(guard + 1, pages);
+        release(guard);
+    }
+}
+
+fn release(guard: Page) {
+    if let Some(stack) = STACKS.lock().iter_mut().find(|stack| stack.guard == guard) {
+        stack.in_use = false;
+    }
+}
+
+// Names the stack whose guard page holds the address. Runs in the page fault handler,
+// so it gives up rather than wait for the lock
+pub fn stack_guard(address: VirtAddr) -> Option<&'static str> {
+    let page = Page::containing_address(address);
+    let stacks = STACKS.try_lock()?;
+    stacks
+        .iter()
+        .find(|stack| stack.in_use && stack.guard == page)
+        .map(|stack| stack.name)
+}
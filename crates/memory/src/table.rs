@@ -0,0 +1,193 @@
+use crate::{
+    FRAME_ALLOCATOR, MAPPER, PHYSICAL_MEMORY_OFFSET, Segfault, USER_END, USER_START, phys_to_virt,
+};
+use core::sync::atomic::Ordering;
+use x86_64::{
+    VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, Translate,
+        mapper::{MapToError, MappedFrame, TranslateResult},
+    },
+};
+
+// Marks a page that is shared with another address space and gets copied on its first write
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+const USER_PARENT_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+// Level 4 entries covering the user range; every other entry maps the kernel
+const USER_ENTRIES: core::ops::Range<usize> =
+    (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+
+pub(crate) fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() }
+}
+
+fn mapper_for(frame: PhysFrame) -> OffsetPageTable<'static> {
+    let offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed));
+    unsafe { OffsetPageTable::new(table_at(frame), offset) }
+}
+
+fn copy_kernel_entries(from: &PageTable, to: &mut PageTable) {
+    for (index, entry) in from.iter().enumerate() {
+        if !USER_ENTRIES.contains(&index) {
+            to[index] = entry.clone();
+        }
+    }
+}
+
+// A level 4 table with an empty user half and the kernel mapped like in the active table
+pub(crate) fn new_table() -> Option<PhysFrame> {
+    let mapper = MAPPER.lock();
+    let mapper = mapper.as_ref()?;
+    let frame = FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()?;
+    let table = table_at(frame);
+    table.zero();
+    copy_kernel_entries(mapper.level_4_table(), table);
+    Some(frame)
+}
+
+// Switches to the table. New top-level kernel entries are carried over from the active table,
+// so a kernel mapping made under any table reaches the others on their next switch
+pub(crate) fn activate(frame: PhysFrame) {
+    let mut mapper = MAPPER.lock();
+    let Some(active) = mapper.as_mut() else {
+        return;
+    };
+    let (active_frame, flags) = Cr3::read();
+    if active_frame == frame {
+        return;
+    }
+    copy_kernel_entries(active.level_4_table(), table_at(frame));
+    *mapper = Some(mapper_for(frame));
+    unsafe { Cr3::write(frame, flags) };
+}
+
+/// Frees the user half of the table, the pages mapped there and finally the table itself.
+///
+/// # Safety
+/// The table must not be active and nothing else may refer to it.
+pub(crate) unsafe fn free_table(frame: PhysFrame) {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let Some(frame_allocator) = frame_allocator.as_mut() else {
+        return;
+    };
+    free_level(frame_allocator, table_at(frame), 4, USER_ENTRIES);
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+fn free_level(
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    table: &PageTable,
+    level: u8,
+    entries: core::ops::Range<usize>,
+) {
+    for entry in table.iter().take(entries.end).skip(entries.start) {
+        let Ok(frame) = entry.frame() else {
+            continue;
+        };
+        if level > 1 {
+            free_level(frame_allocator, table_at(frame), level - 1, 0..512);
+        }
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+// Maps the page of the active table into `child` too, sharing its frame.
+// Writable pages turn read-only and copy-on-write in both tables
+pub(crate) fn share_page(page: Page, child: PhysFrame) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator
+        .as_mut()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return Ok(());
+    };
+    let flags = match flags.contains(PageTableFlags::WRITABLE) {
+        true => (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE,
+        false => flags,
+    };
+    if !frame_allocator.share(frame) {
+        return Err(MapToError::FrameAllocationFailed);
+    }
+    let mapped = unsafe {
+        mapper_for(child).map_to_with_table_flags(
+            page,
+            frame,
+            flags,
+            USER_PARENT_FLAGS,
+            frame_allocator,
+        )
+    };
+    match mapped {
+        Ok(flush) => flush.ignore(),
+        Err(error) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            return Err(error);
+        }
+    }
+    if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+        flush.flush();
+    }
+    Ok(())
+}
+
+// Gives a copy-on-write page of the active table a frame of its own and makes it writable.
+// The last owner of a shared frame keeps it without copying
+pub(crate) fn copy_on_write(page: Page) -> Result<(), Segfault> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(Segfault::Unmapped)?;
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().ok_or(Segfault::OutOfMemory)?;
+
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return Err(Segfault::Unmapped);
+    };
+    if !flags.contains(COPY_ON_WRITE) {
+        return Err(Segfault::Protection);
+    }
+    let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+    if !frame_allocator.is_shared(frame) {
+        if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+            flush.flush();
+        }
+        return Ok(());
+    }
+
+    let copy = frame_allocator
+        .allocate_frame()
+        .ok_or(Segfault::OutOfMemory)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+            phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+            4096,
+        )
+    };
+    if let Ok((frame, flush)) = mapper.unmap(page) {
+        flush.flush();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+    // The parent tables are still there, so this cannot fail
+    unsafe { mapper.map_to(page, copy, flags, frame_allocator) }
+        .expect("Remapping a copied page failed")
+        .flush();
+    Ok(())
+}
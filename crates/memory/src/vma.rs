@@ -0,0 +1,491 @@
+use crate::{
+    allocate_frame, deallocate_frame, kernel_table, map_frame, map_zeroed, phys_to_virt,
+    protect_pages,
+    table::{self, copy_on_write, share_page},
+    translate_page, unmap_pages,
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageTableFlags, PhysFrame},
+};
+
+const PAGE_SIZE: u64 = 4096;
+
+// User mappings live in this part of the lower half, clear of the bootloader's mappings and the heap
+pub const USER_START: u64 = 0x0000_1000_0000_0000;
+pub const USER_END: u64 = 0x0000_4000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmaFlags(pub u8);
+
+impl VmaFlags {
+    pub const READ: VmaFlags = VmaFlags(1);
+    pub const WRITE: VmaFlags = VmaFlags(2);
+    pub const EXEC: VmaFlags = VmaFlags(4);
+
+    pub fn contains(self, flags: VmaFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    // Pages without any access stay mapped but out of reach of user mode
+    pub fn page_flags(self) -> PageTableFlags {
+        if self.0 == 0 {
+            return PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+        }
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if self.contains(VmaFlags::WRITE) {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !self.contains(VmaFlags::EXEC) {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+impl core::ops::BitOr for VmaFlags {
+    type Output = VmaFlags;
+
+    fn bitor(self, rhs: VmaFlags) -> VmaFlags {
+        VmaFlags(self.0 | rhs.0)
+    }
+}
+
+// Supplies the contents of file-backed pages
+pub trait Backing: Send + Sync {
+    // Fills the buffer from `offset`; whatever lies past the end of the file stays zero
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), &'static str>;
+}
+
+#[derive(Clone)]
+pub enum VmaKind {
+    // Zero-filled on first touch
+    Anonymous,
+    // Read from the file on first touch, the first page from `offset`. The mapping is
+    // private: writes go to a copy and never reach the file
+    File {
+        backing: Arc<dyn Backing>,
+        offset: u64,
+    },
+}
+
+impl fmt::Debug for VmaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmaKind::Anonymous => f.write_str("Anonymous"),
+            VmaKind::File { offset, .. } => write!(f, "File at {:#x}", offset),
+        }
+    }
+}
+
+// A page-aligned range of an address space; its pages are only backed once they are touched
+#[derive(Debug, Clone)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: VmaFlags,
+    pub kind: VmaKind,
+}
+
+impl Vma {
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    fn pages(&self) -> (Page, u64) {
+        let start = Page::containing_address(self.start);
+        (start, (self.end - self.start) / PAGE_SIZE)
+    }
+
+    // Splits before `address`, which must lie inside the VMA past its start
+    fn split(self, address: VirtAddr) -> (Vma, Vma) {
+        let kind = match &self.kind {
+            VmaKind::Anonymous => VmaKind::Anonymous,
+            VmaKind::File { backing, offset } => VmaKind::File {
+                backing: backing.clone(),
+                // mmap makes sure the offset of the end of a mapping fits
+                offset: offset
+                    .checked_add(address - self.start)
+                    .expect("file offset overflow"),
+            },
+        };
+        let back = Vma {
+            start: address,
+            kind,
+            ..self.clone()
+        };
+        let front = Vma {
+            end: address,
+            ..self
+        };
+        (front, back)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segfault {
+    Unmapped,
+    Protection,
+    OutOfMemory,
+}
+
+impl Segfault {
+    pub fn reason(self) -> &'static str {
+        match self {
+            Segfault::Unmapped => "address not mapped",
+            Segfault::Protection => "access not permitted",
+            Segfault::OutOfMemory => "out of memory",
+        }
+    }
+}
+
+// A page of a file mapping still to be read in. Reading may take filesystem locks, so it
+// happens once the address space is no longer locked
+pub struct FilePage {
+    page: Page,
+    flags: PageTableFlags,
+    backing: Arc<dyn Backing>,
+    offset: u64,
+}
+
+impl FilePage {
+    pub fn read_in(self) -> Result<(), Segfault> {
+        map_from_file(self.page, self.flags, self.backing.as_ref(), self.offset)
+    }
+}
+
+// The user half of an address space, as a set of non-overlapping VMAs keyed by their start.
+// Pages are mapped and unmapped in the active table, so only the active address space may change
+pub struct AddressSpace {
+    vmas: BTreeMap<u64, Vma>,
+    // Level 4 table of a forked address space; the others share the kernel's table
+    table: Option<PhysFrame>,
+    // The heap runs from `heap_start` up to the program break, both unset until a program is loaded
+    heap_start: VirtAddr,
+    brk: VirtAddr,
+}
+
+impl AddressSpace {
+    pub const fn new() -> Self {
+        AddressSpace {
+            vmas: BTreeMap::new(),
+            table: None,
+            heap_start: VirtAddr::zero(),
+            brk: VirtAddr::zero(),
+        }
+    }
+
+    // Copies the active address space into a table of its own. Mapped pages are shared
+    // copy-on-write instead of copied
+    pub fn fork(&self) -> Result<AddressSpace, &'static str> {
+        let table = table::new_table().ok_or("Out of memory")?;
+        let child = AddressSpace {
+            vmas: self.vmas.clone(),
+            table: Some(table),
+            heap_start: self.heap_start,
+            brk: self.brk,
+        };
+        for vma in self.vmas.values() {
+            let (start, count) = vma.pages();
+            for page in start..start + count {
+                share_page(page, table).map_err(|_| "Out of memory")?;
+            }
+        }
+        Ok(child)
+    }
+
+    pub fn activate(&self) {
+        table::activate(self.table.unwrap_or_else(kernel_table));
+    }
+
+    pub fn map(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: VmaFlags,
+        kind: VmaKind,
+    ) -> Result<(), &'static str> {
+        let end = checked_range(start, size)?;
+        if self.overlapping(start, end).next().is_some() {
+            return Err("Range is already mapped");
+        }
+        let vma = Vma {
+            start,
+            end,
+            flags,
+            kind,
+        };
+        self.vmas.insert(start.as_u64(), vma);
+        Ok(())
+    }
+
+    // Removes the range from every VMA it touches, splitting them where needed
+    pub fn unmap(&mut self, start: VirtAddr, size: u64) -> Result<(), &'static str> {
+        let end = checked_range(start, size)?;
+        self.split_at(start);
+        self.split_at(end);
+        let inside: Vec<u64> = self
+            .overlapping(start, end)
+            .map(|vma| vma.start.as_u64())
+            .collect();
+        for key in inside {
+            let (start, count) = self.vmas.remove(&key).unwrap().pages();
+            unmap_pages(start, count);
+        }
+        Ok(())
+    }
+
+    // Changes the permissions of a range that must be mapped throughout
+    pub fn protect(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: VmaFlags,
+    ) -> Result<(), &'static str> {
+        let end = checked_range(start, size)?;
+        if !self.covers(start, size, VmaFlags(0)) {
+            return Err("Range is not mapped");
+        }
+        self.split_at(start);
+        self.split_at(end);
+        for (_, vma) in self.vmas.range_mut(start.as_u64()..end.as_u64()) {
+            vma.flags = flags;
+            let (start, count) = vma.pages();
+            protect_pages(start, count, flags.page_flags());
+        }
+        Ok(())
+    }
+
+    // Makes `address` the start of a VMA if it falls inside one
+    fn split_at(&mut self, address: VirtAddr) {
+        let Some(vma) = self.find(address) else {
+            return;
+        };
+        if vma.start == address {
+            return;
+        }
+        let key = vma.start.as_u64();
+        let (front, back) = self.vmas.remove(&key).unwrap().split(address);
+        self.vmas.insert(key, front);
+        self.vmas.insert(address.as_u64(), back);
+    }
+
+    // The lowest unmapped range of `size` bytes at or above `from`
+    pub fn find_free(&self, from: VirtAddr, size: u64) -> Option<VirtAddr> {
+        let mut start = from.align_up(PAGE_SIZE).as_u64().max(USER_START);
+        for vma in self.vmas.values() {
+            if vma.end.as_u64() <= start {
+                continue;
+            }
+            if start.checked_add(size)? <= vma.start.as_u64() {
+                break;
+            }
+            start = vma.end.as_u64();
+        }
+        (start.checked_add(size)? <= USER_END).then(|| VirtAddr::new(start))
+    }
+
+    // Starts an empty heap at the first page boundary from `start`, normally the end of the program
+    pub fn set_heap_start(&mut self, start: VirtAddr) {
+        self.heap_start = start.align_up(PAGE_SIZE);
+        self.brk = self.heap_start;
+    }
+
+    pub fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    // Moves the program break, mapping or unmapping whole pages of heap as needed
+    pub fn set_brk(&mut self, brk: VirtAddr) -> Result<(), &'static str> {
+        if self.heap_start.is_null() {
+            return Err("There is no heap");
+        }
+        if brk < self.heap_start {
+            return Err("Break is below the start of the heap");
+        }
+        let old_end = self.brk.align_up(PAGE_SIZE);
+        let new_end = brk.align_up(PAGE_SIZE);
+        if new_end > old_end {
+            let flags = VmaFlags::READ | VmaFlags::WRITE;
+            self.map(old_end, new_end - old_end, flags, VmaKind::Anonymous)?;
+        } else if new_end < old_end {
+            self.unmap(new_end, old_end - new_end)?;
+        }
+        self.brk = brk;
+        Ok(())
+    }
+
+    // Whether the range lies in the user range and VMAs allowing `flags` cover all of it
+    pub fn covers(&self, start: VirtAddr, len: u64, flags: VmaFlags) -> bool {
+        let Some(end) = start.as_u64().checked_add(len) else {
+            return false;
+        };
+        if start.as_u64() < USER_START || end > USER_END {
+            return false;
+        }
+        let mut covered = start.as_u64();
+        while covered < end {
+            match self.find(VirtAddr::new(covered)) {
+                Some(vma) if vma.flags.contains(flags) => covered = vma.end.as_u64(),
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    pub fn find(&self, address: VirtAddr) -> Option<&Vma> {
+        let (_, vma) = self.vmas.range(..=address.as_u64()).next_back()?;
+        vma.contains(address).then_some(vma)
+    }
+
+    pub fn vmas(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.values()
+    }
+
+    fn overlapping(&self, start: VirtAddr, end: VirtAddr) -> impl Iterator<Item = &Vma> {
+        self.vmas
+            .values()
+            .filter(move |vma| vma.start < end && start < vma.end)
+    }
+
+    // Backs the faulting page if the VMA allows the access. A present page only faults when
+    // its flags forbid the access, which the VMA then does too, or on a write to a
+    // copy-on-write page. Pages of files are left to the caller to read in
+    pub fn handle_fault(
+        &mut self,
+        address: VirtAddr,
+        access: Access,
+        present: bool,
+    ) -> Result<Option<FilePage>, Segfault> {
+        let vma = self.find(address).ok_or(Segfault::Unmapped)?;
+        let required = match access {
+            Access::Read => VmaFlags::READ,
+            Access::Write => VmaFlags::WRITE,
+            Access::Execute => VmaFlags::EXEC,
+        };
+        if !vma.flags.contains(required) {
+            return Err(Segfault::Protection);
+        }
+        let page = Page::containing_address(address);
+        if present {
+            return match access {
+                Access::Write => copy_on_write(page).map(|()| None),
+                _ => Err(Segfault::Protection),
+            };
+        }
+        match &vma.kind {
+            VmaKind::Anonymous => map_zeroed(page, vma.flags.page_flags())
+                .map(|()| None)
+                .map_err(|_| Segfault::OutOfMemory),
+            VmaKind::File { backing, offset } => Ok(Some(FilePage {
+                page,
+                flags: vma.flags.page_flags(),
+                backing: backing.clone(),
+                offset: offset
+                    .checked_add(page.start_address() - vma.start)
+                    .ok_or(Segfault::Unmapped)?,
+            })),
+        }
+    }
+
+    // Copies into the address space regardless of the VMA's permissions, for loading programs
+    pub fn write(&mut self, address: VirtAddr, data: &[u8]) -> Result<(), Segfault> {
+        let mut done = 0;
+        while done < data.len() {
+            let address = address + done as u64;
+            let flags = self.find(address).ok_or(Segfault::Unmapped)?.flags;
+            let page = Page::containing_address(address);
+            match copy_on_write(page) {
+                Ok(()) | Err(Segfault::Protection | Segfault::Unmapped) => {}
+                Err(error) => return Err(error),
+            }
+            let frame = match translate_page(page) {
+                Some(frame) => frame,
+                None => {
+                    map_zeroed(page, flags.page_flags()).map_err(|_| Segfault::OutOfMemory)?;
+                    translate_page(page).ok_or(Segfault::OutOfMemory)?
+                }
+            };
+            let offset = address - page.start_address();
+            let len = (PAGE_SIZE - offset).min((data.len() - done) as u64) as usize;
+            let target = phys_to_virt(frame.start_address() + offset).as_mut_ptr::<u8>();
+            unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), target, len) };
+            done += len;
+        }
+        Ok(())
+    }
+
+    // Unmaps every VMA, the heap included, and frees the frames behind them
+    pub fn clear(&mut self) {
+        self.heap_start = VirtAddr::zero();
+        self.brk = VirtAddr::zero();
+        for vma in core::mem::take(&mut self.vmas).into_values() {
+            let (start, count) = vma.pages();
+            unmap_pages(start, count);
+        }
+    }
+}
+
+impl Default for AddressSpace {
+    fn default() -> Self {
+        AddressSpace::new()
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        match self.table {
+            // A forked address space is dropped after switching away from it
+            Some(table) => unsafe { table::free_table(table) },
+            None => self.clear(),
+        }
+    }
+}
+
+// The frame is filled from the file before it becomes visible
+fn map_from_file(
+    page: Page,
+    flags: PageTableFlags,
+    backing: &dyn Backing,
+    offset: u64,
+) -> Result<(), Segfault> {
+    let frame = allocate_frame().ok_or(Segfault::OutOfMemory)?;
+    let contents = unsafe {
+        core::slice::from_raw_parts_mut(
+            phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+            PAGE_SIZE as usize,
+        )
+    };
+    contents.fill(0);
+    let mapped = match backing.read(offset, contents) {
+        Ok(()) => map_frame(page, frame, flags).map_err(|_| Segfault::OutOfMemory),
+        Err(_) => Err(Segfault::Unmapped),
+    };
+    if mapped.is_err() {
+        unsafe { deallocate_frame(frame) };
+    }
+    mapped
+}
+
+fn checked_range(start: VirtAddr, size: u64) -> Result<VirtAddr, &'static str> {
+    if !start.is_aligned(PAGE_SIZE) || size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+        return Err("Range is not page-aligned");
+    }
+    let end = start
+        .as_u64()
+        .checked_add(size)
+        .ok_or("Range is out of bounds")?;
+    if start.as_u64() < USER_START || end > USER_END {
+        return Err("Range is out of bounds");
+    }
+    Ok(VirtAddr::new(end))
+}
@@ -0,0 +1,98 @@
+use crate::{MAPPER, table::table_at};
+use alloc::vec::Vec;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{
+        PageTable, PageTableFlags, PhysFrame, Translate, mapper::TranslateResult,
+    },
+};
+
+// The CPU sets these as pages are used, so they would split otherwise identical ranges
+const VOLATILE_FLAGS: PageTableFlags = PageTableFlags::ACCESSED
+    .union(PageTableFlags::DIRTY)
+    .union(PageTableFlags::HUGE_PAGE);
+
+// Consecutive pages of one size mapped with the same flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: VirtAddr,
+    pub size: u64,
+    pub page_size: u64,
+    pub flags: PageTableFlags,
+    // Only known when the whole range is physically contiguous
+    pub phys: Option<PhysAddr>,
+}
+
+impl Region {
+    fn extend(
+        &mut self,
+        start: VirtAddr,
+        phys: PhysAddr,
+        page_size: u64,
+        flags: PageTableFlags,
+    ) -> bool {
+        let next = self.start.as_u64().wrapping_add(self.size);
+        if next != start.as_u64() || page_size != self.page_size || flags != self.flags {
+            return false;
+        }
+        if self.phys.is_some_and(|first| first + self.size != phys) {
+            self.phys = None;
+        }
+        self.size += page_size;
+        true
+    }
+}
+
+// The physical address and flags of the page behind `address` in the active table
+pub fn translate(address: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+    match MAPPER.lock().as_ref()?.translate(address) {
+        TranslateResult::Mapped {
+            frame,
+            offset,
+            flags,
+        } => Some((frame.start_address() + offset, flags)),
+        _ => None,
+    }
+}
+
+// Lists what the active table maps, in address order. The tables are read without the mapper
+// lock, since collecting the regions may grow the heap and map pages through it
+pub fn regions() -> Vec<Region> {
+    let (frame, _) = Cr3::read();
+    let mut regions = Vec::new();
+    walk(table_at(frame), 4, 0, &mut regions);
+    regions
+}
+
+fn walk(table: &PageTable, level: u8, base: u64, regions: &mut Vec<Region>) {
+    let shift = 12 + 9 * (level as u64 - 1);
+    for (index, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let address = base | (index as u64) << shift;
+        let huge = level < 4 && flags.contains(PageTableFlags::HUGE_PAGE);
+        if level > 1 && !huge {
+            let frame = PhysFrame::containing_address(entry.addr());
+            walk(table_at(frame), level - 1, address, regions);
+            continue;
+        }
+        // Addresses in the upper half are sign-extended from bit 47
+        let start = VirtAddr::new_truncate(address);
+        let (page_size, flags) = (1 << shift, flags - VOLATILE_FLAGS);
+        let extended = regions
+            .last_mut()
+            .is_some_and(|last| last.extend(start, entry.addr(), page_size, flags));
+        if !extended {
+            regions.push(Region {
+                start,
+                size: page_size,
+                page_size,
+                flags,
+                phys: Some(entry.addr()),
+            });
+        }
+    }
+}
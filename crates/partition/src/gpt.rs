@@ -0,0 +1,134 @@
+use crate::{Error, PartitionInfo, PartitionKind, crc32::crc32, read_block, u32_at, u64_at};
+use alloc::{format, string::String, vec, vec::Vec};
+use block::BlockDevice;
+use core::fmt;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_ENTRY_SIZE: usize = 128;
+const MAX_ENTRIES: u32 = 1024;
+const NAME_OFFSET: usize = 56;
+const NAME_LEN: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const fn is_zero(&self) -> bool {
+        u128::from_ne_bytes(self.0) == 0
+    }
+}
+
+// The first three groups are stored little-endian
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        b[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+struct Header {
+    entries_lba: u64,
+    entry_count: u32,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+// The primary table follows the protective MBR, a backup copy ends in the last block
+pub fn parse(device: &dyn BlockDevice) -> Result<Vec<PartitionInfo>, Error> {
+    read_table(device, 1).or_else(|_| read_table(device, device.block_count() - 1))
+}
+
+fn read_table(device: &dyn BlockDevice, header_lba: u64) -> Result<Vec<PartitionInfo>, Error> {
+    let header = read_header(device, header_lba)?;
+    let block_size = device.block_size();
+    let len = header.entry_count as usize * header.entry_size;
+    let mut data = vec![0u8; len.div_ceil(block_size) * block_size];
+    device
+        .read_blocks(header.entries_lba, &mut data)
+        .map_err(Error::Device)?;
+    if crc32(&data[..len]) != header.entries_crc {
+        return Err(Error::Corrupt("GPT entry array checksum mismatch"));
+    }
+
+    let mut partitions = Vec::new();
+    for (index, raw) in data[..len].chunks_exact(header.entry_size).enumerate() {
+        let type_guid = Guid(raw[..16].try_into().unwrap());
+        if type_guid.is_zero() {
+            continue;
+        }
+        let (first, last) = (u64_at(raw, 32), u64_at(raw, 40));
+        if first == 0 || last < first || last >= device.block_count() {
+            return Err(Error::Corrupt("Partition outside the disk"));
+        }
+
+        let name = raw[NAME_OFFSET..NAME_OFFSET + NAME_LEN * 2]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0);
+        partitions.push(PartitionInfo {
+            number: index as u32 + 1,
+            start: first,
+            count: last - first + 1,
+            kind: PartitionKind::Gpt {
+                type_guid,
+                name: char::decode_utf16(name)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect::<String>(),
+            },
+        });
+    }
+    Ok(partitions)
+}
+
+fn read_header(device: &dyn BlockDevice, lba: u64) -> Result<Header, Error> {
+    let mut sector = read_block(device, lba)?;
+    if &sector[..8] != SIGNATURE {
+        return Err(Error::Corrupt("Missing GPT signature"));
+    }
+
+    let header_size = u32_at(&sector, 12) as usize;
+    if !(MIN_HEADER_SIZE..=sector.len()).contains(&header_size) {
+        return Err(Error::Corrupt("Invalid GPT header size"));
+    }
+    // The checksum covers the header with its own checksum field zeroed
+    let header_crc = u32_at(&sector, 16);
+    sector[16..20].fill(0);
+    if crc32(&sector[..header_size]) != header_crc {
+        return Err(Error::Corrupt("GPT header checksum mismatch"));
+    }
+
+    let header = Header {
+        entries_lba: u64_at(&sector, 72),
+        entry_count: u32_at(&sector, 80),
+        entry_size: u32_at(&sector, 84) as usize,
+        entries_crc: u32_at(&sector, 88),
+    };
+    if u64_at(&sector, 24) != lba
+        || header.entry_count > MAX_ENTRIES
+        || header.entry_size < MIN_ENTRY_SIZE
+        || !header.entry_size.is_power_of_two()
+    {
+        return Err(Error::Corrupt("Invalid GPT header"));
+    }
+    Ok(header)
+}
+
+pub fn type_name(guid: &Guid) -> &'static str {
+    match format!("{}", guid).as_str() {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System",
+        "21686148-6449-6E6F-744E-656564454649" => "BIOS boot",
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Microsoft basic data",
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem",
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap",
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => "Linux LVM",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,120 @@
+#![no_std]
+extern crate alloc;
+
+mod crc32;
+mod gpt;
+mod mbr;
+
+pub use gpt::Guid;
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use block::BlockDevice;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Device(block::Error),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Device(error) => write!(f, "{}", error),
+            Error::Corrupt(message) => f.write_str(message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PartitionKind {
+    Mbr(u8),
+    Gpt { type_guid: Guid, name: String },
+}
+
+impl PartitionKind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            PartitionKind::Mbr(kind) => mbr::type_name(*kind),
+            PartitionKind::Gpt { type_guid, .. } => gpt::type_name(type_guid),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    pub number: u32,
+    pub start: u64,
+    pub count: u64,
+    pub kind: PartitionKind,
+}
+
+// Reads the partition table of a disk; a disk without one has no partitions
+pub fn scan(device: &dyn BlockDevice) -> Result<Vec<PartitionInfo>, Error> {
+    if device.block_count() < 2 {
+        return Ok(Vec::new());
+    }
+    let sector = read_block(device, 0)?;
+    if !mbr::has_signature(&sector) {
+        return Ok(Vec::new());
+    }
+    match mbr::is_protective(&sector) {
+        true => gpt::parse(device),
+        false => mbr::parse(device, &sector),
+    }
+}
+
+// A window of blocks on another device
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    start: u64,
+    count: u64,
+}
+
+impl Partition {
+    pub fn new(device: Arc<dyn BlockDevice>, info: &PartitionInfo) -> Self {
+        Partition {
+            device,
+            start: info.start,
+            count: info.count,
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> block::Result<()> {
+        self.check_request(lba, buf.len())?;
+        self.device.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> block::Result<()> {
+        self.check_request(lba, buf.len())?;
+        self.device.write_blocks(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> block::Result<()> {
+        self.device.flush()
+    }
+}
+
+fn read_block(device: &dyn BlockDevice, lba: u64) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0u8; device.block_size()];
+    device.read_blocks(lba, &mut data).map_err(Error::Device)?;
+    Ok(data)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,115 @@
+use crate::{Error, PartitionInfo, PartitionKind, read_block, u32_at};
+use alloc::vec::Vec;
+use block::BlockDevice;
+
+const ENTRIES_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+pub const PROTECTIVE_TYPE: u8 = 0xEE;
+// Logical partitions are numbered after the four primary slots
+const FIRST_LOGICAL: u32 = 5;
+const MAX_LOGICAL: u32 = 128;
+
+struct Entry {
+    kind: u8,
+    start: u64,
+    count: u64,
+}
+
+pub fn has_signature(sector: &[u8]) -> bool {
+    sector[510] == 0x55 && sector[511] == 0xAA
+}
+
+fn entries(sector: &[u8]) -> impl Iterator<Item = Entry> + '_ {
+    sector[ENTRIES_OFFSET..ENTRIES_OFFSET + 4 * ENTRY_SIZE]
+        .chunks_exact(ENTRY_SIZE)
+        .map(|raw| Entry {
+            kind: raw[4],
+            start: u32_at(raw, 8) as u64,
+            count: u32_at(raw, 12) as u64,
+        })
+}
+
+fn is_extended(kind: u8) -> bool {
+    matches!(kind, 0x05 | 0x0F | 0x85)
+}
+
+pub fn is_protective(sector: &[u8]) -> bool {
+    entries(sector).any(|entry| entry.kind == PROTECTIVE_TYPE)
+}
+
+pub fn parse(device: &dyn BlockDevice, sector: &[u8]) -> Result<Vec<PartitionInfo>, Error> {
+    let mut partitions = Vec::new();
+    for (index, entry) in entries(sector).enumerate() {
+        if entry.kind == 0 || entry.count == 0 {
+            continue;
+        }
+        check_bounds(device, entry.start, entry.count)?;
+        if is_extended(entry.kind) {
+            parse_extended(device, entry.start, &mut partitions)?;
+        } else {
+            partitions.push(PartitionInfo {
+                number: index as u32 + 1,
+                start: entry.start,
+                count: entry.count,
+                kind: PartitionKind::Mbr(entry.kind),
+            });
+        }
+    }
+    partitions.sort_by_key(|partition| partition.number);
+    Ok(partitions)
+}
+
+// Each extended boot record holds one logical partition and a link to the next record.
+// The partition is relative to its own record, the link to the start of the extended partition
+fn parse_extended(
+    device: &dyn BlockDevice,
+    extended_start: u64,
+    partitions: &mut Vec<PartitionInfo>,
+) -> Result<(), Error> {
+    let mut record = extended_start;
+    for number in FIRST_LOGICAL..FIRST_LOGICAL + MAX_LOGICAL {
+        let sector = read_block(device, record)?;
+        if !has_signature(&sector) {
+            return Err(Error::Corrupt("Extended boot record without signature"));
+        }
+
+        let mut entries = entries(&sector);
+        let (logical, next) = (entries.next().unwrap(), entries.next().unwrap());
+        if logical.kind != 0 && logical.count != 0 {
+            check_bounds(device, record + logical.start, logical.count)?;
+            partitions.push(PartitionInfo {
+                number,
+                start: record + logical.start,
+                count: logical.count,
+                kind: PartitionKind::Mbr(logical.kind),
+            });
+        }
+
+        if !is_extended(next.kind) || next.start == 0 {
+            return Ok(());
+        }
+        record = extended_start + next.start;
+    }
+    Err(Error::Corrupt("Too many logical partitions"))
+}
+
+fn check_bounds(device: &dyn BlockDevice, start: u64, count: u64) -> Result<(), Error> {
+    match start.checked_add(count) {
+        Some(end) if start > 0 && end <= device.block_count() => Ok(()),
+        _ => Err(Error::Corrupt("Partition outside the disk")),
+    }
+}
+
+pub fn type_name(kind: u8) -> &'static str {
+    match kind {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0E => "FAT16",
+        0x0B | 0x0C => "FAT32",
+        0x07 => "NTFS/exFAT",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x8E => "Linux LVM",
+        0xEF => "EFI System",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::PciAddress;
+use core::fmt;
+
+const BAR0: u8 = 0x10;
+const IO_SPACE: u32 = 0x1;
+const MEMORY_TYPE_64: u32 = 0x4;
+const PREFETCHABLE: u32 = 0x8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64: bool,
+    },
+    Io {
+        port: u16,
+        size: u32,
+    },
+}
+
+impl Bar {
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory { size, .. } => size,
+            Bar::Io { size, .. } => size as u64,
+        }
+    }
+
+    pub fn memory_address(&self) -> Option<u64> {
+        match *self {
+            Bar::Memory { address, .. } => Some(address),
+            Bar::Io { .. } => None,
+        }
+    }
+
+    pub fn io_port(&self) -> Option<u16> {
+        match *self {
+            Bar::Io { port, .. } => Some(port),
+            Bar::Memory { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                is_64,
+            } => write!(
+                f,
+                "Memory at {:#x} ({}-bit, {}prefetchable, {} bytes)",
+                address,
+                if is_64 { 64 } else { 32 },
+                if prefetchable { "" } else { "non-" },
+                size
+            ),
+            Bar::Io { port, size } => write!(f, "I/O ports at {:#x} ({} bytes)", port, size),
+        }
+    }
+}
+
+// Writes all ones to a register and reads back which address bits the device implements
+fn probe_mask(address: &PciAddress, offset: u8) -> u32 {
+    let original = address.read_u32(offset);
+    address.write_u32(offset, 0xFFFF_FFFF);
+    let mask = address.read_u32(offset);
+    address.write_u32(offset, original);
+    mask
+}
+
+// Decodes the BARs of a function. A 64-bit BAR takes two slots, leaving the upper one empty.
+// Decoding must be disabled by the caller while the registers hold the probe pattern
+pub fn read_bars(address: &PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let mut index = 0;
+    while index < count {
+        let offset = BAR0 + index as u8 * 4;
+        let raw = address.read_u32(offset);
+
+        // A BAR whose address bits cannot be set is not implemented
+        if raw & IO_SPACE != 0 {
+            let mask = probe_mask(address, offset) & !0x3;
+            if mask != 0 {
+                bars[index] = Some(Bar::Io {
+                    port: (raw & !0x3) as u16,
+                    size: (!mask & 0xFFFF) + 1,
+                });
+            }
+            index += 1;
+            continue;
+        }
+
+        let is_64 = raw & 0x6 == MEMORY_TYPE_64 && index + 1 < count;
+        let mut address_bits = (raw & !0xF) as u64;
+        let mut mask = (probe_mask(address, offset) & !0xF) as u64;
+        if is_64 {
+            address_bits |= (address.read_u32(offset + 4) as u64) << 32;
+            mask |= (probe_mask(address, offset + 4) as u64) << 32;
+        } else if mask != 0 {
+            mask |= 0xFFFF_FFFF_0000_0000;
+        }
+
+        if mask != 0 {
+            bars[index] = Some(Bar::Memory {
+                address: address_bits,
+                size: (!mask).wrapping_add(1),
+                prefetchable: raw & PREFETCHABLE != 0,
+                is_64,
+            });
+        }
+        index += if is_64 { 2 } else { 1 };
+    }
+    bars
+}
@@ -0,0 +1,105 @@
+use crate::config::PciAddress;
+use alloc::vec::Vec;
+
+const STATUS: u8 = 0x06;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const CAPABILITIES_POINTER: u8 = 0x34;
+// Bounds the walk in case a broken device links the list into a loop
+const MAX_CAPABILITIES: usize = 48;
+
+pub const ID_POWER_MANAGEMENT: u8 = 0x01;
+pub const ID_MSI: u8 = 0x05;
+pub const ID_VENDOR_SPECIFIC: u8 = 0x09;
+pub const ID_PCI_EXPRESS: u8 = 0x10;
+pub const ID_MSI_X: u8 = 0x11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Msi {
+        offset: u8,
+        is_64: bool,
+        per_vector_mask: bool,
+        vectors: u8,
+        enabled: bool,
+    },
+    MsiX {
+        offset: u8,
+        table_size: u16,
+        table_bar: u8,
+        table_offset: u32,
+        pba_bar: u8,
+        pba_offset: u32,
+        enabled: bool,
+    },
+    Other {
+        id: u8,
+        offset: u8,
+    },
+}
+
+impl Capability {
+    pub fn id(&self) -> u8 {
+        match *self {
+            Capability::Msi { .. } => ID_MSI,
+            Capability::MsiX { .. } => ID_MSI_X,
+            Capability::Other { id, .. } => id,
+        }
+    }
+
+    pub fn offset(&self) -> u8 {
+        match *self {
+            Capability::Msi { offset, .. }
+            | Capability::MsiX { offset, .. }
+            | Capability::Other { offset, .. } => offset,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.id() {
+            ID_POWER_MANAGEMENT => "Power Management",
+            ID_MSI => "MSI",
+            ID_VENDOR_SPECIFIC => "Vendor Specific",
+            ID_PCI_EXPRESS => "PCI Express",
+            ID_MSI_X => "MSI-X",
+            _ => "Unknown",
+        }
+    }
+}
+
+pub fn read_capabilities(address: &PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if address.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+
+    let mut offset = address.read_u8(CAPABILITIES_POINTER) & 0xFC;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let id = address.read_u8(offset);
+        let control = address.read_u16(offset + 2);
+        capabilities.push(match id {
+            ID_MSI => Capability::Msi {
+                offset,
+                is_64: control & (1 << 7) != 0,
+                per_vector_mask: control & (1 << 8) != 0,
+                vectors: 1 << ((control >> 1) & 0x7),
+                enabled: control & 1 != 0,
+            },
+            ID_MSI_X => {
+                let table = address.read_u32(offset + 4);
+                let pba = address.read_u32(offset + 8);
+                Capability::MsiX {
+                    offset,
+                    table_size: (control & 0x7FF) + 1,
+                    table_bar: (table & 0x7) as u8,
+                    table_offset: table & !0x7,
+                    pba_bar: (pba & 0x7) as u8,
+                    pba_offset: pba & !0x7,
+                    enabled: control & (1 << 15) != 0,
+                }
+            }
+            _ => Capability::Other { id, offset },
+        });
+        offset = address.read_u8(offset + 1) & 0xFC;
+    }
+    capabilities
+}
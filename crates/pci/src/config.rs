@@ -0,0 +1,63 @@
+use core::fmt;
+use custom_types::spin_lock::SpinLock;
+use x86_64::instructions::port::Port;
+
+// Configuration mechanism #1: select a register through the address port, then use the data port
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const ENABLE: u32 = 1 << 31;
+
+// Both ports form a single register window, so accesses must not interleave
+static CONFIG_LOCK: SpinLock<()> = SpinLock::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn select(&self, offset: u8) {
+        let address = ENABLE
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xFC) as u32;
+        unsafe { Port::new(CONFIG_ADDRESS).write(address) };
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let _guard = CONFIG_LOCK.lock();
+        self.select(offset);
+        unsafe { Port::new(CONFIG_DATA).read() }
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        let _guard = CONFIG_LOCK.lock();
+        self.select(offset);
+        unsafe { Port::new(CONFIG_DATA).write(value) };
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
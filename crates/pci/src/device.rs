@@ -0,0 +1,112 @@
+use crate::{
+    bar::{Bar, read_bars},
+    capability::{Capability, read_capabilities},
+    config::PciAddress,
+};
+use alloc::vec::Vec;
+
+const VENDOR_ID: u8 = 0x00;
+const DEVICE_ID: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+const REVISION: u8 = 0x08;
+const PROG_IF: u8 = 0x09;
+const SUBCLASS: u8 = 0x0A;
+const CLASS: u8 = 0x0B;
+const HEADER_TYPE: u8 = 0x0E;
+const SECONDARY_BUS: u8 = 0x19;
+const INTERRUPT_LINE: u8 = 0x3C;
+const INTERRUPT_PIN: u8 = 0x3D;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+
+pub const HEADER_GENERAL: u8 = 0x00;
+pub const HEADER_PCI_BRIDGE: u8 = 0x01;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+pub const NO_VENDOR: u16 = 0xFFFF;
+
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+}
+
+impl PciDevice {
+    pub fn read(address: PciAddress) -> Option<Self> {
+        if !Self::exists(&address) {
+            return None;
+        }
+
+        let header_type = address.read_u8(HEADER_TYPE) & !HEADER_MULTIFUNCTION;
+        let bar_count = match header_type {
+            HEADER_GENERAL => 6,
+            HEADER_PCI_BRIDGE => 2,
+            _ => 0,
+        };
+
+        // Sizing the BARs briefly changes their addresses, so decoding is off meanwhile
+        let command = address.read_u16(COMMAND);
+        write_command(&address, command & !(COMMAND_IO | COMMAND_MEMORY));
+        let bars = read_bars(&address, bar_count);
+        write_command(&address, command);
+
+        Some(PciDevice {
+            address,
+            vendor_id: address.read_u16(VENDOR_ID),
+            device_id: address.read_u16(DEVICE_ID),
+            class: address.read_u8(CLASS),
+            subclass: address.read_u8(SUBCLASS),
+            prog_if: address.read_u8(PROG_IF),
+            revision: address.read_u8(REVISION),
+            header_type,
+            interrupt_line: address.read_u8(INTERRUPT_LINE),
+            interrupt_pin: address.read_u8(INTERRUPT_PIN),
+            bars,
+            capabilities: read_capabilities(&address),
+        })
+    }
+
+    pub fn exists(address: &PciAddress) -> bool {
+        address.read_u16(VENDOR_ID) != NO_VENDOR
+    }
+
+    pub fn is_multifunction(address: &PciAddress) -> bool {
+        address.read_u8(HEADER_TYPE) & HEADER_MULTIFUNCTION != 0
+    }
+
+    pub fn secondary_bus(&self) -> Option<u8> {
+        (self.header_type == HEADER_PCI_BRIDGE).then(|| self.address.read_u8(SECONDARY_BUS))
+    }
+
+    pub fn command(&self) -> u16 {
+        self.address.read_u16(COMMAND)
+    }
+
+    pub fn enable(&self, bits: u16) {
+        write_command(&self.address, self.command() | bits);
+    }
+
+    pub fn capability(&self, id: u8) -> Option<&Capability> {
+        self.capabilities
+            .iter()
+            .find(|capability| capability.id() == id)
+    }
+}
+
+// The status register shares the dword and clears bits written as one, so zeroes go there
+fn write_command(address: &PciAddress, command: u16) {
+    address.write_u32(COMMAND, command as u32);
+}
@@ -0,0 +1,81 @@
+use crate::{config::PciAddress, device::PciDevice};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId {
+    pub vendor: u16,
+    pub device: u16,
+}
+
+impl DeviceId {
+    pub const fn new(vendor: u16, device: u16) -> Self {
+        DeviceId { vendor, device }
+    }
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub ids: &'static [DeviceId],
+    pub probe: fn(&PciDevice) -> Result<(), &'static str>,
+}
+
+impl Driver {
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        self.ids
+            .iter()
+            .any(|id| id.vendor == device.vendor_id && id.device == device.device_id)
+    }
+}
+
+pub struct ProbeResult {
+    pub address: PciAddress,
+    pub driver: &'static str,
+    pub result: Result<(), &'static str>,
+}
+
+#[derive(Default)]
+pub struct DriverRegistry {
+    drivers: Vec<&'static Driver>,
+    bound: BTreeMap<PciAddress, &'static str>,
+}
+
+impl DriverRegistry {
+    pub const fn new() -> Self {
+        DriverRegistry {
+            drivers: Vec::new(),
+            bound: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, driver: &'static Driver) {
+        self.drivers.push(driver);
+    }
+
+    // Offers every unbound device to the first matching driver
+    pub fn probe(&mut self, devices: &[PciDevice]) -> Vec<ProbeResult> {
+        let mut results = Vec::new();
+        for device in devices {
+            if self.bound.contains_key(&device.address) {
+                continue;
+            }
+            let Some(driver) = self.drivers.iter().find(|driver| driver.matches(device)) else {
+                continue;
+            };
+
+            let result = (driver.probe)(device);
+            if result.is_ok() {
+                self.bound.insert(device.address, driver.name);
+            }
+            results.push(ProbeResult {
+                address: device.address,
+                driver: driver.name,
+                result,
+            });
+        }
+        results
+    }
+
+    pub fn bound_driver(&self, address: &PciAddress) -> Option<&'static str> {
+        self.bound.get(address).copied()
+    }
+}
@@ -0,0 +1,106 @@
+#![no_std]
+extern crate alloc;
+
+mod bar;
+mod capability;
+mod config;
+mod device;
+mod driver;
+
+pub use bar::Bar;
+pub use capability::{
+    Capability, ID_MSI, ID_MSI_X, ID_PCI_EXPRESS, ID_POWER_MANAGEMENT, ID_VENDOR_SPECIFIC,
+};
+pub use config::PciAddress;
+pub use device::{
+    COMMAND_BUS_MASTER, COMMAND_INTERRUPT_DISABLE, COMMAND_IO, COMMAND_MEMORY, PciDevice,
+};
+pub use driver::{DeviceId, Driver, DriverRegistry, ProbeResult};
+
+use alloc::vec::Vec;
+
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+// Walks the hierarchy from the host bridges down through every PCI-to-PCI bridge
+pub fn enumerate() -> Vec<PciDevice> {
+    let mut scan = Scan {
+        devices: Vec::new(),
+        visited: [false; 256],
+    };
+
+    // A multi-function host bridge means one host controller, and root bus, per function
+    let host = PciAddress::new(0, 0, 0);
+    if !PciDevice::is_multifunction(&host) {
+        scan.bus(0);
+    } else {
+        for function in 0..FUNCTIONS_PER_DEVICE {
+            if PciDevice::exists(&PciAddress::new(0, 0, function)) {
+                scan.bus(function);
+            }
+        }
+    }
+    scan.devices
+}
+
+struct Scan {
+    devices: Vec<PciDevice>,
+    visited: [bool; 256],
+}
+
+impl Scan {
+    fn bus(&mut self, bus: u8) {
+        if core::mem::replace(&mut self.visited[bus as usize], true) {
+            return;
+        }
+        for device in 0..DEVICES_PER_BUS {
+            let address = PciAddress::new(bus, device, 0);
+            if !self.function(address) {
+                continue;
+            }
+            if PciDevice::is_multifunction(&address) {
+                for function in 1..FUNCTIONS_PER_DEVICE {
+                    self.function(PciAddress::new(bus, device, function));
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, address: PciAddress) -> bool {
+        let Some(device) = PciDevice::read(address) else {
+            return false;
+        };
+        let secondary_bus = device.secondary_bus();
+        self.devices.push(device);
+        if let Some(bus) = secondary_bus {
+            self.bus(bus);
+        }
+        true
+    }
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        (0xFF, _) => "Unassigned class",
+        _ => "Unknown device",
+    }
+}
@@ -0,0 +1,53 @@
+use crate::chacha::{self, BLOCK_SIZE};
+
+// Output and reseeding use different nonces, so their keystreams never overlap
+const OUTPUT_NONCE: u64 = 0;
+const RESEED_NONCE: u64 = u64::MAX;
+
+// ChaCha20 with fast key erasure: every request first derives the key for the next one,
+// so a later compromise of the state does not reveal earlier output
+pub struct Generator {
+    key: [u32; 8],
+    seeded: bool,
+}
+
+impl Generator {
+    pub const fn new() -> Self {
+        Generator {
+            key: [0; 8],
+            seeded: false,
+        }
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.seeded
+    }
+
+    // Folds the seed into the key eight words at a time, running the block function after each
+    pub fn reseed(&mut self, seed: &[u32]) {
+        for chunk in seed.chunks(8) {
+            for (key, word) in self.key.iter_mut().zip(chunk) {
+                *key ^= word;
+            }
+            let block = chacha::block(&self.key, 0, RESEED_NONCE);
+            self.key.copy_from_slice(&block[..8]);
+        }
+        self.seeded = true;
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        let key = self.key;
+        let next = chacha::block(&key, 0, OUTPUT_NONCE);
+        for (i, chunk) in buf.chunks_mut(BLOCK_SIZE).enumerate() {
+            let block = chacha::block_bytes(&key, i as u64 + 1, OUTPUT_NONCE);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        self.key.copy_from_slice(&next[..8]);
+    }
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,54 @@
+use x86_64::instructions::random::RdRand;
+
+// Both instructions may fail transiently while the hardware refills
+const RETRIES: usize = 10;
+const RDSEED_LEAF: u32 = 7;
+const RDSEED_BIT: u32 = 1 << 18;
+
+pub fn rdrand() -> Option<u64> {
+    let rdrand = RdRand::new()?;
+    (0..RETRIES).find_map(|_| rdrand.get_u64())
+}
+
+pub fn has_rdrand() -> bool {
+    RdRand::new().is_some()
+}
+
+pub fn has_rdseed() -> bool {
+    let cpuid = core::arch::x86_64::__cpuid_count(RDSEED_LEAF, 0);
+    cpuid.ebx & RDSEED_BIT != 0
+}
+
+pub fn rdseed() -> Option<u64> {
+    if !has_rdseed() {
+        return None;
+    }
+    (0..RETRIES).find_map(|_| {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!(
+                "rdseed {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack)
+            );
+        }
+        (ok != 0).then_some(value)
+    })
+}
+
+// The time a short memory-bound loop takes varies with caches, pipelines and interrupts.
+// Only the low bits of each delta carry anything, so the caller credits little for them
+pub fn jitter(samples: usize, mut sample: impl FnMut(u64)) {
+    let mut scratch = [0u64; 64];
+    let mut previous = datetime::rdtsc();
+    for i in 0..samples {
+        let index = (previous as usize ^ i) % scratch.len();
+        scratch[index] = scratch[index].wrapping_add(previous).rotate_left(7);
+        let now = datetime::rdtsc();
+        sample(now.wrapping_sub(previous) ^ core::hint::black_box(scratch[index]));
+        previous = now;
+    }
+}
@@ -0,0 +1,165 @@
+#![no_std]
+extern crate alloc;
+
+mod chacha;
+mod generator;
+mod hardware;
+mod pool;
+
+pub use generator::Generator;
+pub use hardware::{has_rdrand, has_rdseed};
+pub use pool::{EntropyPool, MAX_ENTROPY_BITS};
+
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use custom_types::spin_lock::SpinLock;
+use datetime::TICKS;
+
+// Bytes requested from a hardware source whenever it is asked for entropy
+const SOURCE_BYTES: usize = 64;
+// Words taken from RDSEED/RDRAND per reseed, credited at half their size
+const CPU_WORDS: usize = 8;
+// Timing samples per reseed, credited at one bit for every eight
+const JITTER_SAMPLES: usize = 512;
+// Interrupt timings are folded together and handed to the pool in batches, worth one bit each
+const INTERRUPT_BATCH: usize = 64;
+// Milliseconds between reseeds of the output generator
+const RESEED_INTERVAL: usize = 60_000;
+
+type Fill = Box<dyn Fn(&mut [u8]) -> usize + Send + Sync>;
+
+pub struct Source {
+    pub name: &'static str,
+    fill: Fill,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub entropy_bits: usize,
+    pub seeded: bool,
+    pub reseeds: usize,
+    pub interrupts: usize,
+    pub rdrand: bool,
+    pub rdseed: bool,
+}
+
+static POOL: SpinLock<EntropyPool> = SpinLock::new(EntropyPool::new());
+static SOURCES: SpinLock<Vec<Source>> = SpinLock::new(Vec::new());
+static GENERATOR: SpinLock<Generator> = SpinLock::new(Generator::new());
+
+static RESEEDS: AtomicUsize = AtomicUsize::new(0);
+static LAST_RESEED: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPT_MIX: AtomicU64 = AtomicU64::new(0);
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn add_entropy(data: &[u8], entropy_bits: usize) {
+    POOL.lock().mix(data, entropy_bits);
+}
+
+pub fn entropy_bits() -> usize {
+    POOL.lock().entropy_bits()
+}
+
+// Called from interrupt handlers, so the pool is only touched when nobody else holds it
+pub fn add_interrupt_timing() {
+    let now = datetime::rdtsc();
+    let mixed = INTERRUPT_MIX.load(Ordering::Relaxed).rotate_left(13) ^ now;
+    INTERRUPT_MIX.store(mixed, Ordering::Relaxed);
+
+    let count = INTERRUPTS.fetch_add(1, Ordering::Relaxed) + 1;
+    if count.is_multiple_of(INTERRUPT_BATCH)
+        && let Some(mut pool) = POOL.try_lock()
+    {
+        pool.mix(&mixed.to_le_bytes(), 1);
+    }
+}
+
+// Hardware sources return the number of bytes they filled and are credited in full
+pub fn register_source(
+    name: &'static str,
+    fill: impl Fn(&mut [u8]) -> usize + Send + Sync + 'static,
+) -> usize {
+    let source = Source {
+        name,
+        fill: Box::new(fill),
+    };
+    let collected = collect(&source);
+    SOURCES.lock().push(source);
+    collected
+}
+
+pub fn sources() -> Vec<&'static str> {
+    SOURCES.lock().iter().map(|source| source.name).collect()
+}
+
+// Pulls fresh input from every registered source; returns the number of bytes mixed in
+pub fn collect_sources() -> usize {
+    SOURCES.lock().iter().map(collect).sum()
+}
+
+fn collect(source: &Source) -> usize {
+    let mut buf = [0; SOURCE_BYTES];
+    let count = (source.fill)(&mut buf).min(SOURCE_BYTES);
+    add_entropy(&buf[..count], count * 8);
+    count
+}
+
+fn collect_cpu() {
+    for _ in 0..CPU_WORDS {
+        if let Some(word) = hardware::rdseed().or_else(hardware::rdrand) {
+            add_entropy(&word.to_le_bytes(), 32);
+        }
+    }
+
+    let mut pool = POOL.lock();
+    let mut samples = 0;
+    hardware::jitter(JITTER_SAMPLES, |delta| {
+        pool.mix(&delta.to_le_bytes(), (samples % 8 == 7) as usize);
+        samples += 1;
+    });
+}
+
+// Gathers from every source, then rekeys the generator from the whole pool
+pub fn reseed() {
+    collect_cpu();
+    collect_sources();
+
+    let words = {
+        let mut pool = POOL.lock();
+        let words = *pool.words();
+        pool.take_entropy();
+        words
+    };
+    GENERATOR.lock().reseed(&words);
+    RESEEDS.fetch_add(1, Ordering::Relaxed);
+    LAST_RESEED.store(TICKS.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+pub fn init() {
+    reseed();
+}
+
+pub fn fill(buf: &mut [u8]) {
+    let elapsed = TICKS.load(Ordering::Relaxed) - LAST_RESEED.load(Ordering::Relaxed);
+    if !GENERATOR.lock().is_seeded() || elapsed >= RESEED_INTERVAL {
+        reseed();
+    }
+    GENERATOR.lock().fill(buf);
+}
+
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        entropy_bits: entropy_bits(),
+        seeded: GENERATOR.lock().is_seeded(),
+        reseeds: RESEEDS.load(Ordering::Relaxed),
+        interrupts: INTERRUPTS.load(Ordering::Relaxed),
+        rdrand: has_rdrand(),
+        rdseed: has_rdseed(),
+    }
+}
@@ -0,0 +1,61 @@
+// Input pool mixed with a twisted GFSR, the scheme Linux used for its entropy pools
+const POOL_WORDS: usize = 32;
+const TAPS: [usize; 5] = [26, 19, 14, 7, 1];
+const TWIST: [u32; 8] = [
+    0x00000000, 0x3B6E20C8, 0x76DC4190, 0x4DB26158, 0xEDB88320, 0xD6D6A3E8, 0x9B64C2B0, 0xA00AE278,
+];
+
+pub const MAX_ENTROPY_BITS: usize = POOL_WORDS * 32;
+
+pub struct EntropyPool {
+    words: [u32; POOL_WORDS],
+    position: usize,
+    rotate: u32,
+    entropy_bits: usize,
+}
+
+impl EntropyPool {
+    pub const fn new() -> Self {
+        EntropyPool {
+            words: [0; POOL_WORDS],
+            position: 0,
+            rotate: 0,
+            entropy_bits: 0,
+        }
+    }
+
+    // Mixing never hurts, but only the credited bits count as entropy
+    pub fn mix(&mut self, data: &[u8], entropy_bits: usize) {
+        for &byte in data {
+            self.position = (self.position + POOL_WORDS - 1) % POOL_WORDS;
+            let i = self.position;
+
+            let mut word = (byte as u32).rotate_left(self.rotate) ^ self.words[i];
+            for tap in TAPS {
+                word ^= self.words[(i + tap) % POOL_WORDS];
+            }
+            self.words[i] = (word >> 3) ^ TWIST[(word & 7) as usize];
+            self.rotate = (self.rotate + if i == 0 { 14 } else { 7 }) & 31;
+        }
+        self.entropy_bits = (self.entropy_bits + entropy_bits).min(MAX_ENTROPY_BITS);
+    }
+
+    pub fn entropy_bits(&self) -> usize {
+        self.entropy_bits
+    }
+
+    pub fn words(&self) -> &[u32; POOL_WORDS] {
+        &self.words
+    }
+
+    // Extracting seed material uses up what the pool was credited with
+    pub fn take_entropy(&mut self) -> usize {
+        core::mem::take(&mut self.entropy_bits)
+    }
+}
+
+impl Default for EntropyPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
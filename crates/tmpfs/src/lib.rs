@@ -0,0 +1,351 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use custom_types::spin_lock::SpinLock;
+use datetime::CURRENT_TIME;
+use vfs::{DirEntry, Error, FileOps, FileType, Filesystem, Inode, Metadata, Result};
+
+// Rough heap cost of a node itself, charged so that empty files and directories count too
+const NODE_COST: usize = 128;
+
+struct Usage {
+    limit: usize,
+    used: SpinLock<usize>,
+    next_inode: AtomicU64,
+}
+
+impl Usage {
+    fn charge(&self, bytes: usize) -> Result<()> {
+        let mut used = self.used.lock();
+        if *used + bytes > self.limit {
+            return Err(Error::NoSpace);
+        }
+        *used += bytes;
+        Ok(())
+    }
+
+    fn release(&self, bytes: usize) {
+        *self.used.lock() -= bytes;
+    }
+}
+
+pub struct Tmpfs {
+    usage: Arc<Usage>,
+    root: Arc<TmpfsInode>,
+}
+
+impl Tmpfs {
+    pub fn new(limit: usize) -> Self {
+        let usage = Arc::new(Usage {
+            limit,
+            used: SpinLock::new(0),
+            next_inode: AtomicU64::new(1),
+        });
+        let root = TmpfsInode::new(&usage, Kind::Directory(BTreeMap::new()), 0o1777)
+            .expect("Tmpfs limit is too small for the root directory");
+        Tmpfs { usage, root }
+    }
+
+    pub fn used(&self) -> usize {
+        *self.usage.used.lock()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.usage.limit
+    }
+}
+
+impl Filesystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+enum Kind {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpfsInode>>),
+}
+
+struct Node {
+    kind: Kind,
+    mode: u32,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
+
+pub struct TmpfsInode {
+    usage: Arc<Usage>,
+    number: u64,
+    node: SpinLock<Node>,
+}
+
+fn now() -> u64 {
+    CURRENT_TIME.lock().timestamp()
+}
+
+impl TmpfsInode {
+    fn new(usage: &Arc<Usage>, kind: Kind, mode: u32) -> Result<Arc<Self>> {
+        usage.charge(NODE_COST)?;
+        let time = now();
+        Ok(Arc::new(TmpfsInode {
+            usage: usage.clone(),
+            number: usage.next_inode.fetch_add(1, Ordering::Relaxed),
+            node: SpinLock::new(Node {
+                kind,
+                mode: mode & 0o7777,
+                atime: time,
+                mtime: time,
+                ctime: time,
+            }),
+        }))
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.node.lock().kind {
+            Kind::File(_) => FileType::File,
+            Kind::Directory(_) => FileType::Directory,
+        }
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&self.node.lock().kind, Kind::Directory(entries) if entries.is_empty())
+    }
+
+    fn resize(&self, data: &mut Vec<u8>, size: usize) -> Result<()> {
+        if size > data.len() {
+            let extra = size - data.len();
+            self.usage.charge(extra)?;
+            if data.try_reserve_exact(extra).is_err() {
+                self.usage.release(extra);
+                return Err(Error::NoSpace);
+            }
+        } else {
+            self.usage.release(data.len() - size);
+        }
+        data.resize(size, 0);
+        Ok(())
+    }
+}
+
+impl Drop for TmpfsInode {
+    fn drop(&mut self) {
+        let data = match &self.node.get_mut().kind {
+            Kind::File(data) => data.len(),
+            Kind::Directory(_) => 0,
+        };
+        self.usage.release(NODE_COST + data);
+    }
+}
+
+impl FileOps for TmpfsInode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut node = self.node.lock();
+        let Kind::File(data) = &node.kind else {
+            return Err(Error::IsADirectory);
+        };
+
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        node.atime = now();
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut node = self.node.lock();
+        let Kind::File(data) = &mut node.kind else {
+            return Err(Error::IsADirectory);
+        };
+
+        let start = offset as usize;
+        let end = start.checked_add(buf.len()).ok_or(Error::InvalidArgument)?;
+        if end > data.len() {
+            self.resize(data, end)?;
+        }
+        data[start..end].copy_from_slice(buf);
+
+        let time = now();
+        node.mtime = time;
+        node.ctime = time;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        let mut node = self.node.lock();
+        let Kind::File(data) = &mut node.kind else {
+            return Err(Error::IsADirectory);
+        };
+
+        self.resize(data, size as usize)?;
+        data.shrink_to_fit();
+
+        let time = now();
+        node.mtime = time;
+        node.ctime = time;
+        Ok(())
+    }
+}
+
+impl Inode for TmpfsInode {
+    fn metadata(&self) -> Metadata {
+        let node = self.node.lock();
+        let (file_type, size) = match &node.kind {
+            Kind::File(data) => (FileType::File, data.len() as u64),
+            Kind::Directory(entries) => (FileType::Directory, entries.len() as u64),
+        };
+
+        Metadata {
+            inode: self.number,
+            file_type,
+            size,
+            mode: node.mode,
+            uid: 0,
+            gid: 0,
+            atime: node.atime,
+            mtime: node.mtime,
+            ctime: node.ctime,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        match &self.node.lock().kind {
+            Kind::Directory(entries) => match entries.get(name) {
+                Some(inode) => Ok(inode.clone()),
+                None => Err(Error::NotFound),
+            },
+            Kind::File(_) => Err(Error::NotADirectory),
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let Kind::Directory(entries) = &self.node.lock().kind else {
+            return Err(Error::NotADirectory);
+        };
+
+        Ok(entries
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                inode: inode.number,
+                file_type: inode.file_type(),
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u32) -> Result<Arc<dyn Inode>> {
+        let kind = match file_type {
+            FileType::File => Kind::File(Vec::new()),
+            FileType::Directory => Kind::Directory(BTreeMap::new()),
+            _ => return Err(Error::Unsupported),
+        };
+
+        let mut node = self.node.lock();
+        let Kind::Directory(entries) = &mut node.kind else {
+            return Err(Error::NotADirectory);
+        };
+        if entries.contains_key(name) {
+            return Err(Error::AlreadyExists);
+        }
+
+        let inode = TmpfsInode::new(&self.usage, kind, mode)?;
+        entries.insert(name.to_string(), inode.clone());
+        node.mtime = inode.node.lock().mtime;
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut node = self.node.lock();
+        let Kind::Directory(entries) = &mut node.kind else {
+            return Err(Error::NotADirectory);
+        };
+
+        let inode = entries.get(name).ok_or(Error::NotFound)?;
+        if inode.file_type() == FileType::Directory && !inode.is_empty_dir() {
+            return Err(Error::NotEmpty);
+        }
+        entries.remove(name);
+        node.mtime = now();
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &dyn Inode, new_name: &str) -> Result<()> {
+        let new_parent = (new_parent as &dyn Any)
+            .downcast_ref::<TmpfsInode>()
+            .filter(|parent| Arc::ptr_eq(&parent.usage, &self.usage))
+            .ok_or(Error::CrossDevice)?;
+
+        // Both directories are locked at once, so a rename within one directory must lock it only
+        // once. Otherwise they are locked in address order, so opposite renames cannot deadlock
+        let (mut old_node, mut new_node) = if new_parent.number == self.number {
+            (self.node.lock(), None)
+        } else if core::ptr::from_ref(self) < core::ptr::from_ref(new_parent) {
+            let old_node = self.node.lock();
+            (old_node, Some(new_parent.node.lock()))
+        } else {
+            let new_node = new_parent.node.lock();
+            (self.node.lock(), Some(new_node))
+        };
+
+        let Kind::Directory(old_entries) = &mut old_node.kind else {
+            return Err(Error::NotADirectory);
+        };
+        let inode = old_entries.get(old_name).ok_or(Error::NotFound)?.clone();
+        if inode.number == new_parent.number {
+            return Err(Error::InvalidArgument);
+        }
+
+        let new_entries = match new_node.as_mut().map(|node| &mut node.kind) {
+            Some(Kind::Directory(entries)) => entries,
+            Some(Kind::File(_)) => return Err(Error::NotADirectory),
+            None => &mut *old_entries,
+        };
+
+        if let Some(existing) = new_entries.get(new_name) {
+            // Renaming an entry onto itself leaves it in place
+            if existing.number == inode.number {
+                return Ok(());
+            }
+            // Replacing an ancestor: it still holds the entry being moved, and it is locked already
+            if existing.number == self.number || existing.number == new_parent.number {
+                return Err(Error::NotEmpty);
+            }
+            match (inode.file_type(), existing.file_type()) {
+                (FileType::Directory, FileType::Directory) if !existing.is_empty_dir() => {
+                    return Err(Error::NotEmpty);
+                }
+                (FileType::Directory, FileType::File) => return Err(Error::NotADirectory),
+                (FileType::File, FileType::Directory) => return Err(Error::IsADirectory),
+                _ => {}
+            }
+        }
+        new_entries.insert(new_name.to_string(), inode.clone());
+
+        let Kind::Directory(old_entries) = &mut old_node.kind else {
+            unreachable!();
+        };
+        old_entries.remove(old_name);
+
+        let time = now();
+        old_node.mtime = time;
+        if let Some(node) = new_node.as_mut() {
+            node.mtime = time;
+        }
+        inode.node.lock().ctime = time;
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    NotEmpty,
+    ReadOnly,
+    PermissionDenied,
+    InvalidArgument,
+    BadFileDescriptor,
+    TooManyOpenFiles,
+    NoSpace,
+    Busy,
+    CrossDevice,
+    TooManyLinks,
+    NameTooLong,
+    Unsupported,
+    NotExecutable,
+    OutOfMemory,
+    BadAddress,
+    Io,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl Error {
+    pub fn errno(self) -> i64 {
+        use Error::*;
+        match self {
+            NotFound => 2,
+            Io => 5,
+            NotExecutable => 8,
+            BadFileDescriptor => 9,
+            OutOfMemory => 12,
+            PermissionDenied => 13,
+            BadAddress => 14,
+            Busy => 16,
+            AlreadyExists => 17,
+            CrossDevice => 18,
+            NotADirectory => 20,
+            IsADirectory => 21,
+            InvalidArgument => 22,
+            TooManyOpenFiles => 24,
+            NoSpace => 28,
+            ReadOnly => 30,
+            NameTooLong => 36,
+            NotEmpty => 39,
+            TooManyLinks => 40,
+            Unsupported => 95,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        use Error::*;
+        match self {
+            NotFound => "No such file or directory",
+            NotADirectory => "Not a directory",
+            IsADirectory => "Is a directory",
+            AlreadyExists => "File exists",
+            NotEmpty => "Directory not empty",
+            ReadOnly => "Read-only file system",
+            PermissionDenied => "Permission denied",
+            InvalidArgument => "Invalid argument",
+            BadFileDescriptor => "Bad file descriptor",
+            TooManyOpenFiles => "Too many open files",
+            NoSpace => "No space left on device",
+            Busy => "Device or resource busy",
+            CrossDevice => "Invalid cross-device link",
+            TooManyLinks => "Too many levels of symbolic links",
+            NameTooLong => "File name too long",
+            Unsupported => "Operation not supported",
+            NotExecutable => "Exec format error",
+            OutOfMemory => "Cannot allocate memory",
+            BadAddress => "Bad address",
+            Io => "Input/output error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
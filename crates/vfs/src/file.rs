@@ -0,0 +1,199 @@
+use crate::{
+    error::{Error, Result},
+    inode::{DirEntry, FileType, Inode, Metadata},
+};
+use alloc::{sync::Arc, vec::Vec};
+use custom_types::spin_lock::SpinLock;
+
+const MAX_OPEN_FILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const READ_ONLY: OpenFlags = OpenFlags(0);
+    pub const WRITE_ONLY: OpenFlags = OpenFlags(0o1);
+    pub const READ_WRITE: OpenFlags = OpenFlags(0o2);
+    pub const CREATE: OpenFlags = OpenFlags(0o100);
+    pub const TRUNCATE: OpenFlags = OpenFlags(0o1000);
+    pub const APPEND: OpenFlags = OpenFlags(0o2000);
+
+    const ACCESS_MODE: u32 = 0o3;
+
+    pub fn contains(self, flags: OpenFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn readable(self) -> bool {
+        self.0 & Self::ACCESS_MODE != Self::WRITE_ONLY.0
+    }
+
+    pub fn writable(self) -> bool {
+        matches!(self.0 & Self::ACCESS_MODE, 0o1 | 0o2)
+    }
+}
+
+impl core::ops::BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, rhs: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whence {
+    Set,
+    Current,
+    End,
+}
+
+impl TryFrom<u64> for Whence {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self> {
+        match value {
+            0 => Ok(Whence::Set),
+            1 => Ok(Whence::Current),
+            2 => Ok(Whence::End),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+pub struct OpenFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: SpinLock<u64>,
+}
+
+impl OpenFile {
+    pub fn new(inode: Arc<dyn Inode>, flags: OpenFlags) -> Self {
+        OpenFile {
+            inode,
+            flags,
+            offset: SpinLock::new(0),
+        }
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.inode.metadata()
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.flags.readable() {
+            return Err(Error::BadFileDescriptor);
+        }
+        if self.metadata().file_type == FileType::Directory {
+            return Err(Error::IsADirectory);
+        }
+
+        let mut offset = self.offset.lock();
+        let read = self.inode.read_at(*offset, buf)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        if !self.flags.writable() {
+            return Err(Error::BadFileDescriptor);
+        }
+
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.metadata().size;
+        }
+        let written = self.inode.write_at(*offset, buf)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    pub fn truncate(&self, size: u64) -> Result<()> {
+        if !self.flags.writable() {
+            return Err(Error::InvalidArgument);
+        }
+        self.inode.truncate(size)
+    }
+
+    pub fn seek(&self, offset: i64, whence: Whence) -> Result<u64> {
+        let mut current = self.offset.lock();
+        let base = match whence {
+            Whence::Set => 0,
+            Whence::Current => *current as i64,
+            Whence::End => self.metadata().size as i64,
+        };
+
+        let new_offset = base.checked_add(offset).ok_or(Error::InvalidArgument)?;
+        if new_offset < 0 {
+            return Err(Error::InvalidArgument);
+        }
+        *current = new_offset as u64;
+        Ok(*current)
+    }
+
+    // Directory streams use the offset as the index of the next entry
+    pub fn next_entry(&self) -> Result<Option<DirEntry>> {
+        let mut offset = self.offset.lock();
+        let entry = self.inode.readdir()?.into_iter().nth(*offset as usize);
+        if entry.is_some() {
+            *offset += 1;
+        }
+        Ok(entry)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FdTable {
+    files: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        FdTable { files: Vec::new() }
+    }
+
+    pub fn insert(&mut self, file: OpenFile) -> Result<usize> {
+        let file = Some(Arc::new(file));
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = file;
+                Ok(fd)
+            }
+            None if self.files.len() < MAX_OPEN_FILES => {
+                self.files.push(file);
+                Ok(self.files.len() - 1)
+            }
+            None => Err(Error::TooManyOpenFiles),
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>> {
+        self.files
+            .get(fd)
+            .and_then(Option::clone)
+            .ok_or(Error::BadFileDescriptor)
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<OpenFile>> {
+        self.files
+            .get_mut(fd)
+            .and_then(Option::take)
+            .ok_or(Error::BadFileDescriptor)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.iter().filter(|file| file.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::{Error, Result};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+impl FileType {
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            FileType::File => 0o100000,
+            FileType::Directory => 0o040000,
+            FileType::Symlink => 0o120000,
+            FileType::CharDevice => 0o020000,
+            FileType::BlockDevice => 0o060000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub inode: u64,
+    pub file_type: FileType,
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+pub trait FileOps: Send + Sync {
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::Unsupported)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        Err(Error::ReadOnly)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// `Any` lets a filesystem recognise its own inodes, e.g. the target directory of a rename
+pub trait Inode: FileOps + Any {
+    fn metadata(&self) -> Metadata;
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> {
+        Err(Error::NotADirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Err(Error::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType, _mode: u32) -> Result<Arc<dyn Inode>> {
+        Err(Error::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn rename(&self, _old_name: &str, _new_parent: &dyn Inode, _new_name: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn read_link(&self) -> Result<String> {
+        Err(Error::InvalidArgument)
+    }
+}
+
+pub trait Filesystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Stat {
+    pub inode: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl From<Metadata> for Stat {
+    fn from(metadata: Metadata) -> Self {
+        Stat {
+            inode: metadata.inode,
+            mode: metadata.file_type.mode_bits() | metadata.mode,
+            uid: metadata.uid,
+            gid: metadata.gid,
+            size: metadata.size,
+            atime: metadata.atime,
+            mtime: metadata.mtime,
+            ctime: metadata.ctime,
+        }
+    }
+}
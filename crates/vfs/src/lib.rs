@@ -0,0 +1,214 @@
+#![no_std]
+extern crate alloc;
+
+mod error;
+mod file;
+mod inode;
+mod mount;
+pub mod path;
+
+pub use error::{Error, Result};
+pub use file::{FdTable, OpenFile, OpenFlags, Whence};
+pub use inode::{DirEntry, FileOps, FileType, Filesystem, Inode, Metadata, Stat};
+pub use mount::{Mount, MountTable};
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use custom_types::spin_lock::SpinLock;
+
+const MAX_SYMLINK_DEPTH: usize = 8;
+const MAX_NAME_LEN: usize = 255;
+
+enum Walk {
+    Found(Arc<dyn Inode>),
+    Symlink(String),
+}
+
+pub struct Vfs {
+    mounts: SpinLock<MountTable>,
+}
+
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vfs {
+    pub const fn new() -> Self {
+        Vfs {
+            mounts: SpinLock::new(MountTable::new()),
+        }
+    }
+
+    pub fn mount(&self, path: &str, fs: Arc<dyn Filesystem>) -> Result<()> {
+        let path = path::normalize("/", path);
+        if !self.mounts.lock().mounts().is_empty() {
+            let metadata = self.lookup(&path)?.metadata();
+            if metadata.file_type != FileType::Directory {
+                return Err(Error::NotADirectory);
+            }
+        } else if path != "/" {
+            return Err(Error::NotFound);
+        }
+        self.mounts.lock().insert(path, fs)
+    }
+
+    pub fn unmount(&self, path: &str) -> Result<Arc<dyn Filesystem>> {
+        let fs = self.mounts.lock().remove(&path::normalize("/", path))?;
+        fs.sync()?;
+        Ok(fs)
+    }
+
+    pub fn mounts(&self) -> Vec<Mount> {
+        self.mounts.lock().mounts().to_vec()
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.mounts().iter().try_for_each(|mount| mount.fs.sync())
+    }
+
+    pub fn lookup(&self, path: &str) -> Result<Arc<dyn Inode>> {
+        self.resolve(path, true)
+    }
+
+    pub fn lookup_no_follow(&self, path: &str) -> Result<Arc<dyn Inode>> {
+        self.resolve(path, false)
+    }
+
+    pub fn stat(&self, path: &str) -> Result<Metadata> {
+        Ok(self.lookup(path)?.metadata())
+    }
+
+    pub fn readdir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        self.lookup(path)?.readdir()
+    }
+
+    pub fn open(&self, path: &str, flags: OpenFlags, mode: u32) -> Result<OpenFile> {
+        let inode = match self.lookup(path) {
+            Ok(inode) => inode,
+            Err(Error::NotFound) if flags.contains(OpenFlags::CREATE) => {
+                self.create(path, FileType::File, mode)?
+            }
+            Err(error) => return Err(error),
+        };
+
+        if inode.metadata().file_type == FileType::Directory && flags.writable() {
+            return Err(Error::IsADirectory);
+        }
+        if flags.contains(OpenFlags::TRUNCATE) && flags.writable() {
+            inode.truncate(0)?;
+        }
+        Ok(OpenFile::new(inode, flags))
+    }
+
+    pub fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
+        self.create(path, FileType::Directory, mode).map(|_| ())
+    }
+
+    pub fn unlink(&self, path: &str) -> Result<()> {
+        let path = path::normalize("/", path);
+        if self.mounts.lock().is_mount_point(&path) {
+            return Err(Error::Busy);
+        }
+
+        let (parent, name) = path::split_parent(&path);
+        if name.is_empty() {
+            return Err(Error::Busy);
+        }
+        self.lookup(parent)?.unlink(name)
+    }
+
+    pub fn rename(&self, old_path: &str, new_path: &str) -> Result<()> {
+        let old_path = path::normalize("/", old_path);
+        let new_path = path::normalize("/", new_path);
+        if old_path == new_path {
+            return Ok(());
+        }
+        if path::starts_with(&new_path, &old_path) {
+            return Err(Error::InvalidArgument);
+        }
+        // The target is an ancestor, which still holds the entry being moved
+        if path::starts_with(&old_path, &new_path) {
+            return Err(Error::NotEmpty);
+        }
+
+        {
+            let mounts = self.mounts.lock();
+            if mounts.is_mount_point(&old_path) || mounts.is_mount_point(&new_path) {
+                return Err(Error::Busy);
+            }
+            let old_mount = mounts.find(&old_path).map(|mount| &mount.path);
+            let new_mount = mounts.find(&new_path).map(|mount| &mount.path);
+            if old_mount != new_mount {
+                return Err(Error::CrossDevice);
+            }
+        }
+
+        let (old_parent, old_name) = path::split_parent(&old_path);
+        let (new_parent, new_name) = path::split_parent(&new_path);
+        if new_name.len() > MAX_NAME_LEN {
+            return Err(Error::NameTooLong);
+        }
+        let new_parent = self.lookup(new_parent)?;
+        self.lookup(old_parent)?
+            .rename(old_name, new_parent.as_ref(), new_name)
+    }
+
+    fn create(&self, path: &str, file_type: FileType, mode: u32) -> Result<Arc<dyn Inode>> {
+        let path = path::normalize("/", path);
+        let (parent, name) = path::split_parent(&path);
+        if name.is_empty() {
+            return Err(Error::AlreadyExists);
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(Error::NameTooLong);
+        }
+        self.lookup(parent)?.create(name, file_type, mode)
+    }
+
+    fn resolve(&self, path: &str, follow_last: bool) -> Result<Arc<dyn Inode>> {
+        let mut path = path::normalize("/", path);
+        for _ in 0..MAX_SYMLINK_DEPTH {
+            match self.walk(&path, follow_last)? {
+                Walk::Found(inode) => return Ok(inode),
+                Walk::Symlink(target) => path = target,
+            }
+        }
+        Err(Error::TooManyLinks)
+    }
+
+    fn walk(&self, path: &str, follow_last: bool) -> Result<Walk> {
+        let mount = self
+            .mounts
+            .lock()
+            .find(path)
+            .cloned()
+            .ok_or(Error::NotFound)?;
+        let rest = path.strip_prefix(mount.path.as_str()).unwrap_or(path);
+        let components: Vec<&str> = path::components(rest).collect();
+
+        let mut inode = mount.fs.root();
+        let mut walked = mount.path.clone();
+        for (index, name) in components.iter().enumerate() {
+            let next = inode.lookup(name)?;
+            let is_last = index + 1 == components.len();
+
+            if next.metadata().file_type == FileType::Symlink && (follow_last || !is_last) {
+                let target = next.read_link()?;
+                let remaining = components[index + 1..].join("/");
+                return Ok(Walk::Symlink(path::normalize(
+                    &walked,
+                    &format!("{}/{}", target, remaining),
+                )));
+            }
+
+            if !walked.ends_with('/') {
+                walked.push('/');
+            }
+            walked.push_str(name);
+            inode = next;
+        }
+
+        Ok(Walk::Found(inode))
+    }
+}
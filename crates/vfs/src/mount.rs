@@ -0,0 +1,64 @@
+use crate::{
+    error::{Error, Result},
+    inode::Filesystem,
+    path,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+#[derive(Clone)]
+pub struct Mount {
+    pub path: String,
+    pub fs: Arc<dyn Filesystem>,
+}
+
+#[derive(Default)]
+pub struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    pub const fn new() -> Self {
+        MountTable { mounts: Vec::new() }
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    pub fn is_mount_point(&self, path: &str) -> bool {
+        self.mounts.iter().any(|mount| mount.path == path)
+    }
+
+    pub(crate) fn insert(&mut self, path: String, fs: Arc<dyn Filesystem>) -> Result<()> {
+        if self.is_mount_point(&path) {
+            return Err(Error::Busy);
+        }
+        self.mounts.push(Mount { path, fs });
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, path: &str) -> Result<Arc<dyn Filesystem>> {
+        let busy = self
+            .mounts
+            .iter()
+            .any(|mount| mount.path != path && path::starts_with(&mount.path, path));
+        if busy {
+            return Err(Error::Busy);
+        }
+
+        let index = self
+            .mounts
+            .iter()
+            .position(|mount| mount.path == path)
+            .ok_or(Error::InvalidArgument)?;
+        Ok(self.mounts.remove(index).fs)
+    }
+
+    // Returns the mount with the longest path that is a prefix of `path`
+    pub fn find(&self, path: &str) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .filter(|mount| path::starts_with(path, &mount.path))
+            .max_by_key(|mount| mount.path.len())
+    }
+}
@@ -0,0 +1,44 @@
+use alloc::{string::String, vec::Vec};
+
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+pub fn normalize(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "" } else { cwd };
+
+    let mut stack: Vec<&str> = Vec::new();
+    for component in components(base).chain(components(path)) {
+        match component {
+            ".." => {
+                stack.pop();
+            }
+            component => stack.push(component),
+        }
+    }
+
+    let mut normalized = String::new();
+    for component in stack {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// Splits a normalized absolute path into its parent directory and the last component
+pub fn split_parent(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(index) => (&path[..index], &path[index + 1..]),
+        None => ("/", path),
+    }
+}
+
+pub fn starts_with(path: &str, prefix: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
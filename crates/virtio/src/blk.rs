@@ -0,0 +1,241 @@
+use crate::{Buffer, Transport, VirtQueue};
+use block::{BlockDevice, Error, Result, SECTOR_SIZE};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use custom_types::spin_lock::{Guard, SpinLock};
+use pci::PciDevice;
+use x86_64::{PhysAddr, instructions::interrupts};
+
+const FEATURE_READ_ONLY: u64 = 1 << 5;
+const FEATURE_FLUSH: u64 = 1 << 9;
+
+const CONFIG_CAPACITY: u16 = 0x00;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+const QUEUE_SIZE: u16 = 64;
+const PAGE_SIZE: usize = 4096;
+// Data goes through a bounce buffer, since callers' buffers are not physically contiguous.
+// The first page holds the request header and the status byte
+const DATA_PAGES: usize = 16;
+const STATUS_OFFSET: u64 = 16;
+
+const POLL_LIMIT: usize = 10_000_000;
+// Each wakeup counts for this many polls, the timer alone wakes us every millisecond
+const POLLS_PER_WAKEUP: usize = 2_000;
+
+const ISR_QUEUE: u8 = 1;
+
+pub struct VirtioBlk {
+    transport: Transport,
+    inner: SpinLock<Inner>,
+    capacity: u64,
+    read_only: bool,
+    flush: bool,
+    use_interrupts: AtomicBool,
+    interrupts: AtomicU64,
+}
+
+struct Inner {
+    queue: VirtQueue,
+    buffer: PhysAddr,
+    // Head of a request that timed out. The device may still complete it and write into the
+    // buffer, so nothing reuses either until its used entry comes back
+    pending: Option<u16>,
+}
+
+impl VirtioBlk {
+    pub fn new(device: &PciDevice) -> core::result::Result<Self, crate::Error> {
+        device.enable(pci::COMMAND_IO | pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        let transport = Transport::new(device)?;
+        let features = transport.negotiate(FEATURE_READ_ONLY | FEATURE_FLUSH)?;
+
+        let setup = || {
+            let queue = transport.create_queue(0, QUEUE_SIZE)?;
+            let buffer = memory::allocate_dma(1 + DATA_PAGES).ok_or(crate::Error::NoMemory)?;
+            Ok(Inner {
+                queue,
+                buffer,
+                pending: None,
+            })
+        };
+        let inner = setup().inspect_err(|_| transport.set_status(crate::STATUS_FAILED))?;
+        transport.driver_ok();
+
+        Ok(VirtioBlk {
+            capacity: transport.config_u64(CONFIG_CAPACITY),
+            transport,
+            inner: SpinLock::new(inner),
+            read_only: features & FEATURE_READ_ONLY != 0,
+            flush: features & FEATURE_FLUSH != 0,
+            use_interrupts: AtomicBool::new(false),
+            interrupts: AtomicU64::new(0),
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        self.transport.is_modern()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn interrupt_count(&self) -> u64 {
+        self.interrupts.load(Ordering::Relaxed)
+    }
+
+    // Called once the interrupt line is hooked up; until then completions are polled
+    pub fn enable_interrupts(&self) {
+        self.use_interrupts.store(true, Ordering::Relaxed);
+    }
+
+    // Runs in interrupt context, so it only acknowledges and leaves the queue to the waiter
+    pub fn handle_interrupt(&self) -> bool {
+        let status = self.transport.interrupt_status();
+        if status & ISR_QUEUE != 0 {
+            self.interrupts.fetch_add(1, Ordering::Relaxed);
+        }
+        status != 0
+    }
+
+    // Waits out a request that timed out earlier, failing again if it is still outstanding
+    fn lock(&self) -> Result<Guard<'_, Inner>> {
+        let mut inner = self.inner.lock();
+        if let Some(head) = inner.pending {
+            self.wait(&mut inner.queue, head)?;
+            inner.pending = None;
+        }
+        Ok(inner)
+    }
+
+    fn request(&self, inner: &mut Inner, kind: u32, lba: u64, length: usize) -> Result<()> {
+        let header = inner.buffer;
+        let status = header + STATUS_OFFSET;
+        unsafe {
+            let base = memory::phys_to_virt(header).as_mut_ptr::<u8>();
+            (base as *mut u32).write_volatile(kind);
+            (base.add(4) as *mut u32).write_volatile(0);
+            (base.add(8) as *mut u64).write_volatile(lba);
+            base.add(STATUS_OFFSET as usize).write_volatile(0xFF);
+        }
+
+        let mut buffers = [Buffer {
+            address: header,
+            length: 16,
+            device_writes: false,
+        }; 3];
+        let mut count = 1;
+        if length > 0 {
+            buffers[count] = Buffer {
+                address: header + PAGE_SIZE as u64,
+                length: length as u32,
+                device_writes: kind == REQUEST_IN,
+            };
+            count += 1;
+        }
+        buffers[count] = Buffer {
+            address: status,
+            length: 1,
+            device_writes: true,
+        };
+        count += 1;
+
+        let head = inner
+            .queue
+            .add(&buffers[..count])
+            .ok_or(Error::Device("Virtqueue is full"))?;
+        self.transport.notify(&inner.queue);
+        if let Err(error) = self.wait(&mut inner.queue, head) {
+            inner.pending = Some(head);
+            return Err(error);
+        }
+
+        match unsafe { memory::phys_to_virt(status).as_ptr::<u8>().read_volatile() } {
+            STATUS_OK => Ok(()),
+            STATUS_UNSUPPORTED => Err(Error::Device("Request not supported")),
+            _ => Err(Error::Device("I/O error")),
+        }
+    }
+
+    // Sleeps until the completion interrupt when possible, and polls the used ring otherwise.
+    // Only one request is in flight, so any other used entry is stale and dropped
+    fn wait(&self, queue: &mut VirtQueue, head: u16) -> Result<()> {
+        let mut budget = POLL_LIMIT;
+        loop {
+            while let Some((used, _)) = queue.pop_used() {
+                if used == head {
+                    return Ok(());
+                }
+            }
+            if budget == 0 {
+                return Err(Error::Timeout);
+            }
+            if self.use_interrupts.load(Ordering::Relaxed) && interrupts::are_enabled() {
+                interrupts::disable();
+                if queue.has_used() {
+                    interrupts::enable();
+                    continue;
+                }
+                interrupts::enable_and_hlt();
+                budget = budget.saturating_sub(POLLS_PER_WAKEUP);
+            } else {
+                core::hint::spin_loop();
+                budget -= 1;
+            }
+        }
+    }
+
+    fn data(&self, inner: &Inner) -> *mut u8 {
+        memory::phys_to_virt(inner.buffer + PAGE_SIZE as u64).as_mut_ptr()
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        self.check_request(lba, buf.len())?;
+
+        let mut inner = self.lock()?;
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(DATA_PAGES * PAGE_SIZE) {
+            self.request(&mut inner, REQUEST_IN, lba, chunk.len())?;
+            let data = self.data(&inner);
+            unsafe { core::ptr::copy_nonoverlapping(data, chunk.as_mut_ptr(), chunk.len()) };
+            lba += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        self.check_request(lba, buf.len())?;
+
+        let mut inner = self.lock()?;
+        let mut lba = lba;
+        for chunk in buf.chunks(DATA_PAGES * PAGE_SIZE) {
+            let data = self.data(&inner);
+            unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), data, chunk.len()) };
+            self.request(&mut inner, REQUEST_OUT, lba, chunk.len())?;
+            lba += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        if !self.flush || self.read_only {
+            return Ok(());
+        }
+        let mut inner = self.lock()?;
+        self.request(&mut inner, REQUEST_FLUSH, 0, 0)
+    }
+}
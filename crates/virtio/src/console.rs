@@ -0,0 +1,333 @@
+use crate::{Buffer, Error, Transport, VirtQueue};
+use alloc::{string::String, vec, vec::Vec};
+use custom_types::spin_lock::SpinLock;
+use pci::PciDevice;
+use x86_64::PhysAddr;
+
+const FEATURE_MULTIPORT: u64 = 1 << 1;
+const CONFIG_MAX_PORTS: u16 = 0x04;
+// Each port takes two queues and a few pages, so only the first ones are used
+const MAX_PORTS: u32 = 4;
+
+const CONTROL_RECEIVE: u16 = 2;
+const CONTROL_TRANSMIT: u16 = 3;
+
+// Control messages: a port ID, an event and a value, with a name following PORT_NAME
+const DEVICE_READY: u16 = 0;
+const DEVICE_ADD: u16 = 1;
+const DEVICE_REMOVE: u16 = 2;
+const PORT_READY: u16 = 3;
+const CONSOLE_PORT: u16 = 4;
+const PORT_OPEN: u16 = 6;
+const PORT_NAME: u16 = 7;
+const CONTROL_SIZE: usize = 8;
+const NO_PORT: u32 = u32::MAX;
+
+const QUEUE_SIZE: u16 = 16;
+const PAGE_SIZE: usize = 4096;
+const SLOT_SIZE: usize = 256;
+// Control replies to DEVICE_READY usually arrive right away, this bounds the wait for them
+const SETTLE_POLLS: usize = 100_000;
+
+const ISR_QUEUE: u8 = 1;
+
+#[derive(Debug, Clone)]
+pub struct PortInfo {
+    pub id: u32,
+    pub name: String,
+    pub console: bool,
+    pub host_connected: bool,
+}
+
+pub struct VirtioConsole {
+    transport: Transport,
+    inner: SpinLock<Inner>,
+}
+
+struct Inner {
+    ports: Vec<Port>,
+    control: Option<(Receiver, Sender)>,
+}
+
+struct Port {
+    info: PortInfo,
+    ready: bool,
+    receiver: Receiver,
+    sender: Sender,
+}
+
+impl VirtioConsole {
+    pub fn new(device: &PciDevice) -> Result<Self, Error> {
+        device.enable(pci::COMMAND_IO | pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        let transport = Transport::new(device)?;
+        let features = transport.negotiate(FEATURE_MULTIPORT)?;
+        let multiport = features & FEATURE_MULTIPORT != 0;
+        let port_count = match multiport {
+            true => transport.config_u32(CONFIG_MAX_PORTS).clamp(1, MAX_PORTS),
+            false => 1,
+        };
+
+        // Without multiport support there is a single console port that is always there
+        let setup = || {
+            let mut ports = Vec::new();
+            for id in 0..port_count {
+                let receive = if id == 0 { 0 } else { (id as u16 + 1) * 2 };
+                ports.push(Port {
+                    info: PortInfo {
+                        id,
+                        name: String::new(),
+                        console: !multiport,
+                        host_connected: !multiport,
+                    },
+                    ready: !multiport,
+                    receiver: Receiver::new(&transport, receive)?,
+                    sender: Sender::new(&transport, receive + 1)?,
+                });
+            }
+            let control = match multiport {
+                true => Some((
+                    Receiver::new(&transport, CONTROL_RECEIVE)?,
+                    Sender::new(&transport, CONTROL_TRANSMIT)?,
+                )),
+                false => None,
+            };
+            Ok(Inner { ports, control })
+        };
+        let inner = setup().inspect_err(|_| transport.set_status(crate::STATUS_FAILED))?;
+        transport.driver_ok();
+
+        let console = VirtioConsole {
+            transport,
+            inner: SpinLock::new(inner),
+        };
+        {
+            let mut inner = console.inner.lock();
+            for port in &inner.ports {
+                console.transport.notify(&port.receiver.queue);
+            }
+            if let Some((receiver, _)) = &inner.control {
+                console.transport.notify(&receiver.queue);
+            }
+            console.send_control(&mut inner, NO_PORT, DEVICE_READY, 1)?;
+        }
+        for _ in 0..SETTLE_POLLS {
+            console.poll();
+        }
+        Ok(console)
+    }
+
+    pub fn is_modern(&self) -> bool {
+        self.transport.is_modern()
+    }
+
+    pub fn ports(&self) -> Vec<PortInfo> {
+        let inner = self.inner.lock();
+        inner
+            .ports
+            .iter()
+            .filter(|port| port.ready)
+            .map(|port| port.info.clone())
+            .collect()
+    }
+
+    // Sends the bytes to every ready port; returns false if the device was busy
+    pub fn write(&self, data: &[u8]) -> bool {
+        let Some(mut inner) = self.inner.try_lock() else {
+            return false;
+        };
+        for port in inner.ports.iter_mut().filter(|port| port.ready) {
+            let _ = port.sender.send(&self.transport, data);
+        }
+        true
+    }
+
+    pub fn write_port(&self, id: u32, data: &[u8]) -> Result<(), Error> {
+        let mut inner = self.inner.lock();
+        match inner.ports.get_mut(id as usize) {
+            Some(port) if port.ready => port.sender.send(&self.transport, data),
+            _ => Err(Error::Device("No such console port")),
+        }
+    }
+
+    // Acknowledges the interrupt and returns the input that arrived on any port
+    pub fn handle_interrupt(&self) -> Vec<u8> {
+        if self.transport.interrupt_status() & ISR_QUEUE == 0 {
+            return Vec::new();
+        }
+        self.poll()
+    }
+
+    // Handles pending control messages and collects input; skipped while the device is in use
+    pub fn poll(&self) -> Vec<u8> {
+        let mut input = Vec::new();
+        let Some(mut inner) = self.inner.try_lock() else {
+            return input;
+        };
+
+        let mut messages = Vec::new();
+        if let Some((receiver, _)) = &mut inner.control {
+            receiver.receive(&self.transport, |data| messages.push(Vec::from(data)));
+        }
+        for message in messages {
+            self.handle_control(&mut inner, &message);
+        }
+
+        for port in inner.ports.iter_mut().filter(|port| port.ready) {
+            port.receiver
+                .receive(&self.transport, |data| input.extend_from_slice(data));
+        }
+        input
+    }
+
+    fn handle_control(&self, inner: &mut Inner, message: &[u8]) {
+        if message.len() < CONTROL_SIZE {
+            return;
+        }
+        let id = u32::from_le_bytes(message[0..4].try_into().unwrap());
+        let event = u16::from_le_bytes(message[4..6].try_into().unwrap());
+        let value = u16::from_le_bytes(message[6..8].try_into().unwrap());
+        let Some(port) = inner.ports.get_mut(id as usize) else {
+            return;
+        };
+
+        match event {
+            // Every port is opened on our side, since all of them act as terminals
+            DEVICE_ADD => {
+                port.ready = true;
+                let _ = self.send_control(inner, id, PORT_READY, 1);
+                let _ = self.send_control(inner, id, PORT_OPEN, 1);
+            }
+            DEVICE_REMOVE => {
+                port.ready = false;
+                port.info.host_connected = false;
+            }
+            CONSOLE_PORT => port.info.console = true,
+            PORT_OPEN => port.info.host_connected = value != 0,
+            PORT_NAME => {
+                let name = &message[CONTROL_SIZE..];
+                let end = name
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .unwrap_or(name.len());
+                port.info.name = String::from_utf8_lossy(&name[..end]).into_owned();
+            }
+            _ => {}
+        }
+    }
+
+    fn send_control(
+        &self,
+        inner: &mut Inner,
+        id: u32,
+        event: u16,
+        value: u16,
+    ) -> Result<(), Error> {
+        let mut message = [0; CONTROL_SIZE];
+        message[0..4].copy_from_slice(&id.to_le_bytes());
+        message[4..6].copy_from_slice(&event.to_le_bytes());
+        message[6..8].copy_from_slice(&value.to_le_bytes());
+        match &mut inner.control {
+            Some((_, sender)) => sender.send(&self.transport, &message),
+            None => Ok(()),
+        }
+    }
+}
+
+// Keeps a page of small buffers posted, so the device always has somewhere to put input
+struct Receiver {
+    queue: VirtQueue,
+    buffer: PhysAddr,
+    slots: Vec<usize>,
+}
+
+impl Receiver {
+    fn new(transport: &Transport, index: u16) -> Result<Self, Error> {
+        let queue = transport.create_queue(index, QUEUE_SIZE)?;
+        let buffer = memory::allocate_dma(1).ok_or(Error::NoMemory)?;
+        let mut receiver = Receiver {
+            slots: vec![0; queue.size() as usize],
+            queue,
+            buffer,
+        };
+        for slot in 0..PAGE_SIZE / SLOT_SIZE {
+            receiver.post(slot);
+        }
+        Ok(receiver)
+    }
+
+    fn slot_address(&self, slot: usize) -> PhysAddr {
+        self.buffer + (slot * SLOT_SIZE) as u64
+    }
+
+    fn post(&mut self, slot: usize) {
+        let buffer = Buffer {
+            address: self.slot_address(slot),
+            length: SLOT_SIZE as u32,
+            device_writes: true,
+        };
+        if let Some(head) = self.queue.add(&[buffer]) {
+            self.slots[head as usize] = slot;
+        }
+    }
+
+    // Passes on every filled buffer and hands it straight back to the device
+    fn receive(&mut self, transport: &Transport, mut handle: impl FnMut(&[u8])) {
+        let mut received = false;
+        while let Some((head, length)) = self.queue.pop_used() {
+            let slot = self.slots[head as usize];
+            let data = memory::phys_to_virt(self.slot_address(slot)).as_ptr::<u8>();
+            let length = (length as usize).min(SLOT_SIZE);
+            handle(unsafe { core::slice::from_raw_parts(data, length) });
+            self.post(slot);
+            received = true;
+        }
+        if received {
+            transport.notify(&self.queue);
+        }
+    }
+}
+
+struct Sender {
+    queue: VirtQueue,
+    buffer: PhysAddr,
+    // Head of a chunk that timed out; the device may still read the buffer until it returns
+    pending: Option<u16>,
+}
+
+impl Sender {
+    fn new(transport: &Transport, index: u16) -> Result<Self, Error> {
+        Ok(Sender {
+            queue: transport.create_queue(index, QUEUE_SIZE)?,
+            buffer: memory::allocate_dma(1).ok_or(Error::NoMemory)?,
+            pending: None,
+        })
+    }
+
+    fn send(&mut self, transport: &Transport, data: &[u8]) -> Result<(), Error> {
+        if let Some(head) = self.pending {
+            self.queue
+                .poll_head(head)
+                .ok_or(Error::Device("Console output timed out"))?;
+            self.pending = None;
+        }
+        for chunk in data.chunks(PAGE_SIZE) {
+            let target = memory::phys_to_virt(self.buffer).as_mut_ptr::<u8>();
+            unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), target, chunk.len()) };
+            let buffer = Buffer {
+                address: self.buffer,
+                length: chunk.len() as u32,
+                device_writes: false,
+            };
+            let head = self
+                .queue
+                .add(&[buffer])
+                .ok_or(Error::Device("Virtqueue is full"))?;
+            transport.notify(&self.queue);
+            if self.queue.poll_head(head).is_none() {
+                self.pending = Some(head);
+                return Err(Error::Device("Console output timed out"));
+            }
+        }
+        Ok(())
+    }
+}
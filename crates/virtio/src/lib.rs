@@ -0,0 +1,79 @@
+#![no_std]
+extern crate alloc;
+
+mod blk;
+mod console;
+mod queue;
+mod rng;
+mod transport;
+
+pub use blk::VirtioBlk;
+pub use console::{PortInfo, VirtioConsole};
+pub use queue::{Buffer, VirtQueue};
+pub use rng::VirtioRng;
+pub use transport::Transport;
+
+use pci::{DeviceId, PciDevice};
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+// Device types, which modern devices add to 0x1040 and transitional ones keep in the subsystem ID
+pub const TYPE_NETWORK: u16 = 1;
+pub const TYPE_BLOCK: u16 = 2;
+pub const TYPE_CONSOLE: u16 = 3;
+pub const TYPE_ENTROPY: u16 = 4;
+
+const SUBSYSTEM_ID: u8 = 0x2E;
+const MODERN_DEVICE_BASE: u16 = 0x1040;
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+pub const FEATURE_VERSION_1: u64 = 1 << 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NoTransport,
+    NoQueue,
+    NoMemory,
+    FeaturesRejected,
+    Device(&'static str),
+}
+
+impl From<Error> for &'static str {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::NoTransport => "No usable virtio transport",
+            Error::NoQueue => "Virtqueue is not available",
+            Error::NoMemory => "Out of DMA memory",
+            Error::FeaturesRejected => "Device rejected the features",
+            Error::Device(message) => message,
+        }
+    }
+}
+
+// The transitional ID first, then the modern one
+pub const fn device_ids(device_type: u16) -> [DeviceId; 2] {
+    let transitional = match device_type {
+        TYPE_BLOCK => 0x1001,
+        TYPE_CONSOLE => 0x1003,
+        TYPE_ENTROPY => 0x1005,
+        TYPE_NETWORK => 0x1000,
+        _ => 0,
+    };
+    [
+        DeviceId::new(VENDOR_ID, transitional),
+        DeviceId::new(VENDOR_ID, MODERN_DEVICE_BASE + device_type),
+    ]
+}
+
+pub fn device_type(device: &PciDevice) -> Option<u16> {
+    match device.device_id {
+        0x1000..=0x103F => Some(device.address.read_u16(SUBSYSTEM_ID)),
+        id if id >= MODERN_DEVICE_BASE => Some(id - MODERN_DEVICE_BASE),
+        _ => None,
+    }
+}
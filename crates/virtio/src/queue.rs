@@ -0,0 +1,195 @@
+use crate::Error;
+use core::sync::atomic::{Ordering, fence};
+use x86_64::PhysAddr;
+
+const PAGE_SIZE: usize = 4096;
+const POLL_LIMIT: usize = 10_000_000;
+
+const DESCRIPTOR_NEXT: u16 = 1;
+const DESCRIPTOR_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    address: u64,
+    length: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElement {
+    id: u32,
+    length: u32,
+}
+
+// A buffer in physical memory, either read by the device or written by it
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub address: PhysAddr,
+    pub length: u32,
+    pub device_writes: bool,
+}
+
+// Split virtqueue in the legacy layout, which modern devices accept as well:
+// descriptor table and available ring, then the used ring on the next page boundary
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    address: PhysAddr,
+    descriptors: *mut Descriptor,
+    available: *mut u16,
+    used: *mut u16,
+    free_head: u16,
+    free_count: u16,
+    last_used: u16,
+    pub(crate) notify_offset: u16,
+}
+
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    pub fn new(index: u16, size: u16) -> Result<Self, Error> {
+        let (used_offset, total) = Self::layout(size);
+        let address = memory::allocate_dma(total / PAGE_SIZE).ok_or(Error::NoMemory)?;
+        let base = memory::phys_to_virt(address).as_mut_ptr::<u8>();
+
+        let descriptors = base as *mut Descriptor;
+        // Every descriptor starts out on the free list
+        for i in 0..size {
+            unsafe { (*descriptors.add(i as usize)).next = i.wrapping_add(1) };
+        }
+
+        Ok(VirtQueue {
+            index,
+            size,
+            address,
+            descriptors,
+            available: unsafe { base.add(size as usize * 16) } as *mut u16,
+            used: unsafe { base.add(used_offset) } as *mut u16,
+            free_head: 0,
+            free_count: size,
+            last_used: 0,
+            notify_offset: 0,
+        })
+    }
+
+    fn layout(size: u16) -> (usize, usize) {
+        let size = size as usize;
+        let used_offset = (size * 16 + 6 + size * 2).next_multiple_of(PAGE_SIZE);
+        let total = (used_offset + 6 + size * 8).next_multiple_of(PAGE_SIZE);
+        (used_offset, total)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn descriptors_address(&self) -> PhysAddr {
+        self.address
+    }
+
+    pub fn available_address(&self) -> PhysAddr {
+        self.address + self.size as u64 * 16
+    }
+
+    pub fn used_address(&self) -> PhysAddr {
+        self.address + Self::layout(self.size).0 as u64
+    }
+
+    // Chains the buffers into descriptors and offers them; returns the head to match completions
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.free_count as usize {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut last = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let index = self.free_head;
+            let descriptor = unsafe { &mut *self.descriptors.add(index as usize) };
+            self.free_head = descriptor.next;
+
+            descriptor.address = buffer.address.as_u64();
+            descriptor.length = buffer.length;
+            descriptor.flags = if buffer.device_writes {
+                DESCRIPTOR_WRITE
+            } else {
+                0
+            };
+            if i + 1 < buffers.len() {
+                descriptor.flags |= DESCRIPTOR_NEXT;
+            }
+            last = index;
+        }
+        unsafe { (*self.descriptors.add(last as usize)).next = self.free_head };
+        self.free_count -= buffers.len() as u16;
+
+        unsafe {
+            let index = self.available.add(1).read_volatile();
+            self.available
+                .add(2 + (index % self.size) as usize)
+                .write_volatile(head);
+            // The entry has to be visible before the index that publishes it
+            fence(Ordering::SeqCst);
+            self.available.add(1).write_volatile(index.wrapping_add(1));
+        }
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.last_used != unsafe { self.used.add(1).read_volatile() }
+    }
+
+    // Takes the next completed chain back onto the free list; returns its head and written length
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+
+        let element = unsafe {
+            let ring = self.used.add(2) as *mut UsedElement;
+            ring.add((self.last_used % self.size) as usize)
+                .read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = element.id as u16;
+        let mut index = head;
+        let mut freed = 1;
+        loop {
+            let descriptor = unsafe { &*self.descriptors.add(index as usize) };
+            if descriptor.flags & DESCRIPTOR_NEXT == 0 {
+                break;
+            }
+            index = descriptor.next;
+            freed += 1;
+        }
+        unsafe { (*self.descriptors.add(index as usize)).next = self.free_head };
+        self.free_head = head;
+        self.free_count += freed;
+
+        Some((head, element.length))
+    }
+
+    // Busy-waits for the chain at `head` to complete, for requests the device finishes right
+    // away; returns the written length. Other completions are stale and dropped
+    pub fn poll_head(&mut self, head: u16) -> Option<u32> {
+        for _ in 0..POLL_LIMIT {
+            while let Some((used, length)) = self.pop_used() {
+                if used == head {
+                    return Some(length);
+                }
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{Buffer, Error, Transport, VirtQueue};
+use custom_types::spin_lock::{Guard, SpinLock};
+use pci::PciDevice;
+use x86_64::PhysAddr;
+
+const PAGE_SIZE: usize = 4096;
+const QUEUE_SIZE: u16 = 8;
+
+pub struct VirtioRng {
+    transport: Transport,
+    inner: SpinLock<Inner>,
+}
+
+struct Inner {
+    queue: VirtQueue,
+    buffer: PhysAddr,
+    // Head of a request that timed out; the device may still write the buffer until it returns
+    pending: Option<u16>,
+}
+
+impl VirtioRng {
+    // Requests complete almost immediately, so the device is polled and its interrupt stays off
+    pub fn new(device: &PciDevice) -> Result<Self, Error> {
+        device.enable(
+            pci::COMMAND_IO
+                | pci::COMMAND_MEMORY
+                | pci::COMMAND_BUS_MASTER
+                | pci::COMMAND_INTERRUPT_DISABLE,
+        );
+        let transport = Transport::new(device)?;
+        transport.negotiate(0)?;
+
+        let setup = || {
+            let queue = transport.create_queue(0, QUEUE_SIZE)?;
+            let buffer = memory::allocate_dma(1).ok_or(Error::NoMemory)?;
+            Ok(Inner {
+                queue,
+                buffer,
+                pending: None,
+            })
+        };
+        let inner = setup().inspect_err(|_| transport.set_status(crate::STATUS_FAILED))?;
+        transport.driver_ok();
+
+        Ok(VirtioRng {
+            transport,
+            inner: SpinLock::new(inner),
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        self.transport.is_modern()
+    }
+
+    // Waits out a request that timed out earlier, failing again if it is still outstanding
+    fn lock(&self) -> Result<Guard<'_, Inner>, Error> {
+        let mut inner = self.inner.lock();
+        if let Some(head) = inner.pending {
+            inner
+                .queue
+                .poll_head(head)
+                .ok_or(Error::Device("Entropy request timed out"))?;
+            inner.pending = None;
+        }
+        Ok(inner)
+    }
+
+    // The device may return fewer bytes than asked for
+    pub fn fill(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut inner = self.lock()?;
+        let length = buf.len().min(PAGE_SIZE);
+        let buffer = Buffer {
+            address: inner.buffer,
+            length: length as u32,
+            device_writes: true,
+        };
+        let head = inner
+            .queue
+            .add(&[buffer])
+            .ok_or(Error::Device("Virtqueue is full"))?;
+        self.transport.notify(&inner.queue);
+
+        let Some(written) = inner.queue.poll_head(head) else {
+            inner.pending = Some(head);
+            return Err(Error::Device("Entropy request timed out"));
+        };
+        let written = (written as usize).min(length);
+        let data = memory::phys_to_virt(inner.buffer).as_ptr::<u8>();
+        unsafe { core::ptr::copy_nonoverlapping(data, buf.as_mut_ptr(), written) };
+        Ok(written)
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{
+    Error, FEATURE_VERSION_1, STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK,
+    STATUS_FEATURES_OK, queue::VirtQueue,
+};
+use pci::PciDevice;
+use x86_64::{PhysAddr, VirtAddr, instructions::port::Port};
+
+// Legacy register block at the start of BAR0, with the device configuration right after it
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG: u16 = 0x14;
+
+// Modern common configuration structure
+const DEVICE_FEATURE_SELECT: u64 = 0x00;
+const DEVICE_FEATURE: u64 = 0x04;
+const DRIVER_FEATURE_SELECT: u64 = 0x08;
+const DRIVER_FEATURE: u64 = 0x0C;
+const DEVICE_STATUS: u64 = 0x14;
+const QUEUE_SELECT: u64 = 0x16;
+const QUEUE_SIZE: u64 = 0x18;
+const QUEUE_ENABLE: u64 = 0x1C;
+const QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const QUEUE_DESC: u64 = 0x20;
+const QUEUE_DRIVER: u64 = 0x28;
+const QUEUE_DEVICE: u64 = 0x30;
+
+// Vendor capability types that locate the modern structures inside the BARs
+const CAP_COMMON: u8 = 1;
+const CAP_NOTIFY: u8 = 2;
+const CAP_ISR: u8 = 3;
+const CAP_DEVICE: u8 = 4;
+
+pub enum Transport {
+    Legacy {
+        io: u16,
+    },
+    Modern {
+        common: VirtAddr,
+        notify: VirtAddr,
+        notify_multiplier: u32,
+        isr: VirtAddr,
+        device: VirtAddr,
+    },
+}
+
+impl Transport {
+    // Modern devices are found through their capabilities, transitional ones fall back to BAR0
+    pub fn new(device: &PciDevice) -> Result<Self, Error> {
+        if let Some(transport) = Self::modern(device) {
+            return Ok(transport);
+        }
+        match device.bars[0].and_then(|bar| bar.io_port()) {
+            Some(io) if device.device_id < 0x1040 => Ok(Transport::Legacy { io }),
+            _ => Err(Error::NoTransport),
+        }
+    }
+
+    fn modern(device: &PciDevice) -> Option<Self> {
+        let (mut common, mut notify, mut isr, mut config) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+
+        for capability in &device.capabilities {
+            if capability.id() != pci::ID_VENDOR_SPECIFIC {
+                continue;
+            }
+            let offset = capability.offset();
+            let address = device.address;
+            let kind = address.read_u8(offset + 3);
+            let bar = address.read_u8(offset + 4) as usize;
+            let start = address.read_u32(offset + 8) as u64;
+            let length = address.read_u32(offset + 12) as u64;
+
+            let Some(base) = device.bars.get(bar).copied().flatten() else {
+                continue;
+            };
+            let Some(base) = base.memory_address() else {
+                continue;
+            };
+            let region = || memory::map_mmio(PhysAddr::new(base + start), length);
+            match kind {
+                CAP_COMMON if common.is_none() => common = region(),
+                CAP_NOTIFY if notify.is_none() => {
+                    notify = region();
+                    notify_multiplier = address.read_u32(offset + 16);
+                }
+                CAP_ISR if isr.is_none() => isr = region(),
+                CAP_DEVICE if config.is_none() => config = region(),
+                _ => {}
+            }
+        }
+
+        Some(Transport::Modern {
+            common: common?,
+            notify: notify?,
+            notify_multiplier,
+            isr: isr?,
+            device: config?,
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    pub fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_STATUS).read() },
+            Transport::Modern { common, .. } => read(common, DEVICE_STATUS),
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_STATUS).write(status) },
+            Transport::Modern { common, .. } => write(common, DEVICE_STATUS, status),
+        }
+    }
+
+    pub fn add_status(&self, bits: u8) {
+        self.set_status(self.status() | bits);
+    }
+
+    pub fn reset(&self) {
+        self.set_status(0);
+        // A modern device may take a while and reads back zero once the reset is done
+        while self.is_modern() && self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        match *self {
+            Transport::Legacy { io } => unsafe {
+                Port::<u32>::new(io + LEGACY_DEVICE_FEATURES).read() as u64
+            },
+            Transport::Modern { common, .. } => {
+                write::<u32>(common, DEVICE_FEATURE_SELECT, 0);
+                let low = read::<u32>(common, DEVICE_FEATURE) as u64;
+                write::<u32>(common, DEVICE_FEATURE_SELECT, 1);
+                let high = read::<u32>(common, DEVICE_FEATURE) as u64;
+                high << 32 | low
+            }
+        }
+    }
+
+    pub fn set_driver_features(&self, features: u64) {
+        match *self {
+            Transport::Legacy { io } => unsafe {
+                Port::new(io + LEGACY_DRIVER_FEATURES).write(features as u32)
+            },
+            Transport::Modern { common, .. } => {
+                write::<u32>(common, DRIVER_FEATURE_SELECT, 0);
+                write(common, DRIVER_FEATURE, features as u32);
+                write::<u32>(common, DRIVER_FEATURE_SELECT, 1);
+                write(common, DRIVER_FEATURE, (features >> 32) as u32);
+            }
+        }
+    }
+
+    // Resets the device and agrees on the features both sides support
+    pub fn negotiate(&self, supported: u64) -> Result<u64, Error> {
+        self.reset();
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+
+        let mut supported = supported;
+        if self.is_modern() {
+            supported |= FEATURE_VERSION_1;
+        }
+        let features = self.device_features() & supported;
+        self.set_driver_features(features);
+
+        if self.is_modern() {
+            self.add_status(STATUS_FEATURES_OK);
+            if self.status() & STATUS_FEATURES_OK == 0 {
+                self.set_status(crate::STATUS_FAILED);
+                return Err(Error::FeaturesRejected);
+            }
+        }
+        Ok(features)
+    }
+
+    pub fn driver_ok(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    pub fn queue_size(&self, index: u16) -> u16 {
+        match *self {
+            Transport::Legacy { io } => unsafe {
+                Port::new(io + LEGACY_QUEUE_SELECT).write(index);
+                Port::new(io + LEGACY_QUEUE_SIZE).read()
+            },
+            Transport::Modern { common, .. } => {
+                write(common, QUEUE_SELECT, index);
+                read(common, QUEUE_SIZE)
+            }
+        }
+    }
+
+    // A zero-sized queue does not exist; legacy devices only accept the size they offer
+    pub fn create_queue(&self, index: u16, max_size: u16) -> Result<VirtQueue, Error> {
+        let offered = self.queue_size(index);
+        if offered == 0 {
+            return Err(Error::NoQueue);
+        }
+        let size = match self {
+            Transport::Legacy { .. } => offered,
+            Transport::Modern { .. } => offered.min(max_size),
+        };
+        let mut queue = VirtQueue::new(index, size)?;
+
+        match *self {
+            Transport::Legacy { io } => unsafe {
+                Port::new(io + LEGACY_QUEUE_SELECT).write(index);
+                let pfn = (queue.descriptors_address().as_u64() >> 12) as u32;
+                Port::new(io + LEGACY_QUEUE_PFN).write(pfn);
+            },
+            Transport::Modern { common, .. } => {
+                write(common, QUEUE_SELECT, index);
+                write(common, QUEUE_SIZE, size);
+                write_u64(common, QUEUE_DESC, queue.descriptors_address().as_u64());
+                write_u64(common, QUEUE_DRIVER, queue.available_address().as_u64());
+                write_u64(common, QUEUE_DEVICE, queue.used_address().as_u64());
+                queue.notify_offset = read(common, QUEUE_NOTIFY_OFF);
+                write::<u16>(common, QUEUE_ENABLE, 1);
+            }
+        }
+        Ok(queue)
+    }
+
+    pub fn notify(&self, queue: &VirtQueue) {
+        match *self {
+            Transport::Legacy { io } => unsafe {
+                Port::new(io + LEGACY_QUEUE_NOTIFY).write(queue.index())
+            },
+            Transport::Modern {
+                notify,
+                notify_multiplier,
+                ..
+            } => {
+                let offset = queue.notify_offset as u64 * notify_multiplier as u64;
+                write(notify, offset, queue.index());
+            }
+        }
+    }
+
+    // Reading the ISR status also acknowledges the interrupt
+    pub fn interrupt_status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_ISR).read() },
+            Transport::Modern { isr, .. } => read(isr, 0),
+        }
+    }
+
+    pub fn config_u8(&self, offset: u16) -> u8 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_CONFIG + offset).read() },
+            Transport::Modern { device, .. } => read(device, offset as u64),
+        }
+    }
+
+    pub fn config_u32(&self, offset: u16) -> u32 {
+        match *self {
+            Transport::Legacy { io } => unsafe { Port::new(io + LEGACY_CONFIG + offset).read() },
+            Transport::Modern { device, .. } => read(device, offset as u64),
+        }
+    }
+
+    pub fn config_u64(&self, offset: u16) -> u64 {
+        (self.config_u32(offset + 4) as u64) << 32 | self.config_u32(offset) as u64
+    }
+}
+
+fn read<T: Copy>(base: VirtAddr, offset: u64) -> T {
+    unsafe { core::ptr::read_volatile((base + offset).as_ptr()) }
+}
+
+fn write<T: Copy>(base: VirtAddr, offset: u64, value: T) {
+    unsafe { core::ptr::write_volatile((base + offset).as_mut_ptr(), value) }
+}
+
+// 64-bit fields are written as two halves, which every device has to accept
+fn write_u64(base: VirtAddr, offset: u64, value: u64) {
+    write(base, offset, value as u32);
+    write(base, offset + 4, (value >> 32) as u32);
+}
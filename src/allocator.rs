@@ -1,7 +1,10 @@
-use allocators::fixed_block::{
-    FixedSizeBlockAllocator, HEAP_SIZE, HEAP_START, LockFixedSizeBlockAllocator,
+use crate::println;
+use alloc::alloc::Layout;
+use allocators::{
+    AllocatorStats, HeapAllocator, Locked, Reclaim,
+    fixed_block::{HEAP_MAX_SIZE, HEAP_SIZE, HEAP_START},
 };
-use custom_types::spin_lock::SpinLock;
+use serial::serial_println;
 use x86_64::{
     VirtAddr,
     structures::paging::{
@@ -9,9 +12,19 @@ use x86_64::{
     },
 };
 
+// The heap strategy is picked with the heap-buddy or heap-tlsf features
+#[cfg(all(feature = "heap-buddy", feature = "heap-tlsf"))]
+compile_error!("Only one of the heap-buddy and heap-tlsf features can be enabled");
+
+#[cfg(feature = "heap-buddy")]
+type KernelHeap = allocators::buddy::BuddyAllocator;
+#[cfg(all(feature = "heap-tlsf", not(feature = "heap-buddy")))]
+type KernelHeap = allocators::tlsf::TlsfAllocator;
+#[cfg(not(any(feature = "heap-buddy", feature = "heap-tlsf")))]
+type KernelHeap = allocators::fixed_block::FixedSizeBlockAllocator;
+
 #[global_allocator]
-static ALLOCATOR: LockFixedSizeBlockAllocator =
-    LockFixedSizeBlockAllocator(SpinLock::new(FixedSizeBlockAllocator::new()));
+static ALLOCATOR: Locked<KernelHeap> = Locked::new(KernelHeap::new());
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -33,6 +46,73 @@ pub fn init_heap(
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
-    unsafe { ALLOCATOR.0.lock().init(HEAP_START, HEAP_SIZE) }
+    let mut allocator = ALLOCATOR.lock();
+    unsafe { allocator.init(HEAP_START, HEAP_SIZE) }
+    allocator.set_growth(HEAP_MAX_SIZE, grow_heap);
     Ok(())
 }
+
+// Only works once the mapper and frame allocator are handed over with `memory::install`
+fn grow_heap(start: usize, size: usize) -> bool {
+    let start = Page::containing_address(VirtAddr::new(start as u64));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::map_pages(start, size.div_ceil(4096) as u64, flags).is_ok()
+}
+
+pub fn heap_size() -> usize {
+    ALLOCATOR.lock().stats().heap_size
+}
+
+pub fn stats() -> AllocatorStats {
+    ALLOCATOR.stats()
+}
+
+pub fn register_reclaim(reclaim: Reclaim) -> Result<(), &'static str> {
+    ALLOCATOR.register_reclaim(reclaim)
+}
+
+// Reached when an infallible allocation still fails after every reclaim callback has run
+#[alloc_error_handler]
+fn out_of_memory(layout: Layout) -> ! {
+    macro_rules! report {
+        ($($arg:tt)*) => {{
+            serial_println!($($arg)*);
+            println!($($arg)*);
+        }};
+    }
+
+    let stats = ALLOCATOR.stats();
+    report!(
+        "out of memory: {} bytes, align {}",
+        layout.size(),
+        layout.align()
+    );
+    report!(
+        "    heap ({}): {} in use, {} peak, {} of {} bytes mapped, {} used by blocks, {} cached per CPU",
+        stats.strategy,
+        stats.in_use,
+        stats.peak,
+        stats.heap_size,
+        stats.heap_max_size,
+        stats.heap_used,
+        stats.cached
+    );
+    if let Some(largest) = stats.largest_free {
+        report!("    largest free block: {} bytes", largest);
+    }
+    report!(
+        "    {} allocations, {} frees, {} failed, {} bytes reclaimed",
+        stats.allocations,
+        stats.frees,
+        stats.failed_allocations,
+        stats.reclaimed
+    );
+    if let Some(frames) = memory::frame_stats() {
+        report!(
+            "    physical: {} of {} frames free",
+            frames.free,
+            frames.total
+        );
+    }
+    panic!("out of memory");
+}
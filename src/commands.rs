@@ -1,7 +1,19 @@
-use crate::{WRITER, print, println};
-use alloc::string::{String, ToString};
+use crate::{
+    WRITER, allocator, devices,
+    fs::{self, VFS},
+    print, println,
+    process::{self, CURRENT_PROCESS, ExitStatus},
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use allocators::fixed_block::BLOCK_SIZES;
 use core::arch::asm;
 use datetime::DateTime;
+use vfs::{FileType, OpenFlags, path};
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
 
 pub enum Command {
     Help,
@@ -9,6 +21,26 @@ pub enum Command {
     Reboot,
     Shutdown,
     Clear,
+    Ls(String),
+    Cat(String),
+    Stat(String),
+    Cd(String),
+    Pwd,
+    Mkdir(String),
+    Rm(String),
+    Touch(String),
+    Mv(String, String),
+    Write(String, String),
+    Mount(String, String, String),
+    Umount(String),
+    Sync,
+    CacheStat(String),
+    Lsblk,
+    Lspci(String),
+    Random(String),
+    Meminfo,
+    Exec(String),
+    Vmmap(String),
     Error(String),
 }
 
@@ -22,6 +54,26 @@ impl Command {
             Reboot => reboot_action(),
             Shutdown => shutdown_action(),
             Clear => clear(),
+            Ls(path) => report(path, ls(path)),
+            Cat(path) => report(path, cat(path)),
+            Stat(path) => report(path, stat(path)),
+            Cd(path) => report(path, cd(path)),
+            Pwd => pwd(),
+            Mkdir(path) => report(path, mkdir(path)),
+            Rm(path) => report(path, rm(path)),
+            Touch(path) => report(path, touch(path)),
+            Mv(from, to) => report(from, mv(from, to)),
+            Write(path, text) => report(path, write(path, text)),
+            Mount(device, path, fs_type) => report(device, mount(device, path, fs_type)),
+            Umount(path) => report(path, umount(path)),
+            Sync => report("sync", fs::sync()),
+            CacheStat(arg) => cache_stat(arg),
+            Lsblk => lsblk(),
+            Lspci(arg) => lspci(arg == "-v"),
+            Random(count) => random(count),
+            Meminfo => meminfo(),
+            Exec(path) => report(path, exec(path)),
+            Vmmap(address) => vmmap(address),
             Error(command) => error_command(command),
         }
         print!("{}$ ", DateTime::now());
@@ -32,12 +84,45 @@ impl From<&str> for Command {
     fn from(val: &str) -> Self {
         use Command::*;
 
-        match val {
+        let (name, arg) = val.split_once(' ').unwrap_or((val, ""));
+        let arg = arg.trim().to_string();
+
+        match name {
             "help" => Help,
             "version" => Version,
             "reboot" => Reboot,
             "shutdown" => Shutdown,
             "clear" => Clear,
+            "ls" => Ls(arg),
+            "cat" => Cat(arg),
+            "stat" => Stat(arg),
+            "cd" => Cd(arg),
+            "pwd" => Pwd,
+            "mkdir" => Mkdir(arg),
+            "rm" => Rm(arg),
+            "touch" => Touch(arg),
+            "umount" => Umount(arg),
+            "sync" => Sync,
+            "cachestat" => CacheStat(arg),
+            "lsblk" => Lsblk,
+            "lspci" => Lspci(arg),
+            "random" => Random(arg),
+            "meminfo" => Meminfo,
+            "exec" => Exec(arg),
+            "vmmap" => Vmmap(arg),
+            "mount" => {
+                let mut args = arg.split_whitespace().map(ToString::to_string);
+                let mut next = || args.next().unwrap_or_default();
+                Mount(next(), next(), next())
+            }
+            "mv" | "write" => {
+                let (first, second) = arg.split_once(' ').unwrap_or((&arg, ""));
+                let (first, second) = (first.to_string(), second.trim().to_string());
+                match name {
+                    "mv" => Mv(first, second),
+                    _ => Write(first, second),
+                }
+            }
             _ => Error(val.to_string()),
         }
     }
@@ -65,6 +150,26 @@ fn help() {
     reboot    - Reboot the system
     shutdown  - Power off the system
     clear     - Clear the screen
+    ls        - List directory contents
+    cat       - Print file contents
+    stat      - Display file information
+    cd        - Change the working directory
+    pwd       - Print the working directory
+    mkdir     - Create a directory
+    rm        - Remove a file or an empty directory
+    touch     - Create an empty file
+    mv        - Move or rename a file
+    write     - Append a line of text to a file
+    mount     - Mount a disk (ext2 or vfat) on a directory, or list mounts
+    umount    - Unmount a filesystem
+    sync      - Write cached data to the disks
+    cachestat - Show block cache statistics (readahead BLOCKS [DISK] sets read-ahead)
+    lsblk     - List block devices and partitions
+    lspci     - List PCI devices (-v for BARs and capabilities)
+    random    - Print random bytes (default 16) and the entropy pool state
+    meminfo   - Show physical memory and kernel heap usage
+    exec      - Run an ELF program and wait for it to exit
+    vmmap     - List the active page mappings, or translate an address
     "
     );
 }
@@ -124,6 +229,419 @@ pub fn clear() {
     WRITER.lock().clear_screen()
 }
 
+fn ls(path: &str) -> vfs::Result<()> {
+    let path = fs::absolute_path(path);
+    let inode = VFS.lookup(&path)?;
+    if inode.metadata().file_type != FileType::Directory {
+        println!("    {}\n", path::split_parent(&path).1);
+        return Ok(());
+    }
+
+    for entry in inode.readdir()? {
+        match entry.file_type {
+            FileType::Directory => println!("    {}/", entry.name),
+            FileType::Symlink => println!("    {}@", entry.name),
+            _ => println!("    {}", entry.name),
+        }
+    }
+    println!();
+    Ok(())
+}
+
+fn cat(path: &str) -> vfs::Result<()> {
+    let file = VFS.open(&fs::absolute_path(path), OpenFlags::READ_ONLY, 0)?;
+
+    let mut contents = Vec::new();
+    let mut buffer = [0u8; 512];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        contents.extend_from_slice(&buffer[..read]);
+    }
+
+    match core::str::from_utf8(&contents) {
+        Ok(text) => println!("{}", text),
+        Err(_) => println!(">>> Binary file: {}\n", path),
+    }
+    Ok(())
+}
+
+fn stat(path: &str) -> vfs::Result<()> {
+    let path = fs::absolute_path(path);
+    let metadata = VFS.lookup_no_follow(&path)?.metadata();
+
+    let file_type = match metadata.file_type {
+        FileType::File => "regular file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symbolic link",
+        FileType::CharDevice => "character device",
+        FileType::BlockDevice => "block device",
+    };
+
+    println!(
+        ">>> File: {}
+    Type: {}
+    Size: {} bytes
+    Inode: {}
+    Mode: {:04o}
+    Uid: {}  Gid: {}
+    Modified: {}
+    ",
+        path,
+        file_type,
+        metadata.size,
+        metadata.inode,
+        metadata.mode,
+        metadata.uid,
+        metadata.gid,
+        DateTime::from_timestamp(metadata.mtime)
+    );
+    Ok(())
+}
+
+fn cd(path: &str) -> vfs::Result<()> {
+    fs::change_dir(if path.is_empty() { "/" } else { path })
+}
+
+fn pwd() {
+    println!("{}\n", CURRENT_PROCESS.lock().cwd);
+}
+
+fn mkdir(path: &str) -> vfs::Result<()> {
+    VFS.mkdir(&fs::absolute_path(path), 0o755)
+}
+
+fn rm(path: &str) -> vfs::Result<()> {
+    VFS.unlink(&fs::absolute_path(path))
+}
+
+fn touch(path: &str) -> vfs::Result<()> {
+    VFS.open(
+        &fs::absolute_path(path),
+        OpenFlags::WRITE_ONLY | OpenFlags::CREATE,
+        0o644,
+    )
+    .map(|_| ())
+}
+
+fn mv(from: &str, to: &str) -> vfs::Result<()> {
+    let from = fs::absolute_path(from);
+    let mut to = fs::absolute_path(to);
+    // Moving into an existing directory keeps the original name
+    if VFS
+        .stat(&to)
+        .is_ok_and(|metadata| metadata.file_type == FileType::Directory)
+    {
+        to = path::normalize(&to, path::split_parent(&from).1);
+    }
+    VFS.rename(&from, &to)
+}
+
+fn write(path: &str, text: &str) -> vfs::Result<()> {
+    let flags = OpenFlags::WRITE_ONLY | OpenFlags::CREATE | OpenFlags::APPEND;
+    let file = VFS.open(&fs::absolute_path(path), flags, 0o644)?;
+    file.write(text.as_bytes())?;
+    file.write(b"\n")?;
+    Ok(())
+}
+
+fn mount(device: &str, path: &str, fs_type: &str) -> vfs::Result<()> {
+    if device.is_empty() {
+        for mount in VFS.mounts() {
+            println!("    {} on {}", mount.fs.name(), mount.path);
+        }
+        println!();
+        return Ok(());
+    }
+    fs::mount_device(device, path, fs_type)
+}
+
+fn umount(path: &str) -> vfs::Result<()> {
+    VFS.unmount(&fs::absolute_path(path)).map(|_| ())
+}
+
+fn cache_stat(arg: &str) {
+    if !arg.is_empty() {
+        set_read_ahead(arg);
+        return;
+    }
+    for (name, stats, read_ahead) in devices::cache_stats() {
+        let lookups = stats.hits + stats.misses;
+        let hit_rate = match lookups {
+            0 => 0,
+            _ => stats.hits * 100 / lookups,
+        };
+        println!(
+            "    {}: {} hits, {} misses ({}% hit rate), {} read ahead, {} written back
+        {}/{} blocks cached, {} dirty, read-ahead {} blocks",
+            name,
+            stats.hits,
+            stats.misses,
+            hit_rate,
+            stats.read_ahead,
+            stats.write_backs,
+            stats.cached,
+            stats.capacity,
+            stats.dirty,
+            read_ahead
+        );
+    }
+    println!();
+}
+
+// The window is capped at half the cache, as `cachestat` then shows
+fn set_read_ahead(arg: &str) {
+    let mut args = arg.split_whitespace();
+    let blocks = match (args.next(), args.next().map(str::parse::<usize>)) {
+        (Some("readahead"), Some(Ok(blocks))) => blocks,
+        _ => {
+            println!("cachestat: expected readahead BLOCKS [DISK]\n");
+            return;
+        }
+    };
+    let disk = args.next();
+    if !devices::set_read_ahead(disk, blocks) {
+        println!("cachestat: no such disk: {}\n", disk.unwrap_or(""));
+    }
+}
+
+const RANDOM_DEFAULT: usize = 16;
+const RANDOM_MAX: usize = 256;
+
+fn random(count: &str) {
+    let count = match count {
+        "" => RANDOM_DEFAULT,
+        count => match count.parse::<usize>() {
+            Ok(count) if count <= RANDOM_MAX => count,
+            _ => {
+                println!("random: expected a byte count up to {}\n", RANDOM_MAX);
+                return;
+            }
+        },
+    };
+
+    let mut bytes = alloc::vec![0; count];
+    random::fill(&mut bytes);
+    for line in bytes.chunks(16) {
+        print!("    ");
+        for byte in line {
+            print!("{:02x}", byte);
+        }
+        println!();
+    }
+
+    let stats = random::stats();
+    let mut sources = random::sources();
+    if stats.rdseed {
+        sources.push("rdseed");
+    }
+    if stats.rdrand {
+        sources.push("rdrand");
+    }
+    sources.extend(["tsc jitter", "interrupts"]);
+    println!(
+        "    {} bits in the pool, {} reseeds, {} interrupts sampled
+    sources: {}\n",
+        stats.entropy_bits,
+        stats.reseeds,
+        stats.interrupts,
+        sources.join(", ")
+    );
+}
+
+fn exec(path: &str) -> vfs::Result<()> {
+    match process::spawn(path)? {
+        ExitStatus::Exited(code) => println!(">>> {} exited with status {}\n", path, code),
+        ExitStatus::Segfault(address) => println!(">>> {}: segfault at {:?}\n", path, address),
+        ExitStatus::Killed(error) => println!(">>> {}: killed: {}\n", path, error),
+    }
+    Ok(())
+}
+
+fn vmmap(address: &str) {
+    if !address.is_empty() {
+        let hex = address.trim_start_matches("0x").replace('_', "");
+        let Some(address) = u64::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(|address| VirtAddr::try_new(address).ok())
+        else {
+            println!("vmmap: expected a virtual address in hex\n");
+            return;
+        };
+        match memory::translate(address) {
+            Some((phys, flags)) => {
+                println!("    {:#x} -> {:#x} {}", address, phys, page_flags(flags))
+            }
+            None => println!("    {:#x} is not mapped", address),
+        }
+        return;
+    }
+
+    println!("    START              END                PAGE    FLAGS  PHYSICAL");
+    for region in memory::regions() {
+        let end = region.start.as_u64() + (region.size - 1);
+        let phys = match region.phys {
+            Some(phys) => format!("{:#x}", phys),
+            None => "scattered".to_string(),
+        };
+        println!(
+            "    {:#018x} {:#018x} {:<7} {}  {}",
+            region.start,
+            end,
+            human_size(region.page_size),
+            page_flags(region.flags),
+            phys
+        );
+    }
+}
+
+// Writable, user-accessible, executable and copy-on-write, like `rwx` in other tools
+fn page_flags(flags: PageTableFlags) -> String {
+    let bits = [
+        (flags.contains(PageTableFlags::WRITABLE), 'w'),
+        (flags.contains(PageTableFlags::USER_ACCESSIBLE), 'u'),
+        (!flags.contains(PageTableFlags::NO_EXECUTE), 'x'),
+        (flags.contains(memory::COPY_ON_WRITE), 'c'),
+    ];
+    bits.iter()
+        .map(|&(set, name)| if set { name } else { '-' })
+        .collect()
+}
+
+fn meminfo() {
+    const PAGE_SIZE: u64 = 4096;
+
+    if let Some(frames) = memory::frame_stats() {
+        println!(
+            "    physical: {} total, {} used, {} free",
+            human_size(frames.total as u64 * PAGE_SIZE),
+            human_size(frames.used() as u64 * PAGE_SIZE),
+            human_size(frames.free as u64 * PAGE_SIZE)
+        );
+        let blocks: Vec<String> = (0..=memory::MAX_ORDER)
+            .filter(|&order| frames.free_blocks[order] > 0)
+            .map(|order| format!("{}x{}", frames.free_blocks[order], 1 << order))
+            .collect();
+        println!("    free blocks (count x pages): {}", blocks.join(" "));
+    }
+
+    let stats = allocator::stats();
+    println!(
+        "    heap ({}): {} in use, {} peak, {} mapped of {}, {} used by blocks, {} cached per CPU
+    {} allocations, {} frees, {} failed, {} reclaimed",
+        stats.strategy,
+        human_size(stats.in_use as u64),
+        human_size(stats.peak as u64),
+        human_size(stats.heap_size as u64),
+        human_size(stats.heap_max_size as u64),
+        human_size(stats.heap_used as u64),
+        human_size(stats.cached as u64),
+        stats.allocations,
+        stats.frees,
+        stats.failed_allocations,
+        human_size(stats.reclaimed as u64)
+    );
+    if let Some(largest) = stats.largest_free {
+        println!("    largest free block: {}", human_size(largest as u64));
+    }
+    // Free blocks are the cached ones, plus the free lists of the fixed-size block allocator
+    if stats.classes.iter().any(|class| class.allocations > 0) {
+        println!("    CLASS   ALLOCS    FREES  FREE BLOCKS");
+        for (size, class) in BLOCK_SIZES.iter().zip(stats.classes) {
+            println!(
+                "    {:>5}  {:>7}  {:>7}  {:>11}",
+                size, class.allocations, class.frees, class.free_blocks
+            );
+        }
+    }
+    println!();
+}
+
+fn lsblk() {
+    let partitions = devices::partitions();
+    println!("    NAME      SIZE        START  TYPE");
+    for (disk, device) in devices::disks() {
+        println!(
+            "    {:<8}  {:>10}  {:>5}  disk",
+            disk,
+            human_size(device.size()),
+            0
+        );
+        for partition in partitions.iter().filter(|partition| partition.disk == disk) {
+            let info = &partition.info;
+            println!(
+                "      {:<6}  {:>10}  {:>5}  {}",
+                partition.name,
+                human_size(info.count * device.block_size() as u64),
+                info.start,
+                info.kind.type_name()
+            );
+        }
+    }
+    println!();
+}
+
+fn lspci(verbose: bool) {
+    for (device, driver) in devices::pci_devices() {
+        println!(
+            "    {} {:04x}:{:04x} {} [{}]",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            pci::class_name(device.class, device.subclass),
+            driver.unwrap_or("-")
+        );
+        if !verbose {
+            continue;
+        }
+        for (index, bar) in device.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                println!("        BAR{}: {}", index, bar);
+            }
+        }
+        for capability in &device.capabilities {
+            println!(
+                "        Capability {:#04x}: {}",
+                capability.offset(),
+                capability.name()
+            );
+        }
+        // Only INTA# to INTD# exist; anything else is printed as read
+        match device.interrupt_pin {
+            0 => {}
+            pin @ 1..=4 => println!(
+                "        Interrupt: pin INT{}, line {}",
+                (b'A' + pin - 1) as char,
+                device.interrupt_line
+            ),
+            pin => println!(
+                "        Interrupt: pin {:#04x}, line {}",
+                pin, device.interrupt_line
+            ),
+        }
+    }
+    println!();
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024 * 10 && unit < units.len() - 1 {
+        size /= 1024;
+        unit += 1;
+    }
+    format!("{} {}", size, units[unit])
+}
+
+fn report(path: &str, result: vfs::Result<()>) {
+    if let Err(error) = result {
+        println!(">>> {}: {}\n", path, error);
+    }
+}
+
 fn error_command(command: &str) {
     println!(">>> Command not found: {}\n", command);
 }
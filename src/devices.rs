@@ -0,0 +1,309 @@
+use crate::{Terminal, allocator, interrupts, keyboard};
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use block::{BlockCache, BlockDevice, CacheStats};
+use custom_types::spin_lock::SpinLock;
+use partition::{Partition, PartitionInfo};
+use pci::{DeviceId, Driver, DriverRegistry, PciDevice};
+use serial::serial_println;
+use virtio::{VirtioBlk, VirtioConsole, VirtioRng};
+
+// Blocks cached per disk and blocks read ahead after a miss; the heap is small for now
+const CACHE_BLOCKS: usize = 32;
+const READ_AHEAD_BLOCKS: usize = 8;
+
+pub static BLOCK_DEVICES: SpinLock<BTreeMap<String, Arc<dyn BlockDevice>>> =
+    SpinLock::new(BTreeMap::new());
+pub static BLOCK_CACHES: SpinLock<BTreeMap<String, Arc<BlockCache>>> =
+    SpinLock::new(BTreeMap::new());
+pub static PARTITIONS: SpinLock<Vec<PartitionEntry>> = SpinLock::new(Vec::new());
+pub static PCI_DEVICES: SpinLock<Vec<PciDevice>> = SpinLock::new(Vec::new());
+pub static PCI_DRIVERS: SpinLock<DriverRegistry> = SpinLock::new(DriverRegistry::new());
+
+static IDE_DRIVER: Driver = Driver {
+    name: "ata",
+    ids: &[
+        DeviceId::new(0x8086, 0x7010), // PIIX3
+        DeviceId::new(0x8086, 0x7111), // PIIX4
+    ],
+    probe: probe_ide,
+};
+
+static VIRTIO_BLK_DRIVER: Driver = Driver {
+    name: "virtio-blk",
+    ids: &virtio::device_ids(virtio::TYPE_BLOCK),
+    probe: probe_virtio_blk,
+};
+static VIRTIO_CONSOLE_DRIVER: Driver = Driver {
+    name: "virtio-console",
+    ids: &virtio::device_ids(virtio::TYPE_CONSOLE),
+    probe: probe_virtio_console,
+};
+static VIRTIO_RNG_DRIVER: Driver = Driver {
+    name: "virtio-rng",
+    ids: &virtio::device_ids(virtio::TYPE_ENTROPY),
+    probe: probe_virtio_rng,
+};
+static VIRTIO_BLK_COUNT: SpinLock<u8> = SpinLock::new(0);
+
+// PCI interrupt lines that the firmware left unrouted read back as this
+const NO_INTERRUPT_LINE: u8 = 0xFF;
+
+#[derive(Clone)]
+pub struct PartitionEntry {
+    pub name: String,
+    pub disk: String,
+    pub info: PartitionInfo,
+}
+
+pub fn init() {
+    let devices = pci::enumerate();
+    for device in &devices {
+        serial_println!(
+            "pci: {} {:04x}:{:04x} {}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            pci::class_name(device.class, device.subclass)
+        );
+    }
+    *PCI_DEVICES.lock() = devices;
+    allocator::register_reclaim(shrink_caches).expect("Registering the block cache failed");
+
+    register_pci_driver(&IDE_DRIVER);
+    register_pci_driver(&VIRTIO_BLK_DRIVER);
+    register_pci_driver(&VIRTIO_CONSOLE_DRIVER);
+    register_pci_driver(&VIRTIO_RNG_DRIVER);
+    probe_pci_drivers();
+}
+
+pub fn register_pci_driver(driver: &'static Driver) {
+    PCI_DRIVERS.lock().register(driver);
+}
+
+// Binds every device that is still without a driver; safe to call again after registering more
+pub fn probe_pci_drivers() {
+    let devices = PCI_DEVICES.lock().clone();
+    for probe in PCI_DRIVERS.lock().probe(&devices) {
+        if let Err(error) = probe.result {
+            serial_println!("pci: {}: {} failed: {}", probe.address, probe.driver, error);
+        }
+    }
+}
+
+pub fn pci_devices() -> Vec<(PciDevice, Option<&'static str>)> {
+    let drivers = PCI_DRIVERS.lock();
+    PCI_DEVICES
+        .lock()
+        .iter()
+        .map(|device| (device.clone(), drivers.bound_driver(&device.address)))
+        .collect()
+}
+
+// A channel in native mode takes its ports from the BARs, otherwise it sits at the legacy ports
+fn probe_ide(device: &PciDevice) -> Result<(), &'static str> {
+    device.enable(pci::COMMAND_IO);
+    let channel = |index: usize, legacy: (u16, u16)| {
+        if device.prog_if & (1 << (index * 2)) == 0 {
+            return Ok(legacy);
+        }
+        let io = device.bars[index * 2].and_then(|bar| bar.io_port());
+        let control = device.bars[index * 2 + 1].and_then(|bar| bar.io_port());
+        match (io, control) {
+            (Some(io), Some(control)) => Ok((io, control + 2)),
+            _ => Err("IDE channel without I/O ports"),
+        }
+    };
+    let channels = [
+        channel(0, (ata::PRIMARY_IO, ata::PRIMARY_CONTROL))?,
+        channel(1, (ata::SECONDARY_IO, ata::SECONDARY_CONTROL))?,
+    ];
+
+    for drive in ata::probe_channels(channels) {
+        let info = drive.info();
+        serial_println!(
+            "ata: {}: {} ({} sectors, {})",
+            drive.name(),
+            info.model,
+            info.sectors,
+            if info.lba48 { "LBA48" } else { "LBA28" }
+        );
+        register_disk(drive.name(), Arc::new(drive));
+    }
+    Ok(())
+}
+
+fn probe_virtio_blk(device: &PciDevice) -> Result<(), &'static str> {
+    let disk = Arc::new(VirtioBlk::new(device)?);
+
+    // Without a routed interrupt line the driver keeps polling for completions
+    let handler = disk.clone();
+    if hook_interrupt(device, move || {
+        handler.handle_interrupt();
+    }) {
+        disk.enable_interrupts();
+    }
+
+    let name = {
+        let mut count = VIRTIO_BLK_COUNT.lock();
+        *count += 1;
+        format!("vd{}", (b'a' + *count - 1) as char)
+    };
+    serial_println!(
+        "virtio-blk: {}: {} sectors ({}{}, irq {})",
+        name,
+        disk.block_count(),
+        if disk.is_modern() { "modern" } else { "legacy" },
+        if disk.is_read_only() {
+            ", read-only"
+        } else {
+            ""
+        },
+        device.interrupt_line
+    );
+    register_disk(name, disk);
+    Ok(())
+}
+
+fn probe_virtio_console(device: &PciDevice) -> Result<(), &'static str> {
+    let console = Arc::new(VirtioConsole::new(device)?);
+
+    let handler = console.clone();
+    if !hook_interrupt(device, move || {
+        for byte in handler.handle_interrupt() {
+            keyboard::handle_byte(byte);
+        }
+    }) {
+        serial_println!("virtio-console: no interrupt line, input is disabled");
+    }
+
+    for port in console.ports() {
+        serial_println!(
+            "virtio-console: port {}{}{}",
+            port.id,
+            if port.name.is_empty() { "" } else { " " },
+            port.name
+        );
+    }
+    crate::register_terminal(console);
+    Ok(())
+}
+
+impl Terminal for VirtioConsole {
+    fn write(&self, bytes: &[u8]) {
+        // Serial terminals expect a carriage return before each line feed
+        for (i, line) in bytes.split(|&byte| byte == b'\n').enumerate() {
+            if i > 0 {
+                VirtioConsole::write(self, b"\r\n");
+            }
+            if !line.is_empty() {
+                VirtioConsole::write(self, line);
+            }
+        }
+    }
+}
+
+fn probe_virtio_rng(device: &PciDevice) -> Result<(), &'static str> {
+    let rng = VirtioRng::new(device)?;
+    let collected = random::register_source("virtio-rng", move |buf| rng.fill(buf).unwrap_or(0));
+    serial_println!("virtio-rng: {} bytes of entropy", collected);
+    Ok(())
+}
+
+fn hook_interrupt(device: &PciDevice, handler: impl Fn() + Send + Sync + 'static) -> bool {
+    device.interrupt_pin != 0
+        && device.interrupt_line != NO_INTERRUPT_LINE
+        && interrupts::register_irq(device.interrupt_line, handler).is_ok()
+}
+
+// Whole disks are registered behind a block cache, which their partitions share
+pub fn register_disk(name: String, device: Arc<dyn BlockDevice>) {
+    let cache = Arc::new(BlockCache::new(device, CACHE_BLOCKS, READ_AHEAD_BLOCKS));
+    BLOCK_CACHES.lock().insert(name.clone(), cache.clone());
+    register_block_device(name.clone(), cache.clone());
+    register_partitions(&name, cache);
+}
+
+fn register_partitions(disk: &str, device: Arc<dyn BlockDevice>) {
+    let partitions = match partition::scan(device.as_ref()) {
+        Ok(partitions) => partitions,
+        Err(error) => {
+            serial_println!("{}: invalid partition table: {}", disk, error);
+            return;
+        }
+    };
+
+    for info in partitions {
+        let name = format!("{}{}", disk, info.number);
+        serial_println!(
+            "{}: {} ({} sectors at {})",
+            name,
+            info.kind.type_name(),
+            info.count,
+            info.start
+        );
+        register_block_device(
+            name.clone(),
+            Arc::new(Partition::new(device.clone(), &info)),
+        );
+        PARTITIONS.lock().push(PartitionEntry {
+            name,
+            disk: String::from(disk),
+            info,
+        });
+    }
+}
+
+pub fn register_block_device(name: String, device: Arc<dyn BlockDevice>) {
+    BLOCK_DEVICES.lock().insert(name, device);
+}
+
+pub fn block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock().get(name).cloned()
+}
+
+pub fn partitions() -> Vec<PartitionEntry> {
+    PARTITIONS.lock().clone()
+}
+
+pub fn disks() -> Vec<(String, Arc<dyn BlockDevice>)> {
+    BLOCK_CACHES
+        .lock()
+        .iter()
+        .map(|(name, cache)| (name.clone(), cache.clone() as Arc<dyn BlockDevice>))
+        .collect()
+}
+
+// Each disk's statistics and read-ahead window in blocks
+pub fn cache_stats() -> Vec<(String, CacheStats, usize)> {
+    BLOCK_CACHES
+        .lock()
+        .iter()
+        .map(|(name, cache)| (name.clone(), cache.stats(), cache.read_ahead()))
+        .collect()
+}
+
+// Sets the read-ahead window of one disk, or of all of them; false if there is no such disk
+pub fn set_read_ahead(disk: Option<&str>, blocks: usize) -> bool {
+    let caches = BLOCK_CACHES.lock();
+    let mut found = false;
+    for (name, cache) in caches.iter() {
+        if disk.is_none_or(|disk| disk == name) {
+            cache.set_read_ahead(blocks);
+            found = true;
+        }
+    }
+    found
+}
+
+// Called when the heap runs out; caches that are in use are left alone
+fn shrink_caches() -> usize {
+    let Some(caches) = BLOCK_CACHES.try_lock() else {
+        return 0;
+    };
+    caches.values().map(|cache| cache.shrink()).sum()
+}
+
+pub fn sync() -> block::Result<()> {
+    let caches: Vec<_> = BLOCK_CACHES.lock().values().cloned().collect();
+    caches.iter().try_for_each(|cache| cache.flush())
+}
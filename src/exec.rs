@@ -0,0 +1,129 @@
+use crate::fs::VFS;
+use alloc::{sync::Arc, vec::Vec};
+use custom_types::fallible::try_zeroed;
+use elf::{Header, Segment};
+use memory::{AddressSpace, Segfault, USER_END, USER_START, VmaFlags, VmaKind};
+use vfs::{Error, Inode, Result};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+const STACK_SIZE: u64 = 64 * PAGE_SIZE;
+const STACK_START: u64 = USER_END - STACK_SIZE;
+// Leaves room for argc, the argv and envp terminators and an empty auxiliary vector, all zero
+const INITIAL_STACK: u64 = USER_END - 64;
+
+// An executable checked far enough that loading it can only run out of memory or hit a disk error
+pub struct Image {
+    inode: Arc<dyn Inode>,
+    entry: u64,
+    segments: Vec<Segment>,
+}
+
+impl Image {
+    pub fn open(path: &str) -> Result<Image> {
+        let inode = VFS.lookup(path)?;
+        let mut header = [0; elf::HEADER_SIZE];
+        read_exact(&*inode, 0, &mut header)?;
+        let header = Header::parse(&header).map_err(|_| Error::NotExecutable)?;
+        let (offset, len) = header.program_header_table();
+        let mut table = try_zeroed(len).map_err(|_| Error::OutOfMemory)?;
+        read_exact(&*inode, offset, &mut table)?;
+
+        let mut segments: Vec<Segment> = header.segments(&table).collect();
+        segments.sort_by_key(|segment| segment.address);
+        let mut end = USER_START;
+        for segment in &segments {
+            let (start, segment_end) = page_range(segment).ok_or(Error::NotExecutable)?;
+            if start < end || segment_end > STACK_START || segment.file_size > segment.memory_size {
+                return Err(Error::NotExecutable);
+            }
+            end = segment_end;
+        }
+        Ok(Image {
+            inode,
+            entry: header.entry,
+            segments,
+        })
+    }
+
+    // Replaces the user half of the address space, which must be the active one, with the program.
+    // The heap starts empty right after it. Returns the entry point and the initial stack pointer
+    pub fn load(&self, address_space: &mut AddressSpace) -> Result<(VirtAddr, VirtAddr)> {
+        address_space.clear();
+        let mut program_end = USER_START;
+        for segment in &self.segments {
+            let (start, end) = page_range(segment).ok_or(Error::NotExecutable)?;
+            program_end = end;
+            address_space
+                .map(
+                    VirtAddr::new(start),
+                    end - start,
+                    vma_flags(segment.flags),
+                    VmaKind::Anonymous,
+                )
+                .map_err(|_| Error::NotExecutable)?;
+            self.copy_segment(segment, address_space)?;
+        }
+        address_space
+            .map(
+                VirtAddr::new(STACK_START),
+                STACK_SIZE,
+                VmaFlags::READ | VmaFlags::WRITE,
+                VmaKind::Anonymous,
+            )
+            .map_err(|_| Error::NotExecutable)?;
+        address_space.set_heap_start(VirtAddr::new(program_end));
+        Ok((VirtAddr::new(self.entry), VirtAddr::new(INITIAL_STACK)))
+    }
+
+    // The rest of the segment is left to be zero-filled on first touch
+    fn copy_segment(&self, segment: &Segment, address_space: &mut AddressSpace) -> Result<()> {
+        let mut buffer = [0; PAGE_SIZE as usize];
+        let mut done = 0;
+        while done < segment.file_size {
+            let len = (segment.file_size - done).min(PAGE_SIZE) as usize;
+            read_exact(&*self.inode, segment.offset + done, &mut buffer[..len])?;
+            address_space
+                .write(VirtAddr::new(segment.address + done), &buffer[..len])
+                .map_err(|error| match error {
+                    Segfault::OutOfMemory => Error::OutOfMemory,
+                    _ => Error::NotExecutable,
+                })?;
+            done += len as u64;
+        }
+        Ok(())
+    }
+}
+
+fn page_range(segment: &Segment) -> Option<(u64, u64)> {
+    let end = segment.address.checked_add(segment.memory_size)?;
+    let start = segment.address & !(PAGE_SIZE - 1);
+    let end = end.checked_next_multiple_of(PAGE_SIZE)?;
+    (USER_START <= start && start < end).then_some((start, end))
+}
+
+fn vma_flags(flags: u32) -> VmaFlags {
+    let mut vma_flags = VmaFlags(0);
+    if flags & elf::READ != 0 {
+        vma_flags = vma_flags | VmaFlags::READ;
+    }
+    if flags & elf::WRITE != 0 {
+        vma_flags = vma_flags | VmaFlags::WRITE;
+    }
+    if flags & elf::EXECUTE != 0 {
+        vma_flags = vma_flags | VmaFlags::EXEC;
+    }
+    vma_flags
+}
+
+// A short read means the file ends before the headers say it does
+fn read_exact(inode: &dyn Inode, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match inode.read_at(offset + done as u64, &mut buf[done..])? {
+            0 => return Err(Error::NotExecutable),
+            read => done += read,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::{devices, process::CURRENT_PROCESS};
+use alloc::{string::String, sync::Arc};
+use allocators::fixed_block::HEAP_MAX_SIZE;
+use ext2::Ext2Fs;
+use fat::FatFs;
+use initrd::{Initrd, InitrdFs};
+use lazy_static::lazy_static;
+use tmpfs::Tmpfs;
+use vfs::{Error, FileType, Filesystem, Vfs, path};
+
+static INITRD_ARCHIVE: &[u8] = include_bytes!("../initrd.tar");
+// File data lives on the heap, which grows on demand up to its maximum
+const TMPFS_SIZE: usize = HEAP_MAX_SIZE / 4;
+
+pub static VFS: Vfs = Vfs::new();
+
+lazy_static! {
+    pub static ref INITRD: Initrd = Initrd::parse(INITRD_ARCHIVE).expect("Initrd parsing failed");
+}
+
+pub fn init() {
+    VFS.mount("/", Arc::new(InitrdFs::new(&INITRD)))
+        .expect("Mounting initrd failed");
+    VFS.mount("/tmp", Arc::new(Tmpfs::new(TMPFS_SIZE)))
+        .expect("Mounting tmpfs failed");
+}
+
+// Without an explicit type every known filesystem is tried in turn
+pub fn mount_device(device: &str, path: &str, fs_type: &str) -> vfs::Result<()> {
+    let device = devices::block_device(device).ok_or(Error::NotFound)?;
+    let fs: Arc<dyn Filesystem> = match fs_type {
+        "ext2" => Arc::new(Ext2Fs::mount(device)?),
+        "vfat" | "fat" => Arc::new(FatFs::mount(device)?),
+        "" => match Ext2Fs::mount(device.clone()) {
+            Ok(fs) => Arc::new(fs),
+            Err(_) => Arc::new(FatFs::mount(device)?),
+        },
+        _ => return Err(Error::InvalidArgument),
+    };
+    VFS.mount(&absolute_path(path), fs)
+}
+
+// Filesystems write their metadata first, then every disk cache is flushed
+pub fn sync() -> vfs::Result<()> {
+    VFS.sync()?;
+    devices::sync().map_err(|_| Error::Io)
+}
+
+pub fn absolute_path(path: &str) -> String {
+    path::normalize(&CURRENT_PROCESS.lock().cwd, path)
+}
+
+pub fn change_dir(path: &str) -> vfs::Result<()> {
+    let path = absolute_path(path);
+    if VFS.stat(&path)?.file_type != FileType::Directory {
+        return Err(Error::NotADirectory);
+    }
+    CURRENT_PROCESS.lock().cwd = path;
+    Ok(())
+}
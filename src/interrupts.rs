@@ -1,11 +1,23 @@
 use super::hlt_loop;
-use crate::{keyboard::print_scancode, println, syscalls::syscall_entry};
+use crate::{
+    keyboard::print_scancode,
+    println,
+    process::{self, CURRENT_PROCESS},
+    syscalls::syscall_entry,
+};
+use alloc::{boxed::Box, vec::Vec};
 use core::{ops::IndexMut, sync::atomic::Ordering};
 use custom_types::spin_lock::SpinLock;
 use datetime::{CURRENT_TIME, TICKS};
 use lazy_static::lazy_static;
+use memory::{Access, FilePage, Segfault, USER_END, USER_START};
 use pic8259::ChainedPics;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{
+    PrivilegeLevel,
+    structures::idt::{
+        HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+    },
+};
 
 const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = 40;
@@ -13,22 +25,51 @@ const PIC_2_OFFSET: u8 = 40;
 pub static PICS: SpinLock<ChainedPics> =
     SpinLock::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+// Lines left to devices; PCI interrupts may be shared, so a line can have several handlers
+const CASCADE_LINE: u8 = 2;
+const DEVICE_LINES: [(u8, HandlerFunc); 13] = [
+    (3, irq_handler::<3>),
+    (4, irq_handler::<4>),
+    (5, irq_handler::<5>),
+    (6, irq_handler::<6>),
+    (7, irq_handler::<7>),
+    (8, irq_handler::<8>),
+    (9, irq_handler::<9>),
+    (10, irq_handler::<10>),
+    (11, irq_handler::<11>),
+    (12, irq_handler::<12>),
+    (13, irq_handler::<13>),
+    (14, irq_handler::<14>),
+    (15, irq_handler::<15>),
+];
+
+type IrqHandler = Box<dyn Fn() + Send + Sync>;
+static IRQ_HANDLERS: SpinLock<Vec<(u8, IrqHandler)>> = SpinLock::new(Vec::new());
+
+static STACK_OVERFLOW_HOOK: SpinLock<Option<fn(&'static str)>> = SpinLock::new(None);
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
         unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
             idt.index_mut(0x80)
-                .set_handler_addr(x86_64::VirtAddr::new(syscall_entry as u64));
+                .set_handler_addr(x86_64::VirtAddr::new(syscall_entry as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
         }
         idt.index_mut(InterruptIndex::Timer.as_u8())
             .set_handler_fn(timer_interrupt_handler);
         idt.index_mut(InterruptIndex::Keyboard.as_u8())
             .set_handler_fn(keyboard_interrupt_handler);
+        for (line, handler) in DEVICE_LINES {
+            idt.index_mut(PIC_1_OFFSET + line).set_handler_fn(handler);
+        }
 
         idt
     };
@@ -55,6 +96,39 @@ pub fn init_idt() {
     IDT.load();
 }
 
+pub fn register_irq(
+    line: u8,
+    handler: impl Fn() + Send + Sync + 'static,
+) -> Result<(), &'static str> {
+    if !DEVICE_LINES
+        .iter()
+        .any(|&(device_line, _)| device_line == line)
+    {
+        return Err("Interrupt line is not available to devices");
+    }
+
+    // The handlers run in interrupt context, so the list must not be locked when one fires
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        IRQ_HANDLERS.lock().push((line, Box::new(handler)));
+
+        let mut pics = PICS.lock();
+        let [mut primary, mut secondary] = unsafe { pics.read_masks() };
+        if line < 8 {
+            primary &= !(1 << line);
+        } else {
+            secondary &= !(1 << (line - 8));
+            primary &= !(1 << CASCADE_LINE);
+        }
+        unsafe { pics.write_masks(primary, secondary) };
+    });
+    Ok(())
+}
+
+// Runs with the name of the overflowed stack once the overflow is reported, before halting
+pub fn set_stack_overflow_hook(hook: fn(&'static str)) {
+    *STACK_OVERFLOW_HOOK.lock() = Some(hook);
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
@@ -65,6 +139,58 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    if let Ok(address) = Cr2::read() {
+        // The faulting stack is unusable, so there is nothing to do but report it
+        if let Some(stack) = memory::stack_guard(address) {
+            println!("EXCEPTION: stack overflow in {}", stack);
+            println!("Accessed Address: {:?}", address);
+            println!("{:#?}", stack_frame);
+            let hook = *STACK_OVERFLOW_HOOK.lock();
+            if let Some(hook) = hook {
+                hook(stack);
+            }
+            hlt_loop();
+        }
+        let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            Access::Execute
+        } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            Access::Write
+        } else {
+            Access::Read
+        };
+        let present = error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+        // Only user addresses can be backed on demand; anything else is a bug or a bad access
+        let user_address = (USER_START..USER_END).contains(&address.as_u64());
+        let fault = if user_address {
+            let fault =
+                (CURRENT_PROCESS.lock().address_space).handle_fault(address, access, present);
+            // With the process unlocked, so the read may take the file's locks
+            fault.and_then(|file_page| file_page.map_or(Ok(()), FilePage::read_in))
+        } else {
+            Err(Segfault::Unmapped)
+        };
+        match fault {
+            Ok(()) => return,
+            // User code is killed for a bad access, and so is a system call making one on its
+            // behalf, while the kernel keeps running
+            Err(fault)
+                if error_code.contains(PageFaultErrorCode::USER_MODE)
+                    || (user_address && process::in_user_program()) =>
+            {
+                let pid = CURRENT_PROCESS.lock().pid;
+                println!(
+                    "segfault: pid {} at {:?}, {:?} ({})",
+                    pid,
+                    address,
+                    access,
+                    fault.reason()
+                );
+                process::kill_segfault(address);
+            }
+            Err(_) => {}
+        }
+    }
+
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
@@ -82,6 +208,7 @@ extern "x86-interrupt" fn double_fault_handler(
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
     TICKS.fetch_add(1, Ordering::Relaxed);
+    random::add_interrupt_timing();
 
     if TICKS.load(Ordering::Relaxed) % 1000 == 0 {
         let mut time = CURRENT_TIME.lock();
@@ -99,6 +226,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 
     let mut port = Port::new(0x60);
     let scancode = unsafe { port.read() };
+    random::add_interrupt_timing();
     print_scancode(scancode);
 
     unsafe {
@@ -107,6 +235,19 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn irq_handler<const LINE: u8>(_stack_frame: InterruptStackFrame) {
+    random::add_interrupt_timing();
+    for (line, handler) in IRQ_HANDLERS.lock().iter() {
+        if *line == LINE {
+            handler();
+        }
+    }
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + LINE);
+    }
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
@@ -5,6 +5,7 @@ use custom_types::spin_lock::SpinLock;
 
 const ENTER: u8 = 0x1C;
 const BACKSPACE: u8 = 0x0E;
+const DELETE: char = '\x08';
 static LINE_BUFFER: SpinLock<String> = SpinLock::new(String::new());
 
 #[inline]
@@ -22,7 +23,7 @@ fn get_scancode(scancode: u8) -> Option<char> {
         0x0B => Some('0'),
         0x0C => Some('-'),
         0x0D => Some('='),
-        BACKSPACE => Some('#'),
+        BACKSPACE => Some(DELETE),
         0x0F => Some('\t'),
         0x10 => Some('q'),
         0x11 => Some('w'),
@@ -69,19 +70,36 @@ fn get_scancode(scancode: u8) -> Option<char> {
 
 #[inline]
 pub fn print_scancode(scancode: u8) {
-    match get_scancode(scancode) {
-        Some('\n') => {
+    if let Some(char) = get_scancode(scancode) {
+        handle_char(char);
+    }
+}
+
+// Input from serial-like terminals, which send carriage returns and DEL for backspace
+pub fn handle_byte(byte: u8) {
+    match byte {
+        b'\r' | b'\n' => handle_char('\n'),
+        0x08 | 0x7F => handle_char(DELETE),
+        0x20..=0x7E | b'\t' => handle_char(byte as char),
+        _ => {}
+    }
+}
+
+fn handle_char(char: char) {
+    match char {
+        '\n' => {
             let buffer = core::mem::take(LINE_BUFFER.lock().deref_mut());
             Command::from(buffer.as_str()).execute();
         }
-        Some('#') => {
-            LINE_BUFFER.lock().pop();
-            WRITER.lock().delete_char();
+        DELETE => {
+            if LINE_BUFFER.lock().pop().is_some() {
+                WRITER.lock().delete_char();
+                crate::write_terminals(b"\x08 \x08");
+            }
         }
-        Some(char) => {
+        char => {
             print!("{}", char);
             LINE_BUFFER.lock().push(char);
         }
-        None => {}
     }
 }
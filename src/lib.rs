@@ -1,6 +1,7 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
@@ -9,10 +10,16 @@ extern crate alloc;
 
 pub mod allocator;
 pub mod commands;
+pub mod devices;
+pub mod exec;
+pub mod fs;
 pub mod interrupts;
 pub mod keyboard;
+pub mod process;
+pub mod stacks;
 pub mod syscalls;
 
+use alloc::{sync::Arc, vec::Vec};
 use custom_types::spin_lock::SpinLock;
 use lazy_static::lazy_static;
 use vga::{
@@ -59,9 +66,40 @@ pub fn _print(args: core::fmt::Arguments) {
     // Stop interrupts while we're printing
     interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).expect("Printing failed");
+        for terminal in TERMINALS.lock().iter() {
+            let _ = TerminalWriter(terminal.as_ref()).write_fmt(args);
+        }
+    });
+}
+
+// Additional terminals that mirror the VGA output and feed their input to the shell
+pub trait Terminal: Send + Sync {
+    fn write(&self, bytes: &[u8]);
+}
+
+static TERMINALS: SpinLock<Vec<Arc<dyn Terminal>>> = SpinLock::new(Vec::new());
+
+pub fn register_terminal(terminal: Arc<dyn Terminal>) {
+    x86_64::instructions::interrupts::without_interrupts(|| TERMINALS.lock().push(terminal));
+}
+
+pub fn write_terminals(bytes: &[u8]) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        for terminal in TERMINALS.lock().iter() {
+            terminal.write(bytes);
+        }
     });
 }
 
+struct TerminalWriter<'a>(&'a dyn Terminal);
+
+impl core::fmt::Write for TerminalWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
@@ -138,9 +176,66 @@ pub fn test_panic_handler(info: &core::panic::PanicInfo) -> ! {
     hlt_loop();
 }
 
+// Boots as far as tests of processes and files need: interrupts, paging with the heap and
+// frame allocator installed, and the root filesystems mounted
+pub fn test_init(boot_info: &'static BootInfo) {
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { memory::BuddyFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    fs::init();
+}
+
+// Where `test_run` loads user code and puts its stack
+const TEST_CODE: u64 = USER_START + 0x10_0000;
+const TEST_STACK: u64 = USER_START + 0x20_0000;
+
+// Maps anonymous user memory into the current process
+pub fn test_map(start: u64, pages: u64, flags: VmaFlags) {
+    let mut process = CURRENT_PROCESS.lock();
+    (process.address_space)
+        .map(
+            VirtAddr::new(start),
+            pages * 4096,
+            flags,
+            VmaKind::Anonymous,
+        )
+        .expect("mapping failed");
+}
+
+pub fn test_unmap(start: u64, pages: u64) {
+    let mut process = CURRENT_PROCESS.lock();
+    (process.address_space)
+        .unmap(VirtAddr::new(start), pages * 4096)
+        .expect("unmapping failed");
+}
+
+// Maps the code and a stack, then runs the code in user mode. Whatever the code maps itself
+// is left to the caller
+pub fn test_run(code: &[u8]) -> ExitStatus {
+    test_map(TEST_CODE, 1, VmaFlags::READ | VmaFlags::EXEC);
+    test_map(TEST_STACK, 4, VmaFlags::READ | VmaFlags::WRITE);
+    (CURRENT_PROCESS.lock().address_space)
+        .write(VirtAddr::new(TEST_CODE), code)
+        .expect("loading failed");
+    let status = process::run_user(
+        VirtAddr::new(TEST_CODE),
+        VirtAddr::new(TEST_STACK + 4 * 4096),
+    );
+    test_unmap(TEST_CODE, 1);
+    test_unmap(TEST_STACK, 4);
+    status
+}
+
+use bootloader::BootInfo;
 #[cfg(test)]
-use bootloader::{BootInfo, entry_point};
+use bootloader::entry_point;
+use memory::{USER_START, VmaFlags, VmaKind};
+use process::{CURRENT_PROCESS, ExitStatus};
 use serial::{serial_print, serial_println};
+use x86_64::VirtAddr;
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
@@ -19,9 +19,18 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = memory::BootInfoFrameAllocator::init(&boot_info.memory_map);
+    let mut frame_allocator = unsafe { memory::BuddyFrameAllocator::init(&boot_info.memory_map) };
 
     init_heap(&mut mapper, &mut frame_allocator).expect("Heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    rust_system::stacks::init();
+    rust_system::stacks::run_on_new_stack("kernel", kernel_thread);
+}
+
+fn kernel_thread() -> ! {
+    rust_system::devices::init();
+    random::init();
+    rust_system::fs::init();
 
     #[cfg(test)]
     test_main();
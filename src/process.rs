@@ -0,0 +1,325 @@
+use crate::{exec::Image, fs::absolute_path};
+use alloc::string::String;
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use custom_types::spin_lock::SpinLock;
+use lazy_static::lazy_static;
+use memory::AddressSpace;
+use vfs::{Error, FdTable, Result};
+use x86_64::{VirtAddr, instructions::interrupts};
+
+const KERNEL_STACK_PAGES: u64 = 8;
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
+pub struct Process {
+    pub pid: usize,
+    pub cwd: String,
+    pub files: FdTable,
+    pub address_space: AddressSpace,
+    // Interrupts and system calls from the process run here; the kernel's own process
+    // keeps the stack set up at boot
+    kernel_stack: Option<VirtAddr>,
+}
+
+impl Process {
+    pub fn new(pid: usize) -> Self {
+        Process {
+            pid,
+            cwd: String::from("/"),
+            files: FdTable::new(),
+            address_space: AddressSpace::new(),
+            kernel_stack: None,
+        }
+    }
+
+    // A new process sharing the open files, with the memory copied on write. Only valid for
+    // the current process, whose address space is the active one
+    pub fn fork(&self) -> Result<Process> {
+        let address_space = self.address_space.fork().map_err(|_| Error::OutOfMemory)?;
+        let kernel_stack = memory::allocate_stack("user process", KERNEL_STACK_PAGES)
+            .map_err(|_| Error::OutOfMemory)?;
+        Ok(Process {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+            cwd: self.cwd.clone(),
+            files: self.files.clone(),
+            address_space,
+            kernel_stack: Some(kernel_stack),
+        })
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        if let Some(top) = self.kernel_stack {
+            unsafe { memory::free_stack(top) };
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref CURRENT_PROCESS: SpinLock<Process> = SpinLock::new(Process::new(0));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(u64),
+    Segfault(VirtAddr),
+    // The kernel gave up on the process, like an `execve` that failed after dropping the old program
+    Killed(Error),
+}
+
+// User registers as `syscall_entry` saves them, followed by the interrupt frame
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UserRegisters {
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rax: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// Kernel stack pointer saved while user code runs, zero otherwise
+static KERNEL_RSP: AtomicU64 = AtomicU64::new(0);
+static EXIT_STATUS: SpinLock<Option<ExitStatus>> = SpinLock::new(None);
+
+// Runs user code in the current address space until it exits or is killed.
+// Only one user program runs at a time, and the kernel waits for it
+pub fn run_user(entry: VirtAddr, stack: VirtAddr) -> ExitStatus {
+    let (code, data) = gdt::user_selectors();
+    run(|saved_rsp| unsafe {
+        enter_user(
+            entry.as_u64(),
+            stack.as_u64(),
+            code.0 as u64,
+            data.0 as u64,
+            saved_rsp,
+        )
+    })
+}
+
+// Runs may nest: a process waits in a system call while the process it started runs.
+// KERNEL_RSP only holds the innermost run's stack, where `leave_user` has to return to, so the
+// outer run's value waits on this stack and is put back once the inner program is done. The
+// waiting process can then still leave through its own run
+fn run(enter: impl FnOnce(*mut u64)) -> ExitStatus {
+    let enabled = interrupts::are_enabled();
+    let outer = KERNEL_RSP.load(Ordering::Relaxed);
+    enter(KERNEL_RSP.as_ptr());
+    // We come back here from an interrupt handler, which left interrupts off
+    KERNEL_RSP.store(outer, Ordering::Relaxed);
+    if enabled {
+        interrupts::enable();
+    }
+    EXIT_STATUS.lock().take().unwrap()
+}
+
+// Makes the process current for `run`, then switches back and drops it
+fn run_child<T>(child: Process, run: impl FnOnce() -> T) -> T {
+    let kernel_stack = child
+        .kernel_stack
+        .expect("Only forked processes run as children");
+    let parent = switch_to(child);
+    let parent_stack = gdt::privilege_stack();
+    gdt::set_privilege_stack(kernel_stack);
+    let result = run();
+    gdt::set_privilege_stack(parent_stack);
+    drop(switch_to(parent));
+    result
+}
+
+fn switch_to(process: Process) -> Process {
+    let mut current = CURRENT_PROCESS.lock();
+    process.address_space.activate();
+    core::mem::replace(&mut *current, process)
+}
+
+// The child starts where the parent made the call, seeing a return value of zero. It runs to
+// completion before the parent continues
+pub fn fork(registers: &UserRegisters) -> Result<u64> {
+    if !in_user_program() {
+        return Err(Error::InvalidArgument);
+    }
+    let child = CURRENT_PROCESS.lock().fork()?;
+    let pid = child.pid;
+    let registers = UserRegisters {
+        rax: 0,
+        ..*registers
+    };
+    run_child(child, || {
+        run(|saved_rsp| unsafe { resume_user(&registers, saved_rsp) })
+    });
+    Ok(pid as u64)
+}
+
+// Replaces the program of the current process; returns only if the new one cannot be opened.
+// The new program starts on a fresh stack and this one is abandoned, so it takes the path to
+// drop it along with everything else it owns before jumping
+pub fn execve(path: String) -> Result<u64> {
+    if !in_user_program() {
+        return Err(Error::InvalidArgument);
+    }
+    match load(path)? {
+        Ok((entry, stack)) => {
+            let (code, data) = gdt::user_selectors();
+            unsafe { jump_to_user(entry.as_u64(), stack.as_u64(), code.0 as u64, data.0 as u64) }
+        }
+        // The old program is gone already
+        Err(error) => leave_user(ExitStatus::Killed(error)),
+    }
+}
+
+// Fails outright if the program cannot be opened, and with the inner error once the old one
+// has been dropped
+fn load(path: String) -> Result<Result<(VirtAddr, VirtAddr)>> {
+    let image = Image::open(&absolute_path(&path))?;
+    drop(path);
+    Ok(image.load(&mut CURRENT_PROCESS.lock().address_space))
+}
+
+// Runs a program in a new process and waits for it
+pub fn spawn(path: &str) -> Result<ExitStatus> {
+    let image = Image::open(&absolute_path(path))?;
+    let child = CURRENT_PROCESS.lock().fork()?;
+    run_child(child, || {
+        let (entry, stack) = image.load(&mut CURRENT_PROCESS.lock().address_space)?;
+        Ok(run_user(entry, stack))
+    })
+}
+
+pub fn in_user_program() -> bool {
+    KERNEL_RSP.load(Ordering::Relaxed) != 0
+}
+
+pub fn exit(code: u64) -> Result<u64> {
+    if !in_user_program() {
+        return Err(Error::InvalidArgument);
+    }
+    leave_user(ExitStatus::Exited(code))
+}
+
+// Called from the page fault handler, which must not hold any locks by now. A system call it
+// interrupts is abandoned along with its stack; checking user pointers first leaves that to
+// pages that could not be backed
+pub fn kill_segfault(address: VirtAddr) -> ! {
+    leave_user(ExitStatus::Segfault(address))
+}
+
+// Drops the stack of the system call or fault and continues after `run_user`
+fn leave_user(status: ExitStatus) -> ! {
+    *EXIT_STATUS.lock() = Some(status);
+    unsafe { return_to_kernel(KERNEL_RSP.load(Ordering::Relaxed)) }
+}
+
+global_asm!(
+    r#"
+.globl enter_user
+.text
+// enter_user(entry, stack, code_selector, data_selector, saved_rsp)
+enter_user:
+    push %rbx
+    push %rbp
+    push %r12
+    push %r13
+    push %r14
+    push %r15
+    mov %rsp, (%r8)
+    // The remaining arguments are where `jump_to_user` expects them
+
+.globl jump_to_user
+// jump_to_user(entry, stack, code_selector, data_selector)
+jump_to_user:
+    // Interrupt frame for iretq: stack segment, stack, flags with interrupts on, code segment, entry
+    push %rcx
+    push %rsi
+    push $0x202
+    push %rdx
+    push %rdi
+
+    // Don't leak kernel values into user mode
+    xor %eax, %eax
+    xor %ebx, %ebx
+    xor %ecx, %ecx
+    xor %edx, %edx
+    xor %esi, %esi
+    xor %edi, %edi
+    xor %ebp, %ebp
+    xor %r8d, %r8d
+    xor %r9d, %r9d
+    xor %r10d, %r10d
+    xor %r11d, %r11d
+    xor %r12d, %r12d
+    xor %r13d, %r13d
+    xor %r14d, %r14d
+    xor %r15d, %r15d
+    iretq
+
+.globl resume_user
+// resume_user(registers, saved_rsp)
+resume_user:
+    push %rbx
+    push %rbp
+    push %r12
+    push %r13
+    push %r14
+    push %r15
+    mov %rsp, (%rsi)
+
+    // The registers are laid out like the stack `syscall_entry` returns from
+    mov %rdi, %rsp
+    pop %r11
+    pop %r10
+    pop %r9
+    pop %r8
+    pop %rcx
+    pop %rdx
+    pop %rsi
+    pop %rdi
+    pop %rax
+    pop %r15
+    pop %r14
+    pop %r13
+    pop %r12
+    pop %rbp
+    pop %rbx
+    iretq
+
+.globl return_to_kernel
+// return_to_kernel(saved_rsp)
+return_to_kernel:
+    mov %rdi, %rsp
+    pop %r15
+    pop %r14
+    pop %r13
+    pop %r12
+    pop %rbp
+    pop %rbx
+    ret
+"#,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    fn enter_user(entry: u64, stack: u64, code: u64, data: u64, saved_rsp: *mut u64);
+    fn jump_to_user(entry: u64, stack: u64, code: u64, data: u64) -> !;
+    fn resume_user(registers: *const UserRegisters, saved_rsp: *mut u64);
+    fn return_to_kernel(saved_rsp: u64) -> !;
+}
@@ -0,0 +1,34 @@
+use gdt::{DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX};
+use x86_64::VirtAddr;
+
+const INTERRUPT_STACK_PAGES: u64 = 5;
+const KERNEL_STACK_PAGES: u64 = 64;
+
+// Replaces the boot stacks in the TSS with guarded ones. Needs the heap and `memory::install`
+pub fn init() {
+    let stack = |name| {
+        memory::allocate_stack(name, INTERRUPT_STACK_PAGES).expect("Allocating a stack failed")
+    };
+    gdt::set_interrupt_stack(DOUBLE_FAULT_IST_INDEX, stack("double fault handler"));
+    // Page faults get their own stack, so a guard page hit can still be reported
+    gdt::set_interrupt_stack(PAGE_FAULT_IST_INDEX, stack("page fault handler"));
+    gdt::set_privilege_stack(stack("system calls"));
+}
+
+// Continues on a fresh guarded stack, leaving the bootloader's stack behind
+pub fn run_on_new_stack(name: &'static str, main: fn() -> !) -> ! {
+    let top = memory::allocate_stack(name, KERNEL_STACK_PAGES).expect("Allocating a stack failed");
+    unsafe { switch_stack(top, main) }
+}
+
+unsafe fn switch_stack(top: VirtAddr, main: fn() -> !) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "call {main}",
+            top = in(reg) top.as_u64(),
+            main = in(reg) main,
+            options(noreturn)
+        )
+    }
+}
@@ -1,9 +1,21 @@
-use crate::WRITER;
-use allocators::fixed_block::HEAP_SIZE;
+mod fs;
+mod memory;
+mod random;
+mod user;
+
+use crate::{
+    WRITER, allocator,
+    process::{self, UserRegisters},
+};
 use core::{arch::global_asm, sync::atomic::Ordering};
 use datetime::TICKS;
 use vga::colors::{Color, ColorCode};
 
+// Errors are returned to the caller as negated errno values
+fn result(value: vfs::Result<u64>) -> u64 {
+    value.unwrap_or_else(|error| -error.errno() as u64)
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn syscall_handler(
     num: u64,
@@ -13,6 +25,7 @@ pub extern "C" fn syscall_handler(
     arg4: u64,
     arg5: u64,
     arg6: u64,
+    registers: &UserRegisters,
 ) -> u64 {
     match num {
         0 => {
@@ -25,32 +38,43 @@ pub extern "C" fn syscall_handler(
             0
         }
         1 => {
-            unsafe {
-                let ptr = arg3 as *const u8;
-
-                let mut len = 0;
-                while *ptr.add(len) != 0 {
-                    len += 1;
-                }
-
-                let slice = core::slice::from_raw_parts(ptr, len);
-                let mut writer = WRITER.lock();
-
-                if let Ok(s) = core::str::from_utf8(slice) {
-                    writer.write_string_at(arg2 as usize, arg1 as usize, s, ColorCode::from(arg4));
-                } else {
-                    writer.write_string_at(
-                        arg2 as usize,
-                        arg1 as usize,
-                        "UTF8 Err",
-                        ColorCode::new(Color::Red, Color::Yellow),
-                    );
-                }
-            }
+            let (text, color) = match user::read_str(arg3) {
+                Ok(text) => (text, ColorCode::from(arg4)),
+                Err(vfs::Error::InvalidArgument) => (
+                    "UTF8 Err".into(),
+                    ColorCode::new(Color::Red, Color::Yellow),
+                ),
+                Err(error) => return result(Err(error)),
+            };
+            WRITER
+                .lock()
+                .write_string_at(arg2 as usize, arg1 as usize, &text, color);
             0
         }
         2 => TICKS.load(Ordering::Relaxed) as u64,
-        0x10 => HEAP_SIZE as u64,
+        0x10 => allocator::heap_size() as u64,
+        0x20 => result(fs::open(arg1, arg2, arg3)),
+        0x21 => result(fs::read(arg1, arg2, arg3)),
+        0x22 => result(fs::write(arg1, arg2, arg3)),
+        0x23 => result(fs::close(arg1)),
+        0x24 => result(fs::lseek(arg1, arg2, arg3)),
+        0x25 => result(fs::stat(arg1, arg2)),
+        0x26 => result(fs::readdir(arg1, arg2, arg3)),
+        0x27 => result(fs::mkdir(arg1, arg2)),
+        0x28 => result(fs::unlink(arg1)),
+        0x29 => result(fs::rename(arg1, arg2)),
+        0x2A => result(fs::ftruncate(arg1, arg2)),
+        0x2B => result(fs::sync()),
+        0x2C => result(random::getrandom(arg1, arg2, arg3)),
+        0x2D => result(memory::meminfo(arg1)),
+        0x30 => result(process::exit(arg1)),
+        0x31 => result(process::fork(registers)),
+        0x32 => result(user::read_str(arg1).and_then(process::execve)),
+        0x40 => result(memory::mmap(arg1, arg2, arg3, arg4, arg5, arg6)),
+        0x41 => result(memory::munmap(arg1, arg2)),
+        0x42 => result(memory::mprotect(arg1, arg2, arg3)),
+        0x43 => memory::brk(arg1),
+        0x44 => result(memory::sbrk(arg1)),
         _ => 0,
     }
 }
@@ -61,7 +85,13 @@ global_asm!(
 .globl syscall_entry
 .text
 syscall_entry:
-    // Save the registers
+    // Save the registers; the callee-saved ones complete the user state for `fork`
+    push %rbx
+    push %rbp
+    push %r12
+    push %r13
+    push %r14
+    push %r15
     push %rax      // [rsp+0]
     push %rdi      // [rsp+8]
     push %rsi      // [rsp+16]
@@ -80,9 +110,19 @@ syscall_entry:
     mov 32(%rsp), %r8    // color (из RCX)
     mov 24(%rsp), %r9    // 6-й аргумент (опц.)
 
+    // The 7th and 8th arguments go on the stack, which stays 16-byte aligned: the user's R9,
+    // then the saved registers
+    mov %rsp, %rax
+    push %rax
+    pushq 16(%rax)
+
     // Calling the handler
     mov $syscall_handler, %rax
     call *%rax
+    add $16, %rsp
+
+    // Hand the return value back to the caller in RAX
+    mov %rax, 64(%rsp)
 
     // Restore registers
     pop %r11
@@ -94,6 +134,12 @@ syscall_entry:
     pop %rsi
     pop %rdi
     pop %rax
+    pop %r15
+    pop %r14
+    pop %r13
+    pop %r12
+    pop %rbp
+    pop %rbx
 
     iretq
 
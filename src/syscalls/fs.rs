@@ -0,0 +1,130 @@
+use super::user::{check, copy_from_user, copy_to_user, read_str, write_user};
+use crate::{
+    fs::{VFS, absolute_path},
+    process::CURRENT_PROCESS,
+};
+use alloc::vec::Vec;
+use custom_types::fallible::try_zeroed;
+use memory::VmaFlags;
+use vfs::{Error, OpenFlags, Result, Stat, Whence};
+
+// Data goes through a kernel buffer between file operations, so no filesystem lock is held
+// while user pages fault in
+const BOUNCE_SIZE: u64 = 4096;
+
+fn bounce_buffer(len: u64) -> Result<Vec<u8>> {
+    try_zeroed(len.min(BOUNCE_SIZE) as usize).map_err(|_| Error::OutOfMemory)
+}
+
+pub fn open(path: u64, flags: u64, mode: u64) -> Result<u64> {
+    let path = absolute_path(&read_str(path)?);
+    let file = VFS.open(&path, OpenFlags(flags as u32), mode as u32)?;
+    Ok(CURRENT_PROCESS.lock().files.insert(file)? as u64)
+}
+
+// Stops at a short read; an error after some data was read returns what was read so far
+pub fn read(fd: u64, buf: u64, len: u64) -> Result<u64> {
+    let file = CURRENT_PROCESS.lock().files.get(fd as usize)?;
+    check(buf, len, VmaFlags::WRITE)?;
+    let mut bounce = bounce_buffer(len)?;
+    let mut done = 0;
+    while done < len {
+        let chunk = &mut bounce[..(len - done).min(BOUNCE_SIZE) as usize];
+        let read = match file.read(chunk) {
+            Ok(read) => read,
+            Err(error) if done == 0 => return Err(error),
+            Err(_) => break,
+        };
+        copy_to_user(buf + done, &chunk[..read])?;
+        done += read as u64;
+        if read < chunk.len() {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+pub fn write(fd: u64, buf: u64, len: u64) -> Result<u64> {
+    let file = CURRENT_PROCESS.lock().files.get(fd as usize)?;
+    check(buf, len, VmaFlags::READ)?;
+    let mut bounce = bounce_buffer(len)?;
+    let mut done = 0;
+    while done < len {
+        let chunk = &mut bounce[..(len - done).min(BOUNCE_SIZE) as usize];
+        copy_from_user(buf + done, chunk)?;
+        let written = match file.write(chunk) {
+            Ok(written) => written,
+            Err(error) if done == 0 => return Err(error),
+            Err(_) => break,
+        };
+        done += written as u64;
+        if written < chunk.len() {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+pub fn close(fd: u64) -> Result<u64> {
+    let file = CURRENT_PROCESS.lock().files.remove(fd as usize)?;
+    file.inode().sync()?;
+    Ok(0)
+}
+
+pub fn lseek(fd: u64, offset: u64, whence: u64) -> Result<u64> {
+    let file = CURRENT_PROCESS.lock().files.get(fd as usize)?;
+    file.seek(offset as i64, Whence::try_from(whence)?)
+}
+
+pub fn stat(path: u64, stat_buf: u64) -> Result<u64> {
+    let path = absolute_path(&read_str(path)?);
+    let stat = Stat::from(VFS.stat(&path)?);
+    write_user(stat_buf, &stat)?;
+    Ok(0)
+}
+
+// Copies the name of the next entry into `buf` and returns its length, 0 at the end of the directory
+pub fn readdir(fd: u64, buf: u64, len: u64) -> Result<u64> {
+    let file = CURRENT_PROCESS.lock().files.get(fd as usize)?;
+    check(buf, len, VmaFlags::WRITE)?;
+    let Some(entry) = file.next_entry()? else {
+        return Ok(0);
+    };
+
+    let name = entry.name.as_bytes();
+    if name.len() as u64 >= len {
+        return Err(Error::NameTooLong);
+    }
+    copy_to_user(buf, name)?;
+    copy_to_user(buf + name.len() as u64, &[0])?;
+    Ok(name.len() as u64)
+}
+
+pub fn mkdir(path: u64, mode: u64) -> Result<u64> {
+    let path = absolute_path(&read_str(path)?);
+    VFS.mkdir(&path, mode as u32)?;
+    Ok(0)
+}
+
+pub fn unlink(path: u64) -> Result<u64> {
+    let path = absolute_path(&read_str(path)?);
+    VFS.unlink(&path)?;
+    Ok(0)
+}
+
+pub fn rename(old_path: u64, new_path: u64) -> Result<u64> {
+    let old_path = absolute_path(&read_str(old_path)?);
+    let new_path = absolute_path(&read_str(new_path)?);
+    VFS.rename(&old_path, &new_path)?;
+    Ok(0)
+}
+
+pub fn ftruncate(fd: u64, size: u64) -> Result<u64> {
+    let file = CURRENT_PROCESS.lock().files.get(fd as usize)?;
+    file.truncate(size)?;
+    Ok(0)
+}
+
+pub fn sync() -> Result<u64> {
+    crate::fs::sync().map(|_| 0)
+}
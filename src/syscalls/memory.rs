@@ -0,0 +1,186 @@
+use super::user::write_user;
+use crate::{allocator, process::CURRENT_PROCESS};
+use alloc::sync::Arc;
+use memory::{Backing, USER_START, VmaFlags, VmaKind};
+use vfs::{Error, Inode, Result};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+const PROT_READ: u64 = 1;
+const PROT_WRITE: u64 = 2;
+const PROT_EXEC: u64 = 4;
+
+const MAP_SHARED: u64 = 0x01;
+const MAP_PRIVATE: u64 = 0x02;
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+// Mappings without an address go above this, well clear of programs and their heaps
+const MMAP_BASE: u64 = 0x0000_2000_0000_0000;
+
+// Sizes are in bytes; physical memory is reported as zero before the frame allocator is installed
+#[repr(C)]
+pub struct MemInfo {
+    pub total: u64,
+    pub free: u64,
+    pub heap_size: u64,
+    pub heap_max_size: u64,
+    pub heap_in_use: u64,
+    pub heap_peak: u64,
+    pub allocations: u64,
+    pub frees: u64,
+    pub failed_allocations: u64,
+}
+
+pub fn meminfo(info_buf: u64) -> Result<u64> {
+    if info_buf == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let heap = allocator::stats();
+    let frames = memory::frame_stats().unwrap_or_default();
+    let info = MemInfo {
+        total: frames.total as u64 * PAGE_SIZE,
+        free: frames.free as u64 * PAGE_SIZE,
+        heap_size: heap.heap_size as u64,
+        heap_max_size: heap.heap_max_size as u64,
+        heap_in_use: heap.in_use as u64,
+        heap_peak: heap.peak as u64,
+        allocations: heap.allocations as u64,
+        frees: heap.frees as u64,
+        failed_allocations: heap.failed_allocations as u64,
+    };
+    write_user(info_buf, &info)?;
+    Ok(0)
+}
+
+// Reads the pages of a private file mapping
+struct InodeBacking(Arc<dyn Inode>);
+
+impl Backing for InodeBacking {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> core::result::Result<(), &'static str> {
+        let mut done = 0;
+        while done < buf.len() {
+            match self.0.read_at(offset + done as u64, &mut buf[done..]) {
+                Ok(0) => break,
+                Ok(read) => done += read,
+                Err(error) => return Err(error.as_str()),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn vma_flags(prot: u64) -> Result<VmaFlags> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(VmaFlags(prot as u8))
+}
+
+// Lengths are rounded up to whole pages
+fn page_size(len: u64) -> Result<u64> {
+    match len.checked_next_multiple_of(PAGE_SIZE) {
+        Some(size) if size > 0 => Ok(size),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+fn page_range(address: u64, len: u64) -> Result<(VirtAddr, u64)> {
+    let address = VirtAddr::try_new(address).map_err(|_| Error::InvalidArgument)?;
+    if !address.is_aligned(PAGE_SIZE) {
+        return Err(Error::InvalidArgument);
+    }
+    Ok((address, page_size(len)?))
+}
+
+// Only private mappings are supported, so writes never reach the file
+pub fn mmap(address: u64, len: u64, prot: u64, flags: u64, fd: u64, offset: u64) -> Result<u64> {
+    let vma_flags = vma_flags(prot)?;
+    match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_PRIVATE => {}
+        MAP_SHARED => return Err(Error::Unsupported),
+        _ => return Err(Error::InvalidArgument),
+    }
+    let size = page_size(len)?;
+
+    let mut process = CURRENT_PROCESS.lock();
+    let kind = if flags & MAP_ANONYMOUS != 0 {
+        VmaKind::Anonymous
+    } else {
+        if !offset.is_multiple_of(PAGE_SIZE) || offset.checked_add(size).is_none() {
+            return Err(Error::InvalidArgument);
+        }
+        let file = process.files.get(fd as usize)?;
+        if !file.flags().readable() {
+            return Err(Error::PermissionDenied);
+        }
+        let backing = Arc::new(InodeBacking(file.inode().clone()));
+        VmaKind::File { backing, offset }
+    };
+
+    let address_space = &mut process.address_space;
+    let start = if flags & MAP_FIXED != 0 {
+        let (start, size) = page_range(address, size)?;
+        address_space
+            .unmap(start, size)
+            .map_err(|_| Error::InvalidArgument)?;
+        start
+    } else {
+        // An address without MAP_FIXED is only a hint
+        let from = VirtAddr::try_new(address)
+            .ok()
+            .filter(|from| from.as_u64() >= USER_START)
+            .unwrap_or(VirtAddr::new(MMAP_BASE));
+        address_space
+            .find_free(from, size)
+            .or_else(|| address_space.find_free(VirtAddr::new(MMAP_BASE), size))
+            .ok_or(Error::OutOfMemory)?
+    };
+    address_space
+        .map(start, size, vma_flags, kind)
+        .map_err(|_| Error::InvalidArgument)?;
+    Ok(start.as_u64())
+}
+
+pub fn munmap(address: u64, len: u64) -> Result<u64> {
+    let (start, size) = page_range(address, len)?;
+    let address_space = &mut CURRENT_PROCESS.lock().address_space;
+    address_space
+        .unmap(start, size)
+        .map_err(|_| Error::InvalidArgument)?;
+    Ok(0)
+}
+
+pub fn mprotect(address: u64, len: u64, prot: u64) -> Result<u64> {
+    let flags = vma_flags(prot)?;
+    let (start, size) = page_range(address, len)?;
+    let address_space = &mut CURRENT_PROCESS.lock().address_space;
+    // Only a range with holes can fail once it is aligned
+    address_space
+        .protect(start, size, flags)
+        .map_err(|_| Error::OutOfMemory)?;
+    Ok(0)
+}
+
+// Like Linux, returns the new break, or the old one if it cannot move; 0 only queries it
+pub fn brk(address: u64) -> u64 {
+    let address_space = &mut CURRENT_PROCESS.lock().address_space;
+    if let Ok(address) = VirtAddr::try_new(address) {
+        let _ = address_space.set_brk(address);
+    }
+    address_space.brk().as_u64()
+}
+
+// Moves the break by a signed increment and returns the old one
+pub fn sbrk(increment: u64) -> Result<u64> {
+    let address_space = &mut CURRENT_PROCESS.lock().address_space;
+    let old = address_space.brk();
+    let new = old
+        .as_u64()
+        .checked_add_signed(increment as i64)
+        .and_then(|new| VirtAddr::try_new(new).ok())
+        .ok_or(Error::OutOfMemory)?;
+    address_space.set_brk(new).map_err(|_| Error::OutOfMemory)?;
+    Ok(old.as_u64())
+}
@@ -0,0 +1,58 @@
+use crate::process::CURRENT_PROCESS;
+use alloc::{string::String, vec::Vec};
+use memory::VmaFlags;
+use vfs::{Error, Result};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+const PATH_MAX: usize = 4096;
+
+// System calls only touch user memory that the process has mapped with the access they need.
+// Touching it may still fault pages in, so no lock may be held while copying
+pub(super) fn check(ptr: u64, len: u64, flags: VmaFlags) -> Result<()> {
+    let start = VirtAddr::try_new(ptr).map_err(|_| Error::BadAddress)?;
+    let process = CURRENT_PROCESS.lock();
+    match process.address_space.covers(start, len, flags) {
+        true => Ok(()),
+        false => Err(Error::BadAddress),
+    }
+}
+
+pub(super) fn copy_from_user(ptr: u64, buf: &mut [u8]) -> Result<()> {
+    check(ptr, buf.len() as u64, VmaFlags::READ)?;
+    unsafe { core::ptr::copy_nonoverlapping(ptr as *const u8, buf.as_mut_ptr(), buf.len()) };
+    Ok(())
+}
+
+pub(super) fn copy_to_user(ptr: u64, data: &[u8]) -> Result<()> {
+    check(ptr, data.len() as u64, VmaFlags::WRITE)?;
+    unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len()) };
+    Ok(())
+}
+
+pub(super) fn write_user<T>(ptr: u64, value: &T) -> Result<()> {
+    let bytes =
+        unsafe { core::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>()) };
+    copy_to_user(ptr, bytes)
+}
+
+// Copies a NUL-terminated string, checking each page before reading from it
+pub(super) fn read_str(ptr: u64) -> Result<String> {
+    let mut bytes = Vec::new();
+    let mut address = ptr;
+    loop {
+        let len = PAGE_SIZE - address % PAGE_SIZE;
+        check(address, len, VmaFlags::READ)?;
+        let page = unsafe { core::slice::from_raw_parts(address as *const u8, len as usize) };
+        if let Some(end) = page.iter().position(|&byte| byte == 0) {
+            bytes.extend_from_slice(&page[..end]);
+            break;
+        }
+        bytes.extend_from_slice(page);
+        if bytes.len() >= PATH_MAX {
+            return Err(Error::NameTooLong);
+        }
+        address += len;
+    }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidArgument)
+}
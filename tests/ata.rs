@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_system::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use block::{BlockDevice, SECTOR_SIZE};
+use bootloader::{BootInfo, entry_point};
+
+// More than one LBA28 command can carry, well below the LBA28 limit
+const SECTORS: usize = 300;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_system::test_init(boot_info);
+
+    test_main();
+    rust_system::hlt_loop();
+}
+
+#[test_case]
+fn large_read_matches_small_reads() {
+    // QEMU attaches the boot image as the first drive
+    let drives = ata::probe();
+    let drive = drives.first().expect("no ATA drive");
+    assert!(drive.block_count() >= SECTORS as u64);
+
+    let mut whole = vec![0u8; SECTORS * SECTOR_SIZE];
+    drive.read_blocks(0, &mut whole).expect("reading failed");
+    let mut pieces = vec![0u8; SECTORS * SECTOR_SIZE];
+    for (index, piece) in pieces.chunks_mut(100 * SECTOR_SIZE).enumerate() {
+        drive
+            .read_blocks(index as u64 * 100, piece)
+            .expect("reading failed");
+    }
+    assert!(whole == pieces);
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rust_system::test_panic_handler(info)
+}
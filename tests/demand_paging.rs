@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_system::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use memory::{USER_START, VmaFlags};
+use rust_system::{process::ExitStatus, test_map, test_run, test_unmap};
+use x86_64::VirtAddr;
+
+const DATA: u64 = USER_START;
+const UNMAPPED: u64 = USER_START + 0x30_0000;
+// The kernel heap
+const KERNEL: u64 = 0x4444_4444_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_system::test_init(boot_info);
+
+    test_main();
+    rust_system::hlt_loop();
+}
+
+#[test_case]
+fn anonymous_memory_is_zeroed_on_first_touch() {
+    test_map(DATA, 16, VmaFlags::READ | VmaFlags::WRITE);
+    for page in 0..16 {
+        let ptr = (DATA + page * 4096 + 8) as *mut u64;
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(page);
+        }
+    }
+    for page in 0..16 {
+        let ptr = (DATA + page * 4096 + 8) as *const u64;
+        assert_eq!(unsafe { ptr.read_volatile() }, page);
+    }
+    test_unmap(DATA, 16);
+}
+
+#[test_case]
+fn user_program_exits() {
+    let code = [
+        0x6a, 0x2a, // push 42, faulting in the stack
+        0x5f, // pop rdi
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+        0xeb, 0xfe, // jmp .
+    ];
+    assert_eq!(test_run(&code), ExitStatus::Exited(42));
+}
+
+#[test_case]
+fn segfault_kills_the_program() {
+    let mut code = [0; 15];
+    code[..2].copy_from_slice(&[0x48, 0xb8]); // movabs rax, UNMAPPED
+    code[2..10].copy_from_slice(&UNMAPPED.to_le_bytes());
+    code[10..].copy_from_slice(&[0xc6, 0x00, 0x01, 0xeb, 0xfe]); // mov byte [rax], 1; jmp .
+    let status = test_run(&code);
+    assert_eq!(status, ExitStatus::Segfault(VirtAddr::new(UNMAPPED)));
+}
+
+#[test_case]
+fn kernel_pointer_fails_the_call() {
+    let mut code = [0; 30];
+    code[..2].copy_from_slice(&[0x48, 0xbf]); // movabs rdi, KERNEL
+    code[2..10].copy_from_slice(&KERNEL.to_le_bytes());
+    code[10..].copy_from_slice(&[
+        0xb8, 0x25, 0x00, 0x00, 0x00, // mov eax, 0x25 (stat)
+        0xcd, 0x80, // int 0x80
+        0x48, 0x89, 0xc7, // mov rdi, rax
+        0x48, 0xf7, 0xdf, // neg rdi
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+    ]);
+    // The call fails with EFAULT instead of reading kernel memory
+    assert_eq!(test_run(&code), ExitStatus::Exited(14));
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rust_system::test_panic_handler(info)
+}
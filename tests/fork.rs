@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_system::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use memory::{USER_START, VmaFlags};
+use rust_system::{
+    fs::VFS,
+    process::{self, CURRENT_PROCESS, ExitStatus},
+    test_map, test_run, test_unmap,
+};
+use vfs::OpenFlags;
+use x86_64::VirtAddr;
+
+const DATA: u64 = USER_START;
+const PROGRAM: u64 = USER_START + 0x40_0000;
+const PROGRAM_PATH: &str = "/tmp/exit7";
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_system::test_init(boot_info);
+    write_program();
+
+    test_main();
+    rust_system::hlt_loop();
+}
+
+// Maps a data page next to the code and stack, then runs the code in user mode
+fn run(code: &[u8], data: &[u8]) -> ExitStatus {
+    test_map(DATA, 1, VmaFlags::READ | VmaFlags::WRITE);
+    (CURRENT_PROCESS.lock().address_space)
+        .write(VirtAddr::new(DATA), data)
+        .expect("loading failed");
+    let status = test_run(code);
+    test_unmap(DATA, 1);
+    status
+}
+
+// A static ELF executable with a single segment that exits with status 7
+fn write_program() {
+    const CODE_OFFSET: usize = 120;
+    let code = [
+        0xbf, 0x07, 0x00, 0x00, 0x00, // mov edi, 7
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+        0xeb, 0xfe, // jmp .
+    ];
+    let mut elf = [0u8; CODE_OFFSET + 14];
+    let size = elf.len() as u64;
+    elf[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+    elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // executable
+    elf[18..20].copy_from_slice(&0x3Eu16.to_le_bytes()); // x86_64
+    elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+    elf[24..32].copy_from_slice(&(PROGRAM + CODE_OFFSET as u64).to_le_bytes());
+    elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+    elf[52..54].copy_from_slice(&64u16.to_le_bytes());
+    elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+    elf[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+    let header = &mut elf[64..CODE_OFFSET];
+    header[0..4].copy_from_slice(&1u32.to_le_bytes()); // loadable
+    header[4..8].copy_from_slice(&5u32.to_le_bytes()); // readable and executable
+    header[16..24].copy_from_slice(&PROGRAM.to_le_bytes());
+    header[24..32].copy_from_slice(&PROGRAM.to_le_bytes());
+    header[32..40].copy_from_slice(&size.to_le_bytes());
+    header[40..48].copy_from_slice(&size.to_le_bytes());
+    header[48..56].copy_from_slice(&4096u64.to_le_bytes());
+    elf[CODE_OFFSET..].copy_from_slice(&code);
+
+    let flags = OpenFlags::WRITE_ONLY | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+    let file = VFS
+        .open(PROGRAM_PATH, flags, 0o755)
+        .expect("creating the program failed");
+    file.write(&elf).expect("writing the program failed");
+}
+
+#[test_case]
+fn fork_copies_memory_on_write() {
+    let mut code = [0; 46];
+    code[..2].copy_from_slice(&[0x48, 0xbb]); // movabs rbx, DATA
+    code[2..10].copy_from_slice(&DATA.to_le_bytes());
+    code[10..].copy_from_slice(&[
+        0x48, 0xc7, 0x03, 0x05, 0x00, 0x00, 0x00, // mov qword [rbx], 5
+        0xb8, 0x31, 0x00, 0x00, 0x00, // mov eax, 0x31 (fork)
+        0xcd, 0x80, // int 0x80
+        0x48, 0x85, 0xc0, // test rax, rax
+        0x75, 0x07, // jnz parent
+        0x48, 0xc7, 0x03, 0x09, 0x00, 0x00, 0x00, // child: mov qword [rbx], 9
+        0x48, 0x8b, 0x3b, // parent: mov rdi, [rbx]
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+    ]);
+    // The child exits with 9 from its own copy, the parent still sees 5
+    assert_eq!(run(&code, &[]), ExitStatus::Exited(5));
+}
+
+#[test_case]
+fn spawn_runs_an_elf_program() {
+    assert_eq!(process::spawn(PROGRAM_PATH), Ok(ExitStatus::Exited(7)));
+}
+
+#[test_case]
+fn execve_replaces_the_program() {
+    let mut code = [0; 27];
+    code[..2].copy_from_slice(&[0x48, 0xbf]); // movabs rdi, DATA
+    code[2..10].copy_from_slice(&DATA.to_le_bytes());
+    code[10..].copy_from_slice(&[
+        0xb8, 0x32, 0x00, 0x00, 0x00, // mov eax, 0x32 (execve)
+        0xcd, 0x80, // int 0x80
+        0x48, 0x89, 0xc7, // mov rdi, rax
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+    ]);
+    let path = b"/tmp/exit7\0";
+    assert_eq!(run(&code, path), ExitStatus::Exited(7));
+    // The new program replaced the test's mappings
+    CURRENT_PROCESS.lock().address_space.clear();
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rust_system::test_panic_handler(info)
+}
@@ -7,24 +7,35 @@
 extern crate alloc;
 
 use alloc::{boxed::Box, vec::Vec};
+use allocators::{
+    HeapAllocator,
+    buddy::BuddyAllocator,
+    fixed_block::{FixedSizeBlockAllocator, HEAP_MAX_SIZE, HEAP_SIZE, HEAP_START},
+    slab::SlabCache,
+    tlsf::TlsfAllocator,
+};
 use bootloader::{BootInfo, entry_point};
-use rust_system::allocator::HEAP_SIZE;
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use custom_types::fallible::{OutOfMemory, try_zeroed};
+use serial::serial_println;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageTableFlags},
+};
+
+// The benchmark heaps live right above the range the kernel heap may grow into
+const BENCH_START: usize = HEAP_START + HEAP_MAX_SIZE;
+const BENCH_SIZE: usize = 4 * 1024 * 1024;
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use rust_system::allocator;
-    use rust_system::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
-
-    rust_system::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = BootInfoFrameAllocator::init(&boot_info.memory_map);
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
-
+    rust_system::test_init(boot_info);
     test_main();
-    loop {}
+    rust_system::hlt_loop();
 }
 
 #[test_case]
@@ -53,6 +64,208 @@ fn many_boxes() {
     }
 }
 
+#[test_case]
+fn failed_allocation() {
+    assert_eq!(try_zeroed(HEAP_MAX_SIZE + 1), Err(OutOfMemory));
+    assert!(rust_system::allocator::stats().failed_allocations > 0);
+    let buffer = try_zeroed(4096).expect("heap unusable after a failed allocation");
+    assert!(buffer.iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn class_stats() {
+    use rust_system::allocator;
+
+    // Boxed u64s all fall into the smallest size class
+    let before = allocator::stats().classes[0];
+    let boxes: Vec<_> = (0..100u64).map(Box::new).collect();
+    let during = allocator::stats().classes[0];
+    assert_eq!(during.allocations - before.allocations, 100);
+    drop(boxes);
+    let after = allocator::stats().classes[0];
+    assert_eq!(after.frees - before.frees, 100);
+    // The last block freed is at least in the per-CPU cache
+    assert!(after.free_blocks > 0);
+}
+
+#[test_case]
+fn slab_across_slabs() {
+    let cache = SlabCache::<u64>::new("test");
+    let per_slab = cache.stats().objects_per_slab;
+    let objects: Vec<_> = (0..per_slab as u64 + 1)
+        .map(|i| cache.allocate_with(i).expect("slab allocation failed"))
+        .collect();
+    let stats = cache.stats();
+    assert_eq!((stats.slabs, stats.in_use), (2, per_slab + 1));
+    assert!(
+        objects
+            .iter()
+            .enumerate()
+            .all(|(i, object)| **object == i as u64)
+    );
+
+    // One empty slab stays cached, the other goes back to the heap
+    drop(objects);
+    let stats = cache.stats();
+    assert_eq!((stats.slabs, stats.empty_slabs, stats.in_use), (1, 1, 0));
+
+    assert_eq!(cache.reclaim(), stats.slab_size);
+    assert_eq!(cache.stats().slabs, 0);
+    assert_eq!(cache.reclaim(), 0);
+}
+
+static CONSTRUCTED: AtomicUsize = AtomicUsize::new(0);
+static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+
+#[test_case]
+fn slab_hooks() {
+    fn construct() -> u64 {
+        CONSTRUCTED.fetch_add(1, Ordering::Relaxed);
+        7
+    }
+    fn destroy(_: u64) {
+        DESTROYED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let cache = SlabCache::with_hooks("hooked", construct, destroy);
+    let mut object = cache.allocate().expect("slab allocation failed");
+    let per_slab = cache.stats().objects_per_slab;
+    assert_eq!(*object, 7);
+    assert_eq!(CONSTRUCTED.load(Ordering::Relaxed), per_slab);
+
+    // Freed objects come back as they were left, without running the constructor again
+    *object = 42;
+    drop(object);
+    let object = cache.allocate().expect("slab allocation failed");
+    assert_eq!(*object, 42);
+    assert_eq!(CONSTRUCTED.load(Ordering::Relaxed), per_slab);
+    assert_eq!(DESTROYED.load(Ordering::Relaxed), 0);
+
+    drop(object);
+    cache.reclaim();
+    assert_eq!(DESTROYED.load(Ordering::Relaxed), per_slab);
+}
+
+#[test_case]
+fn slab_large_objects() {
+    struct Big([u8; 5000]);
+
+    let cache = SlabCache::<Big>::new("big");
+    let objects: Vec<_> = (0..10u8)
+        .map(|i| {
+            cache
+                .allocate_with(Big([i; 5000]))
+                .expect("slab allocation failed")
+        })
+        .collect();
+    let stats = cache.stats();
+    assert!(stats.slab_size > 4096);
+    assert!(stats.objects_per_slab >= 8);
+    assert!(stats.objects_per_slab * stats.object_size <= stats.slab_size);
+    assert_eq!(stats.slabs, 10usize.div_ceil(stats.objects_per_slab));
+    for (i, object) in objects.iter().enumerate() {
+        assert!(object.0.iter().all(|&byte| byte == i as u8));
+    }
+    drop(objects);
+    cache.reclaim();
+    assert_eq!(cache.stats().slabs, 0);
+}
+
+#[test_case]
+fn heap_strategies() {
+    let start = Page::containing_address(VirtAddr::new(BENCH_START as u64));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    memory::map_pages(start, (BENCH_SIZE / 4096) as u64, flags).expect("mapping failed");
+
+    bench(FixedSizeBlockAllocator::new());
+    bench(BuddyAllocator::new());
+    bench(TlsfAllocator::new());
+}
+
+// Runs the same workloads on a fresh heap and reports cycles per operation and fragmentation
+fn bench(mut heap: impl HeapAllocator) {
+    unsafe { heap.init(BENCH_START, BENCH_SIZE) };
+    let strategy = heap.stats().strategy;
+    let report = |workload: &str, operations: u64, start: u64| {
+        let cycles = (datetime::rdtsc() - start) / operations;
+        serial_println!("    {} {}: {} cycles/op", strategy, workload, cycles);
+    };
+
+    // Small boxes freed right away, like `many_boxes`
+    let start = datetime::rdtsc();
+    let layout = Layout::new::<u64>();
+    for i in 0..HEAP_SIZE as u64 {
+        let ptr = heap.allocate(layout) as *mut u64;
+        assert!(!ptr.is_null());
+        unsafe {
+            ptr.write(i);
+            assert_eq!(ptr.read(), i);
+            heap.deallocate(ptr as *mut u8, layout);
+        }
+    }
+    report("small boxes", HEAP_SIZE as u64, start);
+
+    // A vector doubling its buffer, like `large_vec`
+    let start = datetime::rdtsc();
+    let mut buffer = (core::ptr::null_mut::<u8>(), Layout::new::<u8>());
+    let mut operations = 0;
+    for _ in 0..64 {
+        let mut size = 16;
+        while size <= 256 * 1024 {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let ptr = heap.allocate(layout);
+            assert!(!ptr.is_null());
+            if !buffer.0.is_null() {
+                unsafe {
+                    core::ptr::copy_nonoverlapping(buffer.0, ptr, buffer.1.size());
+                    heap.deallocate(buffer.0, buffer.1);
+                }
+            }
+            buffer = (ptr, layout);
+            size *= 2;
+            operations += 1;
+        }
+        unsafe { heap.deallocate(buffer.0, buffer.1) };
+        buffer.0 = core::ptr::null_mut();
+    }
+    report("growing vector", operations, start);
+
+    // Random sizes replaced in random order, which leaves holes behind
+    let start = datetime::rdtsc();
+    let mut live = [(core::ptr::null_mut::<u8>(), Layout::new::<u8>()); 256];
+    let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+    for _ in 0..20_000 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let slot = &mut live[seed as usize % 256];
+        if !slot.0.is_null() {
+            unsafe { heap.deallocate(slot.0, slot.1) };
+        }
+        let layout = Layout::from_size_align((seed >> 32) as usize % 4096 + 8, 8).unwrap();
+        let ptr = heap.allocate(layout);
+        assert!(!ptr.is_null());
+        *slot = (ptr, layout);
+    }
+    report("random sizes", 20_000, start);
+
+    let stats = heap.stats();
+    let free = stats.heap_size - stats.heap_used;
+    match stats.largest_free {
+        Some(largest) if free > 0 => serial_println!(
+            "    {} fragmentation: {}% ({} of {} bytes free in the largest block)",
+            strategy,
+            100 - largest * 100 / free,
+            largest,
+            free
+        ),
+        _ => serial_println!("    {} fragmentation: unknown", strategy),
+    }
+    for (ptr, layout) in live {
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+}
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     rust_system::test_panic_handler(info)
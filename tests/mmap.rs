@@ -0,0 +1,226 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_system::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{BootInfo, entry_point};
+use memory::USER_START;
+use rust_system::{
+    fs::VFS,
+    process::{CURRENT_PROCESS, ExitStatus},
+    test_run, test_unmap,
+};
+use vfs::{OpenFlags, Whence};
+use x86_64::VirtAddr;
+
+const HEAP: u64 = USER_START + 0x30_0000;
+// Where mappings without an address go while nothing else is mapped there
+const MMAP_BASE: u64 = 0x0000_2000_0000_0000;
+const FILE_PATH: &str = "/tmp/mapped";
+
+const PROT_READ: u32 = 1;
+const PROT_WRITE: u32 = 2;
+const MAP_PRIVATE: u32 = 0x02;
+const MAP_ANONYMOUS: u32 = 0x20;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_system::test_init(boot_info);
+
+    test_main();
+    rust_system::hlt_loop();
+}
+
+// Creates the file with the contents and opens it for the current process, at the start
+fn open_file(contents: &[u8]) -> usize {
+    let flags = OpenFlags::READ_WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+    let file = VFS
+        .open(FILE_PATH, flags, 0o644)
+        .expect("creating the file failed");
+    file.write(contents).expect("writing the file failed");
+    file.seek(0, Whence::Set).expect("seeking failed");
+    CURRENT_PROCESS
+        .lock()
+        .files
+        .insert(file)
+        .expect("no free fd")
+}
+
+fn close(fd: usize) {
+    CURRENT_PROCESS
+        .lock()
+        .files
+        .remove(fd)
+        .expect("closing failed");
+}
+
+// Calls mmap without an address, leaving the mapping in rax
+fn mmap_call(len: u32, prot: u32, flags: u32, fd: u32, offset: u32) -> Vec<u8> {
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x31, 0xff]); // xor edi, edi
+    code.push(0xbe); // mov esi, len
+    code.extend_from_slice(&len.to_le_bytes());
+    code.push(0xba); // mov edx, prot
+    code.extend_from_slice(&prot.to_le_bytes());
+    code.push(0xb9); // mov ecx, flags
+    code.extend_from_slice(&flags.to_le_bytes());
+    code.extend_from_slice(&[0x41, 0xb8]); // mov r8d, fd
+    code.extend_from_slice(&fd.to_le_bytes());
+    code.extend_from_slice(&[0x41, 0xb9]); // mov r9d, offset
+    code.extend_from_slice(&offset.to_le_bytes());
+    code.extend_from_slice(&[0xb8, 0x40, 0x00, 0x00, 0x00]); // mov eax, 0x40 (mmap)
+    code.extend_from_slice(&[0xcd, 0x80]); // int 0x80
+    code
+}
+
+#[test_case]
+fn mprotect_makes_memory_read_only() {
+    let mut code = mmap_call(
+        4096,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        0,
+        0,
+    );
+    code.extend_from_slice(&[
+        0x48, 0x89, 0xc3, // mov rbx, rax
+        0x48, 0xc7, 0x03, 0x05, 0x00, 0x00, 0x00, // mov qword [rbx], 5
+        0x48, 0x89, 0xdf, // mov rdi, rbx
+        0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 4096
+        0xba, 0x01, 0x00, 0x00, 0x00, // mov edx, PROT_READ
+        0xb8, 0x42, 0x00, 0x00, 0x00, // mov eax, 0x42 (mprotect)
+        0xcd, 0x80, // int 0x80
+        0x48, 0x8b, 0x3b, // mov rdi, [rbx]
+        0x48, 0xc7, 0x03, 0x06, 0x00, 0x00, 0x00, // mov qword [rbx], 6
+        0xeb, 0xfe, // jmp .
+    ]);
+    let status = test_run(&code);
+    test_unmap(MMAP_BASE, 1);
+    assert_eq!(status, ExitStatus::Segfault(VirtAddr::new(MMAP_BASE)));
+}
+
+#[test_case]
+fn data_is_not_executable() {
+    let mut code = mmap_call(
+        4096,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        0,
+        0,
+    );
+    code.extend_from_slice(&[
+        0xc6, 0x00, 0xc3, // mov byte [rax], 0xc3 (ret), mapping the page
+        0xff, 0xe0, // jmp rax
+    ]);
+    let status = test_run(&code);
+    test_unmap(MMAP_BASE, 1);
+    assert_eq!(status, ExitStatus::Segfault(VirtAddr::new(MMAP_BASE)));
+}
+
+#[test_case]
+fn file_mapping_reads_the_file() {
+    let mut contents = [0u8; 4096 + 8];
+    contents[4096..].copy_from_slice(&42u64.to_le_bytes());
+    let fd = open_file(&contents);
+
+    // Maps the second page of the file, which ends after 8 bytes
+    let mut code = mmap_call(4096, PROT_READ, MAP_PRIVATE, fd as u32, 4096);
+    code.extend_from_slice(&[
+        0x48, 0x8b, 0x78, 0x08, // mov rdi, [rax + 8]
+        0x48, 0x03, 0x38, // add rdi, [rax]
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+    ]);
+    let status = test_run(&code);
+    test_unmap(MMAP_BASE, 1);
+    close(fd);
+    assert_eq!(status, ExitStatus::Exited(42));
+}
+
+#[test_case]
+fn file_offset_overflow_fails() {
+    let fd = open_file(&[0; 4096]);
+
+    // The last page an offset can name, so the end of the mapping wraps around
+    let mut code = mmap_call(8192, PROT_READ, MAP_PRIVATE, fd as u32, 0);
+    let call = code.split_off(code.len() - 7);
+    code.extend_from_slice(&[0x49, 0xb9]); // movabs r9, offset
+    code.extend_from_slice(&(u64::MAX - 4095).to_le_bytes());
+    code.extend_from_slice(&call);
+    code.extend_from_slice(&[
+        0x48, 0x89, 0xc7, // mov rdi, rax
+        0x48, 0xf7, 0xdf, // neg rdi
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+    ]);
+    let status = test_run(&code);
+    close(fd);
+    // EINVAL
+    assert_eq!(status, ExitStatus::Exited(22));
+}
+
+#[test_case]
+fn read_into_a_mapping_of_the_same_file() {
+    let mut contents = [0u8; 4096];
+    contents[..8].copy_from_slice(&42u64.to_le_bytes());
+    let fd = open_file(&contents);
+
+    // The read faults the mapping in, which reads the file again
+    let mut code = mmap_call(4096, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd as u32, 0);
+    code.extend_from_slice(&[
+        0x48, 0x89, 0xc3, // mov rbx, rax
+        0xbf, // mov edi, fd
+    ]);
+    code.extend_from_slice(&(fd as u32).to_le_bytes());
+    code.extend_from_slice(&[
+        0x48, 0x89, 0xde, // mov rsi, rbx
+        0xba, 0x00, 0x10, 0x00, 0x00, // mov edx, 4096
+        0xb8, 0x21, 0x00, 0x00, 0x00, // mov eax, 0x21 (read)
+        0xcd, 0x80, // int 0x80
+        0x48, 0x8b, 0x3b, // mov rdi, [rbx]
+        0x48, 0x01, 0xc7, // add rdi, rax
+        0xb8, 0x30, 0x00, 0x00, 0x00, // mov eax, 0x30 (exit)
+        0xcd, 0x80, // int 0x80
+    ]);
+    let status = test_run(&code);
+    test_unmap(MMAP_BASE, 1);
+    close(fd);
+    assert_eq!(status, ExitStatus::Exited(42 + 4096));
+}
+
+#[test_case]
+fn brk_grows_and_shrinks_the_heap() {
+    let mut process = CURRENT_PROCESS.lock();
+    let address_space = &mut process.address_space;
+    address_space.set_heap_start(VirtAddr::new(HEAP - 100));
+    assert_eq!(address_space.brk(), VirtAddr::new(HEAP));
+    assert!(address_space.set_brk(VirtAddr::new(HEAP - 1)).is_err());
+    address_space
+        .set_brk(VirtAddr::new(HEAP + 3 * 4096 + 1))
+        .expect("growing the heap failed");
+    assert!(address_space.find(VirtAddr::new(HEAP + 3 * 4096)).is_some());
+    drop(process);
+
+    let ptr = (HEAP + 3 * 4096) as *mut u8;
+    unsafe { ptr.write_volatile(1) };
+
+    let mut process = CURRENT_PROCESS.lock();
+    let address_space = &mut process.address_space;
+    address_space
+        .set_brk(VirtAddr::new(HEAP + 4096))
+        .expect("shrinking the heap failed");
+    assert!(address_space.find(VirtAddr::new(HEAP + 4096)).is_none());
+    assert!(address_space.find(VirtAddr::new(HEAP)).is_some());
+    address_space.clear();
+    assert_eq!(address_space.brk(), VirtAddr::zero());
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rust_system::test_panic_handler(info)
+}
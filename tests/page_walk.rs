@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_system::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use allocators::fixed_block::HEAP_START;
+use bootloader::{BootInfo, entry_point};
+use memory::{USER_START, VmaFlags};
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+
+const DATA: u64 = USER_START;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_system::test_init(boot_info);
+
+    test_main();
+    rust_system::hlt_loop();
+}
+
+#[test_case]
+fn translate_finds_the_heap() {
+    let heap = VirtAddr::new(HEAP_START as u64 + 8);
+    let (phys, flags) = memory::translate(heap).expect("the heap is not mapped");
+    assert_eq!(phys.as_u64() % 4096, 8);
+    assert!(flags.contains(PageTableFlags::WRITABLE));
+    // The physical memory mapping reaches the same byte
+    let alias = memory::phys_to_virt(phys);
+    assert_eq!(memory::translate(alias).map(|(phys, _)| phys), Some(phys));
+}
+
+#[test_case]
+fn translate_misses_unmapped_memory() {
+    assert_eq!(memory::translate(VirtAddr::new(DATA)), None);
+}
+
+#[test_case]
+fn regions_cover_touched_user_pages() {
+    rust_system::test_map(DATA, 4, VmaFlags::READ | VmaFlags::WRITE);
+    for page in 0..3 {
+        unsafe { ((DATA + page * 4096) as *mut u8).write_volatile(1) };
+    }
+
+    let regions = memory::regions();
+    let region = regions
+        .iter()
+        .find(|region| region.start == VirtAddr::new(DATA))
+        .expect("the user pages are missing");
+    assert_eq!(region.size, 3 * 4096);
+    assert_eq!(region.page_size, 4096);
+    assert!(region.flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert!(region.flags.contains(PageTableFlags::NO_EXECUTE));
+    assert!(regions.windows(2).all(|pair| pair[0].start < pair[1].start));
+
+    rust_system::test_unmap(DATA, 4);
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rust_system::test_panic_handler(info)
+}
@@ -1,19 +1,24 @@
 #![no_std]
 #![no_main]
-#![feature(abi_x86_interrupt)]
 
-use lazy_static::lazy_static;
-use rust_system::{QemuExitCode, exit_qemu, serial_print, serial_println};
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use bootloader::{BootInfo, entry_point};
+use rust_system::{QemuExitCode, exit_qemu, interrupts};
+use serial::{serial_print, serial_println};
 
-#[unsafe(no_mangle)]
-fn _start() -> ! {
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
 
-    rust_system::gdt::init();
-    init_test_idt();
-    stack_overflow();
+    rust_system::test_init(boot_info);
+    // The kernel's own page fault handler reports the overflow, so the hook only checks it
+    interrupts::set_stack_overflow_hook(overflow_reported);
+    rust_system::stacks::init();
+    rust_system::stacks::run_on_new_stack("overflowing", overflow_thread);
+}
 
+fn overflow_thread() -> ! {
+    stack_overflow();
     panic!("Execution continued after stack overflow");
 }
 
@@ -23,30 +28,15 @@ fn stack_overflow() {
     volatile::Volatile::new(0).read();
 }
 
-lazy_static! {
-    static ref TEST_IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(test_double_fault_handler)
-                .set_stack_index(rust_system::gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-
-        idt
-    };
-}
-
-pub fn init_test_idt() {
-    TEST_IDT.load();
-}
-
-extern "x86-interrupt" fn test_double_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    serial_println!("[ok]");
-    exit_qemu(QemuExitCode::Success);
-    loop {}
+fn overflow_reported(stack: &'static str) {
+    if stack == "overflowing" {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: overflow reported in {} instead\n", stack);
+        exit_qemu(QemuExitCode::Failed);
+    }
 }
 
 #[panic_handler]
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_system::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use rust_system::fs::VFS;
+use vfs::{Error, OpenFlags};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_system::test_init(boot_info);
+
+    test_main();
+    rust_system::hlt_loop();
+}
+
+#[test_case]
+fn rename_onto_an_ancestor_fails() {
+    VFS.mkdir("/tmp/a", 0o755)
+        .expect("creating the directory failed");
+    let flags = OpenFlags::WRITE_ONLY | OpenFlags::CREATE;
+    VFS.open("/tmp/a/b", flags, 0o644)
+        .expect("creating the file failed");
+
+    assert_eq!(VFS.rename("/tmp/a/b", "/tmp/a"), Err(Error::NotEmpty));
+    // The filesystem refuses too, without locking the directory twice
+    let tmp = VFS.lookup("/tmp").expect("/tmp is missing");
+    let a = VFS.lookup("/tmp/a").expect("/tmp/a is missing");
+    assert_eq!(a.rename("b", tmp.as_ref(), "a"), Err(Error::NotEmpty));
+
+    assert!(VFS.lookup("/tmp/a/b").is_ok());
+    VFS.unlink("/tmp/a/b").expect("removing the file failed");
+    VFS.unlink("/tmp/a").expect("removing the directory failed");
+}
+
+#[test_case]
+fn rename_onto_itself_keeps_the_entry() {
+    let flags = OpenFlags::WRITE_ONLY | OpenFlags::CREATE;
+    VFS.open("/tmp/same", flags, 0o644)
+        .expect("creating the file failed");
+
+    let tmp = VFS.lookup("/tmp").expect("/tmp is missing");
+    tmp.rename("same", tmp.as_ref(), "same")
+        .expect("renaming failed");
+
+    assert!(VFS.lookup("/tmp/same").is_ok());
+    VFS.unlink("/tmp/same").expect("removing the file failed");
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rust_system::test_panic_handler(info)
+}